@@ -0,0 +1,583 @@
+use color_eyre::{eyre::eyre, Result};
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    editor::Mode,
+    editorconfig::{IndentStyle, Properties},
+    filetype::Filetype,
+    toml,
+    util::{Encoding, LineEnding, LogLevel},
+};
+
+/// The contents of the config file.
+#[derive(Debug, Default)]
+pub struct Config {
+    pub settings: Settings,
+    pub cursor: CursorStyles,
+    /// Keys bound in `[keys.normal]` and `[keys.visual]`, on top of the built-in ones.
+    pub keys: Vec<KeyBinding>,
+    /// Commands `:fmt` pipes buffers through, by filetype, from the `[formatters]` section.
+    pub formatters: HashMap<Filetype, String>,
+    /// Filetypes run through their formatter before being written, from the `[format_on_save]` section.
+    pub format_on_save: HashSet<Filetype>,
+    /// Problems found while reading the file, which didn't stop the rest from loading.
+    pub warnings: Vec<String>,
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config.join("red").join("config.toml"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Self::parse(&text)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut config = Self::default();
+        let pairs = match toml::parse(text) {
+            Ok(pairs) => pairs,
+            Err(e) => {
+                config.warnings.push(e.to_string());
+                return config;
+            }
+        };
+        for (key, value) in pairs {
+            let result = match key.split_once('.') {
+                Some(("cursor", mode)) => config.cursor.set(mode, &value),
+                Some(("keys", binding)) => {
+                    KeyBinding::parse(binding, &value).map(|binding| config.keys.push(binding))
+                }
+                Some(("formatters", name)) => match Filetype::parse(name) {
+                    Some(filetype) => {
+                        config.formatters.insert(filetype, value);
+                        Ok(())
+                    }
+                    None => Err(eyre!("unknown filetype: {name}")),
+                },
+                Some(("format_on_save", name)) => match Filetype::parse(name) {
+                    Some(filetype) => parse_bool(&value).map(|on| {
+                        if on {
+                            config.format_on_save.insert(filetype);
+                        } else {
+                            config.format_on_save.remove(&filetype);
+                        }
+                    }),
+                    None => Err(eyre!("unknown filetype: {name}")),
+                },
+                Some(_) => Err(eyre!("unknown option: {key}")),
+                None => config.settings.set(&format!("{key}={value}")),
+            };
+            if let Err(e) = result {
+                config.warnings.push(e.to_string());
+            }
+        }
+        config
+    }
+}
+
+/// A key sequence bound to a named command, written as `keys = "command"` in the
+/// section of its mode, e.g. `"ctrl+w v" = "vsplit"` under `[keys.normal]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyBinding {
+    pub mode: Mode,
+    pub keys: Vec<(KeyModifiers, KeyCode)>,
+    pub command: String,
+}
+
+impl KeyBinding {
+    /// Parses the `mode.keys` part of a config key and the command bound to it.
+    fn parse(binding: &str, command: &str) -> Result<Self> {
+        let (mode, keys) = binding
+            .split_once('.')
+            .ok_or_else(|| eyre!("expected keys.<mode>.<keys>, got keys.{binding}"))?;
+        let mode = match mode {
+            "normal" => Mode::Normal,
+            "visual" => Mode::Visual,
+            _ => return Err(eyre!("keys can't be bound in mode: {mode}")),
+        };
+        let keys = keys
+            .split_whitespace()
+            .map(parse_key)
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            mode,
+            keys,
+            command: command.to_string(),
+        })
+    }
+}
+
+/// Reads one key such as `x`, `ctrl+w`, `alt+enter` or `pagedown`.
+fn parse_key(key: &str) -> Result<(KeyModifiers, KeyCode)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut name = key;
+    while let Some((modifier, rest)) = name.split_once('+').filter(|(_, rest)| !rest.is_empty()) {
+        modifiers |= match modifier {
+            "ctrl" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            _ => return Err(eyre!("unknown modifier in key: {key}")),
+        };
+        name = rest;
+    }
+    let mut chars = name.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(ch), None) => KeyCode::Char(ch),
+        _ => match name {
+            "space" => KeyCode::Char(' '),
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "backspace" => KeyCode::Backspace,
+            "delete" => KeyCode::Delete,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            _ => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => return Err(eyre!("unknown key: {key}")),
+            },
+        },
+    };
+    Ok((modifiers, code))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Underline,
+    Bar,
+}
+
+/// How the cursor looks in one mode, written as a shape optionally followed by `blink`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorStyle {
+    pub shape: CursorShape,
+    pub blink: bool,
+}
+
+impl CursorStyle {
+    pub fn parse(value: &str) -> Result<Self> {
+        let mut words = value.split_whitespace();
+        let shape = match words.next() {
+            Some("block") => CursorShape::Block,
+            Some("underline") => CursorShape::Underline,
+            Some("bar") => CursorShape::Bar,
+            _ => return Err(eyre!("unknown cursor style: {value}")),
+        };
+        let blink = match words.next() {
+            None => false,
+            Some("blink") if words.next().is_none() => true,
+            Some(_) => return Err(eyre!("unknown cursor style: {value}")),
+        };
+        Ok(Self { shape, blink })
+    }
+}
+
+/// Cursor styles per mode, `None` leaves the terminal's own.
+#[derive(Debug, Default, Clone)]
+pub struct CursorStyles {
+    pub normal: Option<CursorStyle>,
+    pub insert: Option<CursorStyle>,
+    pub visual: Option<CursorStyle>,
+    pub command: Option<CursorStyle>,
+}
+
+impl CursorStyles {
+    fn set(&mut self, mode: &str, value: &str) -> Result<()> {
+        let style = Some(CursorStyle::parse(value)?);
+        match mode {
+            "normal" => self.normal = style,
+            "insert" => self.insert = style,
+            "visual" => self.visual = style,
+            "command" => self.command = style,
+            _ => return Err(eyre!("unknown mode: cursor.{mode}")),
+        }
+        Ok(())
+    }
+}
+
+/// What the numbers in the gutter count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineNumberMode {
+    /// 1-based line numbers.
+    Absolute,
+    /// Distance from the cursor line.
+    Relative,
+}
+
+/// Where spaces and tabs are drawn as `·` and `→`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShowWhitespace {
+    Off,
+    All,
+    /// Only inside the Visual mode selection.
+    Selection,
+}
+
+impl ShowWhitespace {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::All => "all",
+            Self::Selection => "selection",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Self::Off, Self::All, Self::Selection]
+            .into_iter()
+            .find(|show| show.name() == name)
+    }
+}
+
+/// Options that change how the editor behaves, adjustable with `:set`.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Show the time spent editing in the status line.
+    pub show_timer: bool,
+    /// Column at which paragraphs are wrapped by `gq`.
+    pub textwidth: usize,
+    /// Highlight the bracket matching the one under the cursor.
+    pub showmatch: bool,
+    /// Wrap lines too long for the window, otherwise every line gets one row
+    /// and is scrolled sideways to follow the cursor.
+    pub wrap: bool,
+    /// Let the view scroll on until the last line is at the top of the window,
+    /// instead of stopping once it reaches the bottom.
+    pub scroll_past_end: bool,
+    /// Widest the text gets in zen mode, centered between blank margins. 0 uses the whole pane.
+    pub zen_width: usize,
+    /// Lines that would wrap into more rows than this are shown truncated
+    /// and scrolled sideways instead, 0 wraps every line.
+    pub maxwrap: usize,
+    /// Let searches continue from the other end of the buffer.
+    pub wrapscan: bool,
+    /// Milliseconds to wait for the next key of a chord before settling on the keys so far.
+    pub timeoutlen: usize,
+    /// Number of changes that can be undone.
+    pub undolevels: usize,
+    /// Bytes of text the undo history may hold, 0 for no limit.
+    pub undosize: usize,
+    /// Show runs of blank lines as a single one, without changing the text.
+    pub squeezeblank: bool,
+    pub show_whitespace: ShowWhitespace,
+    /// Draw the gutter right of the text instead of left of it.
+    pub numbers_on_right: bool,
+    pub number_mode: LineNumberMode,
+    /// Drawn between the gutter and the text, e.g. `│` or a space.
+    pub gutter_separator: String,
+    /// Use relative numbers in Normal mode and absolute ones everywhere else,
+    /// whatever `number_mode` says.
+    pub auto_relative: bool,
+    /// Columns per indentation level and per tab character.
+    pub tab_width: usize,
+    /// Insert spaces instead of a tab character when pressing Tab.
+    pub expand_tab: bool,
+    /// Start new lines with the indentation of the line they were opened from.
+    pub autoindent: bool,
+    /// Insert the closing bracket along with an opening one.
+    pub autopairs: bool,
+    /// Strip spaces and tabs from the end of every line when writing.
+    pub trim_trailing_whitespace: bool,
+    /// Line ending to write files with, `None` keeps whatever the file uses.
+    pub end_of_line: Option<LineEnding>,
+    /// Encoding to write files with, `None` keeps whatever the file uses.
+    pub charset: Option<Encoding>,
+    /// Write back the UTF-8 byte order mark of files that had one.
+    pub keep_bom: bool,
+    /// Reload buffers without unsaved changes when their file changes on disk.
+    pub autoreload: bool,
+    /// Let the cursor sit just past the last char of a line in Normal mode.
+    pub onemore: bool,
+    /// Keep the cursor line and the scroll position when a buffer is reloaded.
+    pub keep_view: bool,
+    /// Also keep the cursor column, as far as the reloaded line is long enough.
+    pub keep_column: bool,
+    /// How much is written to `red.log`.
+    pub log_level: LogLevel,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            show_timer: false,
+            textwidth: 79,
+            showmatch: true,
+            wrap: true,
+            scroll_past_end: false,
+            zen_width: 80,
+            maxwrap: 10,
+            squeezeblank: false,
+            show_whitespace: ShowWhitespace::Off,
+            numbers_on_right: false,
+            number_mode: LineNumberMode::Relative,
+            gutter_separator: String::new(),
+            auto_relative: false,
+            wrapscan: true,
+            timeoutlen: 1000,
+            undolevels: 1000,
+            undosize: 0,
+            tab_width: 4,
+            expand_tab: false,
+            autoindent: false,
+            autopairs: false,
+            trim_trailing_whitespace: false,
+            end_of_line: None,
+            charset: None,
+            keep_bom: true,
+            autoreload: true,
+            keep_view: true,
+            keep_column: true,
+            onemore: true,
+            log_level: LogLevel::Off,
+        }
+    }
+}
+
+impl Settings {
+    /// Applies a `:set` argument: `name`, `noname` or `name=value`.
+    pub fn set(&mut self, arg: &str) -> Result<()> {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg, None),
+        };
+        match (name, value) {
+            ("showtimer", None) => self.show_timer = true,
+            ("noshowtimer", None) => self.show_timer = false,
+            ("showtimer", Some(value)) => self.show_timer = parse_bool(value)?,
+            ("showmatch", None) => self.showmatch = true,
+            ("noshowmatch", None) => self.showmatch = false,
+            ("showmatch", Some(value)) => self.showmatch = parse_bool(value)?,
+            ("wrap", None) => self.wrap = true,
+            ("nowrap", None) => self.wrap = false,
+            ("wrap", Some(value)) => self.wrap = parse_bool(value)?,
+            ("scrollpastend", None) => self.scroll_past_end = true,
+            ("noscrollpastend", None) => self.scroll_past_end = false,
+            ("scrollpastend", Some(value)) => self.scroll_past_end = parse_bool(value)?,
+            ("zenwidth", Some(value)) => self.zen_width = parse_number(value)?,
+            ("maxwrap", Some(value)) => self.maxwrap = parse_number(value)?,
+            ("wrapscan" | "ws", None) => self.wrapscan = true,
+            ("nowrapscan" | "nows", None) => self.wrapscan = false,
+            ("wrapscan" | "ws", Some(value)) => self.wrapscan = parse_bool(value)?,
+            ("timeoutlen" | "tm", Some(value)) => self.timeoutlen = parse_number(value)?,
+            ("undolevels" | "ul", Some(value)) => self.undolevels = parse_number(value)?,
+            ("undosize", Some(value)) => self.undosize = parse_number(value)?,
+            ("keepbom", None) => self.keep_bom = true,
+            ("nokeepbom", None) => self.keep_bom = false,
+            ("keepbom", Some(value)) => self.keep_bom = parse_bool(value)?,
+            ("squeezeblank", None) => self.squeezeblank = true,
+            ("nosqueezeblank", None) => self.squeezeblank = false,
+            ("squeezeblank", Some(value)) => self.squeezeblank = parse_bool(value)?,
+            ("whitespace", Some(value)) => {
+                self.show_whitespace = ShowWhitespace::parse(value)
+                    .ok_or_else(|| eyre!("expected off, all or selection, got {value}"))?
+            }
+            ("numbersonright", None) => self.numbers_on_right = true,
+            ("nonumbersonright", None) => self.numbers_on_right = false,
+            ("numbersonright", Some(value)) => self.numbers_on_right = parse_bool(value)?,
+            ("relativenumber" | "rnu", None) => self.number_mode = LineNumberMode::Relative,
+            ("norelativenumber" | "nornu", None) => self.number_mode = LineNumberMode::Absolute,
+            ("gutterseparator", Some(value)) => self.gutter_separator = value.to_string(),
+            ("autorelative", None) => self.auto_relative = true,
+            ("noautorelative", None) => self.auto_relative = false,
+            ("autorelative", Some(value)) => self.auto_relative = parse_bool(value)?,
+            ("tabstop" | "ts", Some(value)) => self.tab_width = parse_number(value)?,
+            ("expandtab" | "et", None) => self.expand_tab = true,
+            ("noexpandtab" | "noet", None) => self.expand_tab = false,
+            ("expandtab" | "et", Some(value)) => self.expand_tab = parse_bool(value)?,
+            ("autoindent" | "ai", None) => self.autoindent = true,
+            ("noautoindent" | "noai", None) => self.autoindent = false,
+            ("autoindent" | "ai", Some(value)) => self.autoindent = parse_bool(value)?,
+            ("autopairs", None) => self.autopairs = true,
+            ("noautopairs", None) => self.autopairs = false,
+            ("autopairs", Some(value)) => self.autopairs = parse_bool(value)?,
+            ("autoreload" | "ar", None) => self.autoreload = true,
+            ("noautoreload" | "noar", None) => self.autoreload = false,
+            ("autoreload" | "ar", Some(value)) => self.autoreload = parse_bool(value)?,
+            ("onemore", None) => self.onemore = true,
+            ("noonemore", None) => self.onemore = false,
+            ("onemore", Some(value)) => self.onemore = parse_bool(value)?,
+            ("keepview", None) => self.keep_view = true,
+            ("nokeepview", None) => self.keep_view = false,
+            ("keepview", Some(value)) => self.keep_view = parse_bool(value)?,
+            ("keepcolumn", None) => self.keep_column = true,
+            ("nokeepcolumn", None) => self.keep_column = false,
+            ("keepcolumn", Some(value)) => self.keep_column = parse_bool(value)?,
+            ("loglevel", Some(value)) => {
+                self.log_level = LogLevel::parse(value)
+                    .ok_or_else(|| eyre!("expected off, info or debug, got {value}"))?
+            }
+            ("textwidth" | "tw", Some(value)) => self.textwidth = parse_number(value)?,
+            _ => return Err(eyre!("unknown option: {arg}")),
+        }
+        Ok(())
+    }
+
+    /// Overrides options with the ones set by an `.editorconfig`.
+    pub fn apply(&mut self, properties: &Properties) {
+        if let Some(style) = properties.indent_style {
+            self.expand_tab = style == IndentStyle::Space;
+        }
+        if let Some(width) = properties.indent_size.or(properties.tab_width) {
+            self.tab_width = width;
+        }
+        if let Some(trim) = properties.trim_trailing_whitespace {
+            self.trim_trailing_whitespace = trim;
+        }
+        if properties.end_of_line.is_some() {
+            self.end_of_line = properties.end_of_line;
+        }
+        if properties.charset.is_some() {
+            self.charset = properties.charset;
+        }
+    }
+
+    /// One `name=value` line per option, as shown by `:set` without arguments.
+    pub fn describe(&self) -> Vec<String> {
+        vec![
+            format!("showtimer={}", self.show_timer),
+            format!("textwidth={}", self.textwidth),
+            format!("showmatch={}", self.showmatch),
+            format!("wrap={}", self.wrap),
+            format!("scrollpastend={}", self.scroll_past_end),
+            format!("zenwidth={}", self.zen_width),
+            format!("maxwrap={}", self.maxwrap),
+            format!("squeezeblank={}", self.squeezeblank),
+            format!("whitespace={}", self.show_whitespace.name()),
+            format!("numbersonright={}", self.numbers_on_right),
+            format!(
+                "relativenumber={}",
+                self.number_mode == LineNumberMode::Relative
+            ),
+            format!("autorelative={}", self.auto_relative),
+            format!("gutterseparator={}", self.gutter_separator),
+            format!("wrapscan={}", self.wrapscan),
+            format!("timeoutlen={}", self.timeoutlen),
+            format!("undolevels={}", self.undolevels),
+            format!("undosize={}", self.undosize),
+            format!("tabstop={}", self.tab_width),
+            format!("expandtab={}", self.expand_tab),
+            format!("autoindent={}", self.autoindent),
+            format!("autopairs={}", self.autopairs),
+            format!("trim_trailing_whitespace={}", self.trim_trailing_whitespace),
+            format!(
+                "end_of_line={}",
+                self.end_of_line.map_or("auto", LineEnding::name)
+            ),
+            format!("charset={}", self.charset.map_or("auto", Encoding::name)),
+            format!("keepbom={}", self.keep_bom),
+            format!("autoreload={}", self.autoreload),
+            format!("onemore={}", self.onemore),
+            format!("keepview={}", self.keep_view),
+            format!("keepcolumn={}", self.keep_column),
+            format!("loglevel={}", self.log_level.name()),
+        ]
+    }
+}
+
+fn parse_number(value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| eyre!("expected a number, got {value}"))
+}
+
+fn parse_bool(value: &str) -> Result<bool> {
+    match value {
+        "true" | "on" | "yes" => Ok(true),
+        "false" | "off" | "no" => Ok(false),
+        _ => Err(eyre!("expected a boolean, got {value}")),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_config() {
+    let config = Config::parse(
+        "textwidth = 100\nexpandtab = true\n[cursor]\nnormal = \"block\"\ninsert = \"bar blink\"\nvisual = \"beam\"\n",
+    );
+    assert_eq!(config.settings.textwidth, 100);
+    assert!(config.settings.expand_tab);
+    assert_eq!(
+        config.cursor.normal,
+        Some(CursorStyle {
+            shape: CursorShape::Block,
+            blink: false
+        })
+    );
+    assert_eq!(
+        config.cursor.insert,
+        Some(CursorStyle {
+            shape: CursorShape::Bar,
+            blink: true
+        })
+    );
+    assert_eq!(config.cursor.visual, None);
+    assert_eq!(config.warnings, ["unknown cursor style: beam"]);
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_key_bindings() {
+    let config = Config::parse(
+        "[keys.normal]\nh = \"cursor_left\"\n\"ctrl+w alt+f5\" = \":vsplit\"\n[keys.insert]\nx = \"quit\"\n",
+    );
+    assert_eq!(
+        config.keys,
+        [
+            KeyBinding {
+                mode: Mode::Normal,
+                keys: vec![(KeyModifiers::NONE, KeyCode::Char('h'))],
+                command: "cursor_left".into(),
+            },
+            KeyBinding {
+                mode: Mode::Normal,
+                keys: vec![
+                    (KeyModifiers::CONTROL, KeyCode::Char('w')),
+                    (KeyModifiers::ALT, KeyCode::F(5)),
+                ],
+                command: ":vsplit".into(),
+            },
+        ]
+    );
+    assert_eq!(config.warnings, ["keys can't be bound in mode: insert"]);
+    assert_eq!(
+        parse_key("+").unwrap(),
+        (KeyModifiers::NONE, KeyCode::Char('+'))
+    );
+    assert_eq!(
+        parse_key("ctrl++").unwrap(),
+        (KeyModifiers::CONTROL, KeyCode::Char('+'))
+    );
+    assert!(parse_key("hyper+x").is_err());
+}
+
+#[cfg(test)]
+#[test]
+fn test_set_boolean_option() {
+    let mut settings = Settings::default();
+    settings.set("showtimer").unwrap();
+    assert!(settings.show_timer);
+    settings.set("showtimer=off").unwrap();
+    assert!(!settings.show_timer);
+    assert!(settings.set("bogus").is_err());
+}
@@ -0,0 +1,74 @@
+/// One line of a line-level diff between two texts.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DiffLine<'a> {
+    Equal(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+/// Diffs two sequences of lines using their longest common subsequence.
+///
+/// Common leading and trailing lines are stripped first, so the quadratic part
+/// only covers the region that actually changed.
+pub fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(o, n)| o == n).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the LCS length of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff: Vec<_> = old[..prefix].iter().map(|l| DiffLine::Equal(l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() && j < new_mid.len() {
+        if old_mid[i] == new_mid[j] {
+            diff.push(DiffLine::Equal(old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            diff.push(DiffLine::Removed(old_mid[i]));
+            i += 1;
+        } else {
+            diff.push(DiffLine::Added(new_mid[j]));
+            j += 1;
+        }
+    }
+    diff.extend(old_mid[i..].iter().map(|l| DiffLine::Removed(l)));
+    diff.extend(new_mid[j..].iter().map(|l| DiffLine::Added(l)));
+    diff.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Equal(l)));
+    diff
+}
+
+#[cfg(test)]
+#[test]
+fn test_diff_lines() {
+    use DiffLine::*;
+    let old = ["a", "b", "c", "d"];
+    let new = ["a", "c", "x", "d", "e"];
+    assert_eq!(
+        diff_lines(&old, &new),
+        vec![
+            Equal("a"),
+            Removed("b"),
+            Equal("c"),
+            Added("x"),
+            Equal("d"),
+            Added("e")
+        ]
+    );
+}
@@ -2,19 +2,49 @@ use color_eyre::Result;
 use crossterm::{
     cursor::MoveTo,
     event::{poll, read, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
-    style::{Color, Print, SetBackgroundColor},
+    style::{Attribute, Color, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
     terminal::{Clear, ClearType},
     QueueableCommand,
 };
 use std::{
-    collections::HashMap,
-    io::{Stdout, Write},
-    iter::repeat,
+    collections::{HashMap, HashSet},
+    io::Write,
+    iter::repeat_n,
     ops::Range,
-    time::Duration,
+    path::PathBuf,
+    time::{Duration, Instant},
 };
 
-use crate::util::{log, FileBuf, RopeExt};
+use crate::{
+    config::{
+        Config, CursorShape, CursorStyle, CursorStyles, LineNumberMode, Settings, ShowWhitespace,
+    },
+    filetype::Filetype,
+    highlight::highlight_line,
+    history::Edit,
+    session::{state_dir, FilePosition, Session},
+    text::{fit_cells, grapheme_cells},
+    textobject,
+    timer::{format_duration, Stopwatch},
+    util::{
+        char_class, is_bracket, log, matching_bracket, next_word_start, set_log_level,
+        word_start_before, CharClass, FileBuf, LogLevel, RopeExt,
+    },
+};
+
+mod buffers;
+mod command;
+mod edit;
+mod explore;
+mod keymap;
+mod palette;
+mod panes;
+mod registers;
+mod render;
+mod search;
+mod visual;
+
+use panes::Rect;
 
 type Cmd = dyn for<'e> Fn(&'e mut Editor) -> Result<Mode>;
 struct RedCmd(Box<Cmd>);
@@ -24,25 +54,55 @@ impl RedCmd {
     }
 }
 
-type Bindings = HashMap<(Mode, KeyModifiers, KeyCode), RedCmd>;
+/// A command on the panes, bound to a key pressed after `Ctrl+W`.
+type PaneCommand = fn(&mut Editor);
+
+type Key = (KeyModifiers, KeyCode);
+
+/// A sequence of keys pressed in a given mode, bound to a single command.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+struct Chord(Mode, Vec<Key>);
+
+impl From<(Mode, KeyModifiers, KeyCode)> for Chord {
+    fn from((mode, modifiers, code): (Mode, KeyModifiers, KeyCode)) -> Self {
+        Self(mode, vec![(modifiers, code)])
+    }
+}
+
+impl<const N: usize> From<(Mode, [Key; N])> for Chord {
+    fn from((mode, keys): (Mode, [Key; N])) -> Self {
+        Self(mode, keys.to_vec())
+    }
+}
+
+impl Chord {
+    fn is_prefix_of(&self, other: &Chord) -> bool {
+        self.0 == other.0 && self.1.len() < other.1.len() && other.1.starts_with(&self.1)
+    }
+}
+
+type Bindings = HashMap<Chord, RedCmd>;
 
 macro_rules! bindings {
     ($($k:expr => $v:expr),* $(,)?) => {{
-        core::convert::From::from([$(($k, RedCmd(Box::new($v))),)*])
+        core::convert::From::from([$((Chord::from($k), RedCmd(Box::new($v))),)*])
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 struct Cursor {
     x: u16,
     y: u16,
 }
 
+#[derive(PartialEq)]
 pub struct VirtualLine {
     start: usize,
     end: usize,
     parent_line: usize,
     subline: bool,
+    /// Covers a whole line too long to wrap, of which only a part is shown.
+    truncated: bool,
 }
 
 impl std::fmt::Debug for VirtualLine {
@@ -65,6 +125,7 @@ impl VirtualLine {
             end: e,
             parent_line: p,
             subline: u,
+            truncated: false,
         }
     }
     pub fn len(&self) -> usize {
@@ -80,6 +141,16 @@ pub struct Editor {
     mode: Mode,
     redraw: bool,
     bindings: Bindings,
+    buffers: Vec<buffers::Buffer>,
+    active: usize,
+    /// File backing the quick-note buffer toggled with `Ctrl+N`.
+    scratch_path: Option<PathBuf>,
+    /// Config file read again by `:source`.
+    config_path: Option<PathBuf>,
+    /// Buffer to go back to when leaving the quick-note buffer.
+    before_scratch: Option<usize>,
+    /// The buffer that was active before the current one, `#` in `:ls`.
+    alternate: Option<usize>,
     buf: FileBuf,
     scr_cursor: Cursor,
     buf_cursor: usize,
@@ -88,19 +159,121 @@ pub struct Editor {
     cur_line: usize,
     cur_vline: usize,
     virtual_lines: Vec<VirtualLine>,
+    pending: Vec<Key>,
+    /// When the last key of `pending` was pressed, for `timeoutlen` to count from.
+    pending_since: Option<Instant>,
+    /// Number typed before a command in Normal mode.
+    count: Option<usize>,
+    /// Count given to the search being typed.
+    search_count: usize,
+    /// Where the cursor was when the search being typed was started,
+    /// matches are looked for from there and it's returned to if the search is abandoned.
+    search_origin: usize,
+    last_search: Option<String>,
+    quickfix: search::Quickfix,
+    /// The last line run from the `:` prompt, repeated by `@:`.
+    last_command: Option<String>,
+    /// What the last `@` ran, repeated by `@@`: `:` for the last command line.
+    last_at: Option<char>,
+    command_buf: String,
+    prompt: Prompt,
+    /// `Ctrl+R` was pressed in Insert mode and the register name comes next.
+    awaiting_register: bool,
+    /// `q` was pressed with unsaved changes, pressing it again right away quits anyway.
+    quit_requested: bool,
+    overlay: Option<Vec<String>>,
+    highlighted_bracket: Option<(usize, Color)>,
+    /// Distraction-free mode: no gutter or status line, just the text, centered.
+    zen: bool,
+    render_cache: render::RenderCache,
+    panes: panes::Panes,
+    /// Screen area of the focused pane.
+    pane: Rect,
+    registers: HashMap<char, registers::Register>,
+    /// Where the selection started in Visual mode, the cursor is its other end.
+    anchor: usize,
+    /// Selections widened by `expand_selection`, innermost last.
+    expansions: Vec<Range<usize>>,
+    block_insert: Option<visual::BlockInsert>,
+    /// Options from the config file, before the buffer's `.editorconfig` is applied.
+    defaults: Settings,
+    /// `:set` arguments given so far, applied over `.editorconfig` for every buffer.
+    overrides: Vec<String>,
+    settings: Settings,
+    cursor_styles: CursorStyles,
+    formatters: HashMap<Filetype, String>,
+    format_on_save: HashSet<Filetype>,
+    editing_time: Stopwatch,
     dbg: String,
 }
 
 pub struct Window {
     pub height: u16,
     pub width: u16,
-    pub stdout: Stdout,
+    pub stdout: Box<dyn Write>,
+}
+
+/// What the line typed in Command mode is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Prompt {
+    Command,
+    /// An expression to insert the value of, started with `Ctrl+R =` in Insert mode.
+    Expression,
+    Search,
+    /// The command palette, opened with `Ctrl+P`.
+    Palette,
+}
+
+impl Prompt {
+    fn symbol(self) -> char {
+        match self {
+            Self::Command => ':',
+            Self::Expression => '=',
+            Self::Search => '/',
+            Self::Palette => '>',
+        }
+    }
+
+    /// The mode to go back to once the line is entered or abandoned.
+    fn origin(self) -> Mode {
+        match self {
+            Self::Command | Self::Search | Self::Palette => Mode::Normal,
+            Self::Expression => Mode::Insert,
+        }
+    }
+}
+
+/// Sets the cursor style, `None` restores the terminal's default.
+pub struct SetCursorStyle(pub Option<CursorStyle>);
+
+impl crossterm::Command for SetCursorStyle {
+    fn write_ansi(&self, f: &mut impl std::fmt::Write) -> std::fmt::Result {
+        let code = match self.0 {
+            None => 0,
+            Some(CursorStyle { shape, blink }) => {
+                let steady = match shape {
+                    CursorShape::Block => 2,
+                    CursorShape::Underline => 4,
+                    CursorShape::Bar => 6,
+                };
+                steady - u8::from(blink)
+            }
+        };
+        write!(f, "\x1b[{code} q")
+    }
+
+    #[cfg(windows)]
+    fn execute_winapi(&self) -> crossterm::Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Mode {
     Normal,
     Insert,
+    Command,
+    Visual,
     Quit,
 }
 
@@ -109,6 +282,8 @@ impl std::fmt::Display for Mode {
         match self {
             Self::Normal => write!(f, "NORMAL"),
             Self::Insert => write!(f, "INSERT"),
+            Self::Command => write!(f, "COMMAND"),
+            Self::Visual => write!(f, "VISUAL"),
             Self::Quit => write!(f, "QUITTING"),
         }
     }
@@ -116,46 +291,459 @@ impl std::fmt::Display for Mode {
 
 impl Editor {
     const LINE_NUMBER_WIDTH: usize = 3;
+    /// How far `showmatch` looks for a matching bracket, as it does so on every tick.
+    const SHOWMATCH_LIMIT: usize = 50_000;
     pub fn new(window: Window, buf: FileBuf) -> Self {
-        let bindings = bindings! {
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('i')) =>
-            |_| Ok(Mode::Insert),
+        let mut bindings: Bindings = bindings! {
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('A')) =>
+            |e| {
+                e.goto_logical_line_end();
+                Ok(Mode::Insert)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('I')) =>
+            |e| {
+                e.goto_first_non_blank();
+                Ok(Mode::Insert)
+            },
+
+            (Mode::Normal, KeyModifiers::CONTROL, KeyCode::Char('n')) =>
+            |e| {
+                e.toggle_scratch()?;
+                Ok(Mode::Normal)
+            },
+
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('n')) =>
+            |e| {
+                let count = e.take_count();
+                e.search_next(count, true);
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('*')) =>
+            |e| {
+                let count = e.take_count();
+                e.search_word(count);
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('N')) =>
+            |e| {
+                let count = e.take_count();
+                e.search_next(count, false);
+                Ok(Mode::Normal)
+            },
+
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Enter) =>
+            |e| {
+                e.open_entry()?;
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('-')) =>
+            |e| {
+                e.explore_parent()?;
+                Ok(Mode::Normal)
+            },
+
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('y')),
+                    (KeyModifiers::NONE, KeyCode::Char('y')),
+                ],
+            ) =>
+            |e| {
+                let count = e.take_count();
+                e.yank_lines(count);
+                Ok(Mode::Normal)
+            },
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('x')),
+                    (KeyModifiers::NONE, KeyCode::Char('x')),
+                ],
+            ) =>
+            |e| {
+                let count = e.take_count();
+                e.delete_lines(count);
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('o')) =>
+            |e| {
+                e.open_line_below();
+                Ok(Mode::Insert)
+            },
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('P')) =>
+            |e| {
+                e.paste(true);
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::CONTROL, KeyCode::Char('a')) =>
+            |e| {
+                let count = i64::try_from(e.take_count()).unwrap_or(i64::MAX);
+                e.increment_number(count);
+                Ok(Mode::Normal)
+            },
+            (Mode::Normal, KeyModifiers::CONTROL, KeyCode::Char('x')) =>
+            |e| {
+                let count = i64::try_from(e.take_count()).unwrap_or(i64::MAX);
+                e.increment_number(-count);
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::CONTROL, KeyCode::Char('a')) =>
+            |e| {
+                let count = i64::try_from(e.take_count()).unwrap_or(i64::MAX);
+                e.increment_selection(count, false);
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::CONTROL, KeyCode::Char('x')) =>
+            |e| {
+                let count = i64::try_from(e.take_count()).unwrap_or(i64::MAX);
+                e.increment_selection(-count, false);
+                Ok(Mode::Normal)
+            },
+            (
+                Mode::Visual,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('g')),
+                    (KeyModifiers::CONTROL, KeyCode::Char('a')),
+                ],
+            ) =>
+            |e| {
+                let count = i64::try_from(e.take_count()).unwrap_or(i64::MAX);
+                e.increment_selection(count, true);
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('o')) =>
+            |e| {
+                e.swap_selection_ends();
+                Ok(Mode::Visual)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('y')) =>
+            |e| {
+                e.yank_selection();
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('x')) =>
+            |e| {
+                e.delete_selection();
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('D')) =>
+            |e| {
+                e.duplicate_selection();
+                Ok(Mode::Normal)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('I')) =>
+            |e| Ok(e.start_block_insert(false)),
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('A')) =>
+            |e| Ok(e.start_block_insert(true)),
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('+')) =>
+            |e| {
+                e.expand_selection();
+                Ok(Mode::Visual)
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char('-')) =>
+            |e| {
+                e.shrink_selection();
+                Ok(Mode::Visual)
+            },
 
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('d')) =>
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char(']')),
+                    (KeyModifiers::NONE, KeyCode::Char('d')),
+                ],
+            ) =>
             |e| {
-                e.cursor_right();
+                let count = e.take_count();
+                e.jump_to_sign(count, true);
                 Ok(Mode::Normal)
             },
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('a')) =>
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('[')),
+                    (KeyModifiers::NONE, KeyCode::Char('d')),
+                ],
+            ) =>
             |e| {
-                e.cursor_left();
+                let count = e.take_count();
+                e.jump_to_sign(count, false);
                 Ok(Mode::Normal)
             },
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('w')) =>
+
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char(']')),
+                    (KeyModifiers::NONE, KeyCode::Char('q')),
+                ],
+            ) =>
             |e| {
-                e.cursor_up();
+                let count = e.take_count();
+                e.jump_to_quickfix(count, true);
                 Ok(Mode::Normal)
             },
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('s')) =>
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('[')),
+                    (KeyModifiers::NONE, KeyCode::Char('q')),
+                ],
+            ) =>
             |e| {
-                e.cursor_down();
+                let count = e.take_count();
+                e.jump_to_quickfix(count, false);
                 Ok(Mode::Normal)
             },
 
             (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('r')) =>
             |e| {
+                e.render_cache.invalidate();
                 e.redraw = true;
                 Ok(Mode::Normal)
             },
 
-            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('q')) =>
-            |_| Ok(Mode::Quit)
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('z')),
+                    (KeyModifiers::NONE, KeyCode::Char('w')),
+                ],
+            ) =>
+            |e| {
+                let toggled = if e.settings.wrap { "nowrap" } else { "wrap" };
+                e.set_options(toggled)?;
+                e.dbg = toggled.into();
+                Ok(Mode::Normal)
+            },
+
+            (Mode::Normal, KeyModifiers::CONTROL, KeyCode::Char('p')) =>
+            |e| Ok(e.open_palette()),
+
+            (Mode::Normal, KeyModifiers::CONTROL, KeyCode::Char('l')) =>
+            |e| {
+                e.repaint_screen()?;
+                Ok(Mode::Normal)
+            },
+
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('g')),
+                    (KeyModifiers::NONE, KeyCode::Char('q')),
+                ],
+            ) =>
+            |e| {
+                e.reflow_paragraph();
+                Ok(Mode::Normal)
+            },
+
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('@')),
+                    (KeyModifiers::NONE, KeyCode::Char(':')),
+                ],
+            ) =>
+            |e| {
+                e.last_at = Some(':');
+                e.repeat_command()
+            },
+            (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('@')),
+                    (KeyModifiers::NONE, KeyCode::Char('@')),
+                ],
+            ) =>
+            |e| match e.last_at {
+                Some(':') => e.repeat_command(),
+                _ => {
+                    e.dbg = "nothing run with @ yet".into();
+                    Ok(Mode::Normal)
+                }
+            },
+            (Mode::Visual, KeyModifiers::NONE, KeyCode::Char(':')) =>
+            |e| {
+                e.mark_selection();
+                e.command_buf = "'<,'>".into();
+                e.prompt = Prompt::Command;
+                e.redraw = true;
+                Ok(Mode::Command)
+            },
         };
+        let named = [
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Char('i'),
+                "insert_mode",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Char('/'),
+                "search",
+            ),
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('u'), "undo"),
+            (
+                Mode::Normal,
+                KeyModifiers::CONTROL,
+                KeyCode::Char('r'),
+                "redo",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Char('v'),
+                "visual_mode",
+            ),
+            (
+                Mode::Visual,
+                KeyModifiers::NONE,
+                KeyCode::Char('v'),
+                "normal_mode",
+            ),
+            (
+                Mode::Visual,
+                KeyModifiers::NONE,
+                KeyCode::Esc,
+                "normal_mode",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Char('p'),
+                "paste",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Char(':'),
+                "command_mode",
+            ),
+            (Mode::Normal, KeyModifiers::NONE, KeyCode::Char('q'), "quit"),
+            (
+                Mode::Normal,
+                KeyModifiers::CONTROL,
+                KeyCode::Char('s'),
+                "write",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::Tab,
+                "next_buffer",
+            ),
+            (
+                Mode::Normal,
+                KeyModifiers::NONE,
+                KeyCode::BackTab,
+                "prev_buffer",
+            ),
+        ];
+        let motions = [
+            (KeyCode::Char('d'), "cursor_right"),
+            (KeyCode::Char('a'), "cursor_left"),
+            (KeyCode::Char('w'), "cursor_up"),
+            (KeyCode::Char('s'), "cursor_down"),
+            (KeyCode::Home, "line_start"),
+            (KeyCode::End, "line_end"),
+            (KeyCode::PageUp, "page_up"),
+            (KeyCode::PageDown, "page_down"),
+            (KeyCode::Char('e'), "next_word"),
+            (KeyCode::Char('b'), "prev_word"),
+        ];
+        let motions = motions.into_iter().flat_map(|(key, name)| {
+            [Mode::Normal, Mode::Visual].map(|mode| (mode, KeyModifiers::NONE, key, name))
+        });
+        for (mode, modifiers, key, name) in named.into_iter().chain(motions) {
+            let command = keymap::named_command(name, mode).expect("built-in commands are named");
+            bindings.insert((mode, modifiers, key).into(), command);
+        }
+        for mark in 'a'..='z' {
+            let chord = (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('m')),
+                    (KeyModifiers::NONE, KeyCode::Char(mark)),
+                ],
+            );
+            let set_mark = move |e: &mut Editor| {
+                e.buf.marks.insert(mark, e.buf_cursor);
+                Ok(Mode::Normal)
+            };
+            bindings.insert(chord.into(), RedCmd(Box::new(set_mark)));
+        }
+        for mark in ('a'..='z').chain(['.', '"']) {
+            let chord = (
+                Mode::Normal,
+                [
+                    (KeyModifiers::NONE, KeyCode::Char('`')),
+                    (KeyModifiers::NONE, KeyCode::Char(mark)),
+                ],
+            );
+            let jump = move |e: &mut Editor| {
+                e.jump_to_mark(mark);
+                Ok(Mode::Normal)
+            };
+            bindings.insert(chord.into(), RedCmd(Box::new(jump)));
+        }
+        let pane_commands: [(KeyModifiers, char, PaneCommand); 9] = [
+            (KeyModifiers::NONE, 's', |e| e.split_pane(false)),
+            (KeyModifiers::NONE, 'v', |e| e.split_pane(true)),
+            (KeyModifiers::NONE, 'h', |e| {
+                e.focus_direction(panes::Direction::Left)
+            }),
+            (KeyModifiers::NONE, 'j', |e| {
+                e.focus_direction(panes::Direction::Down)
+            }),
+            (KeyModifiers::NONE, 'k', |e| {
+                e.focus_direction(panes::Direction::Up)
+            }),
+            (KeyModifiers::NONE, 'l', |e| {
+                e.focus_direction(panes::Direction::Right)
+            }),
+            (KeyModifiers::NONE, 'c', |e| {
+                if let Err(err) = e.close_pane() {
+                    e.dbg = err.to_string();
+                }
+            }),
+            (KeyModifiers::CONTROL, 'e', |e| e.scroll_other_pane(1)),
+            (KeyModifiers::CONTROL, 'y', |e| e.scroll_other_pane(-1)),
+        ];
+        for (modifiers, key, command) in pane_commands {
+            let chord = (
+                Mode::Normal,
+                [
+                    (KeyModifiers::CONTROL, KeyCode::Char('w')),
+                    (modifiers, KeyCode::Char(key)),
+                ],
+            );
+            let command = move |e: &mut Editor| {
+                command(e);
+                Ok(Mode::Normal)
+            };
+            bindings.insert(chord.into(), RedCmd(Box::new(command)));
+        }
 
+        let pane = Rect {
+            x: 0,
+            y: 0,
+            width: window.width,
+            height: window.height,
+        };
         let mut editor = Self {
             window,
             mode: Mode::Normal,
             bindings,
+            buffers: vec![Default::default()],
+            active: 0,
+            scratch_path: state_dir().map(|dir| dir.join("scratch.txt")),
+            config_path: Config::default_path(),
+            before_scratch: None,
+            alternate: None,
             buf,
             scr_cursor: Cursor { x: 0, y: 0 },
             buf_cursor: 0,
@@ -165,105 +753,604 @@ impl Editor {
             cur_line: 0,
             cur_vline: 0,
             virtual_lines: Vec::new(),
+            pending: Vec::new(),
+            pending_since: None,
+            count: None,
+            search_count: 1,
+            search_origin: 0,
+            last_search: None,
+            quickfix: Default::default(),
+            last_command: None,
+            last_at: None,
+            command_buf: String::new(),
+            prompt: Prompt::Command,
+            awaiting_register: false,
+            quit_requested: false,
+            overlay: None,
+            highlighted_bracket: None,
+            zen: false,
+            render_cache: Default::default(),
+            panes: panes::Panes::new(pane),
+            pane,
+            registers: HashMap::new(),
+            anchor: 0,
+            expansions: Vec::new(),
+            block_insert: None,
+            defaults: Settings::default(),
+            overrides: Vec::new(),
+            settings: Settings::default(),
+            cursor_styles: CursorStyles::default(),
+            formatters: HashMap::new(),
+            format_on_save: HashSet::new(),
+            editing_time: Stopwatch::start(Instant::now()),
             dbg: String::new(),
         };
+        editor.apply_buffer_settings();
         editor.compute_virtual_lines();
         editor
     }
 
-    fn cursor_right(&mut self) {
-        let y = self.scr_cursor.y + 1;
-        let cur_vline_start = self.virtual_lines[self.cur_vline].start;
-        let cur_vline_len = self.virtual_lines[self.cur_vline].len();
-        if y <= self.window.width && y <= cur_vline_len as u16 {
-            self.scr_cursor.y = y;
-            self.buf_cursor = cur_vline_start + y as usize;
-            self.desired_position = y;
-        } else if let Some(next_vline) = self.virtual_lines.get(self.cur_vline + 1) {
-            if next_vline.subline {
-                self.scr_cursor.y = 0;
-                self.cursor_down();
-                self.desired_position = y;
+    /// The count typed before the current command, 1 if there was none.
+    fn take_count(&mut self) -> usize {
+        self.count.take().unwrap_or(1)
+    }
+
+    /// Takes the options and cursor styles from the config file.
+    pub fn configure(&mut self, config: Config) -> Result<()> {
+        self.defaults = config.settings;
+        self.cursor_styles = config.cursor;
+        self.formatters = config.formatters;
+        self.format_on_save = config.format_on_save;
+        self.bind_keys(&config.keys)?;
+        set_log_level(self.defaults.log_level);
+        self.apply_buffer_settings();
+        self.compute_virtual_lines();
+        self.panes.invalidate(None);
+        self.clamp_state_to_buffer();
+        if !config.warnings.is_empty() {
+            self.dbg = format!("config: {}", config.warnings.join(", "));
+        }
+        self.apply_cursor_style()
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<()> {
+        if mode != self.mode {
+            self.mode = mode;
+            self.apply_cursor_style()?;
+            // leaving Insert mode can leave the cursor past where Normal mode allows it
+            let max = self.virtual_lines[self.cur_vline].start + self.max_column(self.cur_vline);
+            if self.buf_cursor > max {
+                self.place_cursor(max);
             }
         }
-        log((
-            self.buf_cursor,
-            &self.virtual_lines[self.cur_vline],
-            self.scr_cursor.y,
-        ));
+        Ok(())
+    }
+
+    fn apply_cursor_style(&mut self) -> Result<()> {
+        let style = match self.mode {
+            Mode::Normal => self.cursor_styles.normal,
+            Mode::Insert => self.cursor_styles.insert,
+            Mode::Visual => self.cursor_styles.visual,
+            Mode::Command => self.cursor_styles.command,
+            Mode::Quit => return Ok(()),
+        };
+        self.window.stdout.queue(SetCursorStyle(style))?;
+        Ok(())
+    }
+
+    /// The numbering shown in the gutter, which can depend on the mode.
+    fn line_number_mode(&self) -> LineNumberMode {
+        match self.settings.auto_relative {
+            true if self.mode == Mode::Normal => LineNumberMode::Relative,
+            true => LineNumberMode::Absolute,
+            false => self.settings.number_mode,
+        }
+    }
+
+    /// Recomputes the effective options for the active buffer.
+    fn apply_buffer_settings(&mut self) {
+        self.settings = self.defaults.clone();
+        self.settings.apply(&self.buf.editorconfig);
+        for arg in &self.overrides {
+            // each one was accepted when it was first set
+            let _ = self.settings.set(arg);
+        }
+    }
+
+    fn cursor_right(&mut self) {
+        let vline = &self.virtual_lines[self.cur_vline];
+        let continued = self
+            .virtual_lines
+            .get(self.cur_vline + 1)
+            .is_some_and(|next| next.subline);
+        if continued || self.buf_cursor < vline.start + self.max_column(self.cur_vline) {
+            self.move_cursor_to(self.next_cluster(self.buf_cursor));
+        }
+        log(
+            LogLevel::Debug,
+            (
+                self.buf_cursor,
+                &self.virtual_lines[self.cur_vline],
+                self.scr_cursor.y,
+            ),
+        );
     }
 
     fn cursor_left(&mut self) {
-        if self.scr_cursor.y > 0 {
-            self.scr_cursor.y -= 1;
-            self.buf_cursor -= 1;
-            self.desired_position = self.scr_cursor.y;
-        } else if self.virtual_lines[self.cur_vline].subline {
-            let len = self.virtual_lines[self.cur_line].len().saturating_sub(1);
-            self.scr_cursor.y = len as u16;
-            self.scr_cursor.x = self.scr_cursor.x.saturating_sub(1);
-            self.buf_cursor -= 1;
-            self.desired_position = self.desired_position.saturating_sub(1);
+        let vline = &self.virtual_lines[self.cur_vline];
+        if self.buf_cursor > vline.start || vline.subline {
+            self.move_cursor_to(self.cluster_start(self.buf_cursor - 1));
         }
     }
 
     fn cursor_down(&mut self) {
-        let x = self.scr_cursor.x + 1;
-        if x > self.window.height - 1 {
-            if self.top_line + 1 < (self.virtual_lines.len() - self.window.height as usize + 1) {
-                self.top_line += 1;
-                if self.cur_vline + 1 < self.virtual_lines.len() {
-                    self.cur_vline += 1;
-                    if !self.virtual_lines[self.cur_vline].subline {
-                        self.cur_line += 1;
-                    }
-                }
-                self.redraw = true;
-            }
-            self.cap_cursor();
-            let diff = self
-                .buf_cursor
-                .abs_diff(self.virtual_lines[self.cur_vline].start);
-            self.buf_cursor += diff;
+        if self.cur_vline + 1 < self.virtual_lines.len() {
+            self.move_to_vline(self.cur_vline + 1);
+        }
+    }
+
+    fn cursor_up(&mut self) {
+        if let Some(vline) = self.cur_vline.checked_sub(1) {
+            self.move_to_vline(vline);
+        }
+    }
+
+    /// Moves to the start of the virtual line, so on a wrapped line to where its row starts.
+    fn line_start(&mut self) {
+        self.move_cursor_to(self.virtual_lines[self.cur_vline].start);
+    }
+
+    fn next_word(&mut self) {
+        let at = next_word_start(&self.buf.rope, self.buf_cursor);
+        self.move_cursor_to(at);
+    }
+
+    fn prev_word(&mut self) {
+        let at = word_start_before(&self.buf.rope, self.buf_cursor);
+        self.move_cursor_to(at);
+    }
+
+    /// Moves to the end of the virtual line, the last char of a row that wraps,
+    /// and keeps to line ends when moving up and down.
+    fn line_end(&mut self) {
+        let vline = &self.virtual_lines[self.cur_vline];
+        let wraps = self
+            .virtual_lines
+            .get(self.cur_vline + 1)
+            .is_some_and(|next| next.subline);
+        let end = match wraps {
+            true => self.cluster_start(vline.end - 1),
+            false => vline.start + self.max_column(self.cur_vline),
+        };
+        self.move_cursor_to(end);
+        self.set_desired_column(u16::MAX);
+    }
+
+    /// The furthest the view scrolls down: until the last line is at the bottom of the pane,
+    /// or at its top with `scrollpastend`.
+    fn max_top_line(&self) -> usize {
+        let last = self.virtual_lines.len() - 1;
+        match self.settings.scroll_past_end {
+            true => last,
+            false => last.saturating_sub(self.pane.height.max(1) as usize - 1),
+        }
+    }
+
+    /// Scrolls down a screen, keeping the old bottom row in view, and moves the cursor as far.
+    fn page_down(&mut self) {
+        let page = (self.pane.height as usize).saturating_sub(1).max(1);
+        let last = self.virtual_lines.len() - 1;
+        self.top_line = (self.top_line + page).min(self.max_top_line().max(self.top_line));
+        self.move_to_vline((self.cur_vline + page).min(last));
+        self.redraw = true;
+    }
+
+    fn page_up(&mut self) {
+        let page = (self.pane.height as usize).saturating_sub(1).max(1);
+        self.top_line = self.top_line.saturating_sub(page);
+        self.move_to_vline(self.cur_vline.saturating_sub(page));
+        self.redraw = true;
+    }
+
+    /// Scrolls the view by `lines`, down for positive ones, moving the cursor
+    /// only as far as it takes to stay on screen.
+    fn scroll_lines(&mut self, lines: isize) {
+        let height = self.pane.height.max(1) as usize;
+        self.top_line = self
+            .top_line
+            .saturating_add_signed(lines)
+            .min(self.max_top_line().max(self.top_line));
+        let vline = self
+            .cur_vline
+            .clamp(self.top_line, self.top_line + height - 1)
+            .min(self.virtual_lines.len() - 1);
+        self.move_to_vline(vline);
+        self.redraw = true;
+    }
+
+    /// The last char offset the cursor can take on `vline`: its line break,
+    /// or just past its last char at the end of the buffer.
+    /// Without `onemore` it stops on the last grapheme instead, except in Insert mode.
+    fn max_column(&self, vline: usize) -> usize {
+        let past_end = self.past_end_column(vline);
+        let line_ends = self
+            .virtual_lines
+            .get(vline + 1)
+            .is_none_or(|next| !next.subline);
+        if !self.settings.onemore && self.mode != Mode::Insert && line_ends {
+            self.vline_cells(vline)
+                .into_iter()
+                .map(|(offset, _)| offset)
+                .take_while(|&offset| offset < past_end)
+                .last()
+                .unwrap_or(0)
         } else {
-            self.scr_cursor.x = x;
-            if self.cur_vline + 1 < self.virtual_lines.len() {
-                self.cur_vline += 1;
-                if !self.virtual_lines[self.cur_vline].subline {
-                    self.cur_line += 1;
-                }
+            past_end
+        }
+    }
+
+    /// Where each grapheme cluster of `vline` starts, as (char offset, cell column) pairs,
+    /// followed by the length and width of the whole line.
+    fn vline_cells(&self, vline: usize) -> Vec<(usize, usize)> {
+        let line = &self.virtual_lines[vline];
+        grapheme_cells(
+            self.buf.rope.slice(line.range()).chars(),
+            self.settings.tab_width,
+        )
+        .collect()
+    }
+
+    /// The cell column char `offset` of `vline` is drawn at.
+    fn cell_column(&self, vline: usize, offset: usize) -> usize {
+        let start = self.virtual_lines[vline].start;
+        let prefix = self.buf.rope.slice(start..start + offset);
+        grapheme_cells(prefix.chars(), self.settings.tab_width)
+            .last()
+            .map_or(0, |(_, column)| column)
+    }
+
+    /// Start of the grapheme cluster char `idx` is part of.
+    fn cluster_start(&self, idx: usize) -> usize {
+        let vline = self.vline_of(idx);
+        let start = self.virtual_lines[vline].start;
+        let cells = self.vline_cells(vline).into_iter();
+        start
+            + cells
+                .map(|(offset, _)| offset)
+                .take_while(|&offset| start + offset <= idx)
+                .last()
+                .unwrap_or(0)
+    }
+
+    /// Start of the grapheme cluster after the one char `idx` is part of.
+    fn next_cluster(&self, idx: usize) -> usize {
+        let vline = self.vline_of(idx);
+        let start = self.virtual_lines[vline].start;
+        let mut cells = self.vline_cells(vline).into_iter();
+        cells
+            .find(|&(offset, _)| start + offset > idx)
+            .map_or(idx + 1, |(offset, _)| start + offset)
+    }
+
+    fn vline_of(&self, idx: usize) -> usize {
+        self.virtual_lines
+            .partition_point(|l| l.start <= idx)
+            .saturating_sub(1)
+    }
+
+    fn past_end_column(&self, vline: usize) -> usize {
+        let line = &self.virtual_lines[vline];
+        match self.buf.rope.get_char(line.end.saturating_sub(1)) {
+            _ if line.len() == 0 => 0,
+            Some('\n') if line.len() > 1 && self.buf.rope.char(line.end - 2) == '\r' => {
+                line.len() - 2
             }
-            self.cap_cursor();
+            _ if line.end < self.buf.rope.len_chars() => line.len() - 1,
+            Some('\n') => line.len() - 1,
+            _ => line.len(),
+        }
+    }
+
+    /// Remembers the column vertical motions try to return to.
+    /// Horizontal motions set it, vertical ones only read it through `cap_cursor`.
+    fn set_desired_column(&mut self, col: u16) {
+        self.desired_position = col;
+    }
+
+    /// The char offset on `vline` of the grapheme closest to the desired column.
+    fn cap_cursor(&self, vline: usize) -> usize {
+        let max = self.max_column(vline);
+        self.vline_cells(vline)
+            .into_iter()
+            .take_while(|&(offset, column)| {
+                column <= self.desired_position as usize && offset <= max
+            })
+            .last()
+            .map_or(0, |(offset, _)| offset)
+    }
+
+    /// Moves to `vline`, keeping the desired column.
+    fn move_to_vline(&mut self, vline: usize) {
+        let col = self.cap_cursor(vline);
+        self.place_cursor(self.virtual_lines[vline].start + col);
+    }
+
+    /// Places the cursor on the char at `idx`, scrolling it into view if needed.
+    fn move_cursor_to(&mut self, idx: usize) {
+        self.place_cursor(idx);
+        self.set_desired_column(self.scr_cursor.y);
+    }
+
+    /// Like `move_cursor_to`, but keeps the desired column.
+    fn place_cursor(&mut self, idx: usize) {
+        let idx = idx.min(self.buf.rope.len_chars());
+        self.cur_vline = self.vline_of(idx);
+        let vline = &self.virtual_lines[self.cur_vline];
+        let col = (idx - vline.start).min(vline.len());
+        self.cur_line = vline.parent_line;
+        self.buf_cursor = vline.start + col;
+        self.scr_cursor.y = self.cell_column(self.cur_vline, col) as u16;
+        self.scroll_to_cursor();
+    }
 
-            let buf_cursor = self.virtual_lines[self.cur_vline].start + self.scr_cursor.y as usize;
-            self.buf_cursor = buf_cursor;
+    fn scroll_to_cursor(&mut self) {
+        // a pane squeezed to nothing still shows the cursor line
+        let height = self.pane.height.max(1) as usize;
+        if self.cur_vline < self.top_line {
+            self.top_line = self.cur_vline;
+            self.redraw = true;
+        } else if self.cur_vline >= self.top_line + height {
+            self.top_line = self.cur_vline + 1 - height;
+            self.redraw = true;
         }
+        self.scr_cursor.x = (self.cur_vline - self.top_line) as u16;
     }
 
-    fn cursor_up(&mut self) {
-        if let Some(new_vline) = self.cur_vline.checked_sub(1) {
-            self.cur_vline = new_vline;
-            if !self.virtual_lines[self.cur_vline].subline {
-                self.cur_line = self.cur_line.saturating_sub(1);
-            }
+    /// Moves the cursor to the start of a 1-based line, clamped to the buffer.
+    fn goto_line(&mut self, line: usize) {
+        let line = line.clamp(1, self.buf.rope.len_lines()) - 1;
+        self.move_cursor_to(self.buf.rope.line_to_char(line));
+    }
+
+    fn jump_to_mark(&mut self, mark: char) {
+        match self.buf.marks.get(&mark) {
+            Some(&idx) => self.move_cursor_to(idx),
+            None => self.dbg = format!("mark `{mark}` not set"),
+        }
+    }
+
+    /// The char the cursor is on, `None` at the end of a line or of the buffer.
+    pub fn char_under_cursor(&self) -> Option<char> {
+        self.buf
+            .rope
+            .get_char(self.buf_cursor)
+            .filter(|c| !matches!(c, '\n' | '\r'))
+    }
 
-            if let Some(new_x) = self.scr_cursor.x.checked_sub(1) {
-                self.scr_cursor.x = new_x;
+    /// The word the cursor is on, or the one it is just past, as at the end of a line.
+    pub fn word_under_cursor(&self) -> Option<String> {
+        let rope = &self.buf.rope;
+        let is_word = |idx: usize| {
+            rope.get_char(idx)
+                .is_some_and(|c| char_class(c) == CharClass::Word)
+        };
+        let at = [Some(self.buf_cursor), self.buf_cursor.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .find(|&idx| is_word(idx))?;
+        let range = textobject::word(rope, at)?;
+        Some(rope.slice(range).to_string())
+    }
+
+    /// The cursor line without its line ending.
+    pub fn current_line_text(&self) -> String {
+        let line = self.buf.rope.line(self.cur_line).to_string();
+        line.trim_end_matches(['\n', '\r']).to_string()
+    }
+
+    /// Sets the `"` and `.` marks from the positions remembered for this file,
+    /// optionally jumping to the last change (or the last cursor position).
+    pub fn restore_position(&mut self, session: &Session, jump: bool) {
+        let Some(position) = session.position(&self.buf.path) else {
+            return;
+        };
+        let len = self.buf.rope.len_chars();
+        self.buf.marks.insert('"', position.cursor.min(len));
+        if let Some(change) = position.last_change {
+            self.buf.marks.insert('.', change.min(len));
+        }
+        if jump {
+            let mark = if position.last_change.is_some() {
+                '.'
             } else {
-                self.top_line = self.top_line.saturating_sub(1);
-            }
-            self.cap_cursor();
-            let buf_cursor = self.virtual_lines[self.cur_vline].start + self.scr_cursor.y as usize;
-            self.buf_cursor = buf_cursor;
+                '"'
+            };
+            self.jump_to_mark(mark);
         }
     }
 
-    fn cap_cursor(&mut self) {
-        let cur_line_len = self.virtual_lines[self.cur_vline].len().saturating_sub(1) as u16;
-        self.scr_cursor.y = self.desired_position.min(cur_line_len);
+    pub fn save_positions(&self, session: &mut Session) {
+        let position = FilePosition {
+            cursor: self.buf_cursor,
+            last_change: self.buf.marks.get(&'.').copied(),
+        };
+        session.set_position(&self.buf.path, position);
+        let parked = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != self.active);
+        for (_, buffer) in parked {
+            let position = FilePosition {
+                cursor: buffer.view.cursor,
+                last_change: buffer.buf.marks.get(&'.').copied(),
+            };
+            session.set_position(&buffer.buf.path, position);
+        }
+    }
+
+    fn insert_text(&mut self, at: usize, text: &str) {
+        if self.refuse_edit() {
+            return;
+        }
+        if text.is_empty() {
+            return;
+        }
+        self.buf.history.record(Edit {
+            at,
+            removed: String::new(),
+            inserted: text.into(),
+        });
+        self.apply_insert(at, text);
+    }
+
+    fn remove_text(&mut self, range: Range<usize>) {
+        if self.refuse_edit() {
+            return;
+        }
+        if range.is_empty() {
+            return;
+        }
+        self.buf.history.record(Edit {
+            at: range.start,
+            removed: self.buf.rope.slice(range.clone()).to_string(),
+            inserted: String::new(),
+        });
+        self.apply_remove(range);
+    }
+
+    /// Directory listings can't be edited.
+    fn refuse_edit(&mut self) -> bool {
+        if self.buf.listing {
+            self.dbg = "directory listings can't be changed".into();
+        }
+        self.buf.listing
+    }
+
+    /// Inserts text without recording it for undo.
+    fn apply_insert(&mut self, at: usize, text: &str) {
+        let line = self.buf.rope.char_to_line(at);
+        let lines = self.buf.rope.len_lines();
+        self.buf.shift_signs(line, text.matches('\n').count());
+        self.buf.rope.insert(at, text);
+        self.buf.shift_marks(at, text.chars().count());
+        self.buf.marks.insert('.', at);
+        self.buf.modified = true;
+        self.panes.invalidate(Some(self.active));
+        let line_delta = self.buf.rope.len_lines() as isize - lines as isize;
+        // the line above too, in case the text went between the halves of a CRLF
+        let first = line.saturating_sub(1);
+        self.recompute_virtual_lines_from(first, line, line_delta, text.chars().count() as isize);
+        self.redraw = true;
+    }
+
+    /// Removes text without recording it for undo.
+    fn apply_remove(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        let first_line = self.buf.rope.char_to_line(range.start);
+        let joined = self.buf.rope.char_to_line(range.end) - first_line;
+        let lines = self.buf.rope.len_lines();
+        self.buf.collapse_signs(first_line, joined);
+        self.buf.rope.remove(range.clone());
+        self.buf.collapse_marks(range.clone());
+        self.buf.marks.insert('.', range.start);
+        self.buf.modified = true;
+        self.panes.invalidate(Some(self.active));
+        let line_delta = self.buf.rope.len_lines() as isize - lines as isize;
+        self.recompute_virtual_lines_from(
+            first_line.saturating_sub(1),
+            first_line + joined,
+            line_delta,
+            -(range.len() as isize),
+        );
+        self.clamp_state_to_buffer();
+        self.redraw = true;
     }
 
     fn interface(&mut self) -> Result<()> {
+        self.gutter()?;
+        // zen mode only shows the status line for the prompt, and plainly
+        let background = match self.zen {
+            true => Color::Black,
+            false => Color::DarkGrey,
+        };
+        self.window.stdout.queue(SetBackgroundColor(background))?;
+        let (status, cursor) = if self.mode == Mode::Command {
+            let status = format!("{}{}", self.prompt.symbol(), self.command_buf);
+            let cursor = MoveTo(status.chars().count() as u16, self.window.height);
+            (status, cursor)
+        } else {
+            let mut status = format!("[{}] {}", self.mode, self.dbg);
+            let line_start = self.buf.rope.line_to_char(self.cur_line);
+            let before = self.buf.rope.slice(line_start..self.buf_cursor);
+            let column = grapheme_cells(before.chars(), 1).count() - 1;
+            let mut position = format!("({}:{column})", self.cur_line);
+            if let Some(buffer) = self.buffer_indicator() {
+                position = format!("{buffer} {position}");
+            }
+            if self.settings.show_timer {
+                let elapsed = self.editing_time.elapsed(Instant::now());
+                position = format!("{} {position}", format_duration(elapsed));
+            }
+            let fill = repeat_n(
+                ' ',
+                (self.window.width as usize).saturating_sub(status.len() + position.len()) + 1,
+            );
+            fill.collect_into(&mut status);
+            status += &position;
+            let hscroll = self.cell_column(self.cur_vline, self.hscroll(self.cur_vline));
+            let col = self.scr_cursor.y - hscroll as u16;
+            let cursor = MoveTo(self.text_column() + col, self.pane.y + self.scr_cursor.x);
+            match self.zen {
+                true => (String::new(), cursor),
+                false => (status, cursor),
+            }
+        };
+
+        if self.render_cache.status.as_ref() != Some(&status) {
+            self.window
+                .stdout
+                .queue(MoveTo(0, self.window.height))?
+                .queue(Clear(ClearType::CurrentLine))?
+                .queue(Print(&status))?;
+            self.render_cache.status = Some(status);
+        }
+        self.window
+            .stdout
+            .queue(cursor)?
+            .queue(SetBackgroundColor(Color::Black))?
+            .flush()?;
+        Ok(())
+    }
+
+    /// Turns zen mode on or off, laying out and drawing everything again.
+    fn toggle_zen(&mut self) -> Result<()> {
+        self.zen = !self.zen;
+        self.compute_virtual_lines();
+        self.panes.invalidate(None);
+        self.clamp_state_to_buffer();
+        self.repaint_screen()
+    }
+
+    /// Clears the terminal and forgets what was drawn, so every pane, gutter
+    /// and the status line are drawn again from scratch.
+    fn repaint_screen(&mut self) -> Result<()> {
+        self.window.stdout.queue(Clear(ClearType::All))?;
+        self.render_cache.invalidate();
+        for pane in &mut self.panes.list {
+            pane.cache.invalidate();
+        }
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Draws the sign column and line numbers of the focused pane.
+    fn gutter(&mut self) -> Result<()> {
+        if self.zen {
+            return Ok(());
+        }
         self.window
             .stdout
             .queue(SetBackgroundColor(Color::DarkGrey))?;
@@ -277,87 +1364,390 @@ impl Editor {
         //     &self.scr_cursor,
         // ));
         let mut lines = self.virtual_lines[self.top_line..].iter();
+        let sign_width = self.sign_column_width() as u16;
+        let Rect { y, height, .. } = self.pane;
+        let x = self.gutter_column();
 
-        for row in 0..self.window.height {
-            if let Some(line) = lines.next() {
-                let rel = self.cur_line.abs_diff(line.parent_line);
-                if line.subline {
-                    self.window
-                        .stdout
-                        .queue(MoveTo(0, row))?
-                        .queue(Print(" @ "))?;
-                } else {
-                    self.window
-                        .stdout
-                        .queue(MoveTo(0, row))?
-                        .queue(Print(format!("{:<1$}", rel, Self::LINE_NUMBER_WIDTH)))?;
+        for row in 0..height {
+            let (sign, label) = match lines.next() {
+                Some(line) => {
+                    let sign = match self.buf.signs.get(&line.parent_line) {
+                        Some(sign) if !line.subline => Some((sign.symbol, sign.color)),
+                        _ => None,
+                    };
+                    let label = if line.subline {
+                        " @ ".to_string()
+                    } else {
+                        let number = match self.line_number_mode() {
+                            LineNumberMode::Relative => self.cur_line.abs_diff(line.parent_line),
+                            LineNumberMode::Absolute => line.parent_line + 1,
+                        };
+                        format!("{:<1$}", number, Self::LINE_NUMBER_WIDTH)
+                    };
+                    (sign, label)
                 }
-            } else {
+                None => (None, " ".repeat(Self::LINE_NUMBER_WIDTH)),
+            };
+            let content = render::GutterRow {
+                column: x,
+                sign_width,
+                sign,
+                label,
+                separator: self.settings.gutter_separator.clone(),
+            };
+            if !self.render_cache.gutter.changed(row, &content) {
+                continue;
+            }
+            // the separator always faces the text
+            let separator_width = content.separator.chars().count() as u16;
+            let (x, separator_x) = match self.settings.numbers_on_right {
+                true => (x + separator_width, x),
+                false => (x, x + sign_width + Self::LINE_NUMBER_WIDTH as u16),
+            };
+            if separator_width > 0 {
                 self.window
                     .stdout
-                    .queue(MoveTo(0, row))?
-                    .queue(Print("   "))?;
+                    .queue(MoveTo(separator_x, y + row))?
+                    .queue(Print(&content.separator))?;
+            }
+            if sign_width > 0 {
+                self.window.stdout.queue(MoveTo(x, y + row))?;
+                match sign {
+                    Some((symbol, color)) => self
+                        .window
+                        .stdout
+                        .queue(SetForegroundColor(color))?
+                        .queue(Print(symbol))?
+                        .queue(SetForegroundColor(Color::Reset))?,
+                    None => self.window.stdout.queue(Print(' '))?,
+                };
             }
+            self.window
+                .stdout
+                .queue(MoveTo(x + sign_width, y + row))?
+                .queue(Print(content.label))?;
         }
-        let mut status = format!("[{}] {}", self.mode, self.dbg);
-        let cursor = format!("({}:{})", self.cur_line, self.scr_cursor.y);
-        let fill =
-            repeat(' ').take(((self.window.width as usize) - (status.len() + cursor.len())) + 1);
-        fill.collect_into(&mut status);
-        status += &cursor;
-
-        self.window
-            .stdout
-            .queue(MoveTo(0, self.window.height))?
-            .queue(Print(status))?
-            .queue(MoveTo(
-                self.scr_cursor.y + Self::LINE_NUMBER_WIDTH as u16,
-                self.scr_cursor.x,
-            ))?
-            .queue(SetBackgroundColor(Color::Black))?
-            .flush()?;
+        self.window.stdout.queue(SetBackgroundColor(Color::Black))?;
         Ok(())
     }
 
+    fn sign_column_width(&self) -> usize {
+        usize::from(!self.buf.signs.is_empty())
+    }
+
+    /// Width of everything beside the text: the sign column, line numbers and separator.
+    fn gutter_width(&self) -> usize {
+        if self.zen {
+            return 0;
+        }
+        let separator = self.settings.gutter_separator.chars().count();
+        self.sign_column_width() + Self::LINE_NUMBER_WIDTH + separator
+    }
+
+    /// Columns available for text beside the gutter.
+    fn text_width(&self) -> usize {
+        let width = (self.pane.width as usize).saturating_sub(self.gutter_width());
+        match self.settings.zen_width {
+            max if self.zen && max > 0 => width.min(max),
+            _ => width,
+        }
+    }
+
+    /// Screen column of the first text cell, past the gutter unless it is on the right.
+    fn text_column(&self) -> u16 {
+        if self.zen {
+            let margin = (self.pane.width as usize).saturating_sub(self.text_width()) / 2;
+            return self.pane.x + margin as u16;
+        }
+        match self.settings.numbers_on_right {
+            true => self.pane.x,
+            false => self.pane.x + self.gutter_width() as u16,
+        }
+    }
+
+    /// Screen column the gutter starts at.
+    fn gutter_column(&self) -> u16 {
+        match self.settings.numbers_on_right {
+            true => self.pane.x + self.text_width() as u16,
+            false => self.pane.x,
+        }
+    }
+
+    /// How many chars of a truncated line are scrolled out sideways: just enough
+    /// to show the cursor, keeping the last column free for the `…` marker.
+    fn hscroll(&self, vline: usize) -> usize {
+        if vline != self.cur_vline || !self.virtual_lines[vline].truncated {
+            return 0;
+        }
+        let overflow = (self.scr_cursor.y as usize + 2).saturating_sub(self.text_width());
+        // whole graphemes scroll out, so a wide char is never cut in half
+        self.vline_cells(vline)
+            .into_iter()
+            .find(|&(_, column)| column >= overflow)
+            .map_or(0, |(offset, _)| offset)
+    }
+
     fn compute_virtual_lines(&mut self) {
-        self.virtual_lines.clear();
+        self.virtual_lines = self.wrap_lines(0, usize::MAX);
+        if self.virtual_lines.is_empty() {
+            // an empty buffer still has a line for the cursor to sit on
+            self.virtual_lines.push(VirtualLine::new(0, 0, 0, false));
+        }
+    }
+
+    /// Re-wraps only the lines touched by an edit: lines `first..=last` of the text before it,
+    /// which changed the number of lines by `line_delta` and of chars by `char_delta`.
+    /// The virtual lines after them are shifted instead of wrapped again.
+    fn recompute_virtual_lines_from(
+        &mut self,
+        first: usize,
+        last: usize,
+        line_delta: isize,
+        char_delta: isize,
+    ) {
+        // whether a blank line shows depends on the lines above it
+        if self.settings.squeezeblank {
+            return self.compute_virtual_lines();
+        }
+        let from = self
+            .virtual_lines
+            .partition_point(|l| l.parent_line < first);
+        let to = self
+            .virtual_lines
+            .partition_point(|l| l.parent_line <= last);
+        for vline in &mut self.virtual_lines[to..] {
+            vline.start = vline.start.saturating_add_signed(char_delta);
+            vline.end = vline.end.saturating_add_signed(char_delta);
+            vline.parent_line = vline.parent_line.saturating_add_signed(line_delta);
+        }
+        let wrapped = self.wrap_lines(first, last.saturating_add_signed(line_delta));
+        self.virtual_lines.splice(from..to, wrapped);
+        if self.virtual_lines.is_empty() {
+            self.virtual_lines.push(VirtualLine::new(0, 0, 0, false));
+        }
+    }
 
-        let available_width = self.window.width as usize - Self::LINE_NUMBER_WIDTH;
+    /// The virtual lines of lines `first..=last`.
+    fn wrap_lines(&self, first: usize, last: usize) -> Vec<VirtualLine> {
+        let mut vlines: Vec<VirtualLine> = Vec::new();
+        let available_width = self.text_width().max(1);
+        let max_len = match self.settings.wrap {
+            true => self.settings.maxwrap * available_width,
+            false => available_width,
+        };
         let slice = self.buf.rope.slice(..);
-        let virtual_lines = slice.iter_virtual_lines(0, available_width);
-        self.virtual_lines = virtual_lines.collect();
+        let mut after_blank = false;
+        let tab_width = self.settings.tab_width;
+        let lines = slice.iter_virtual_lines(first, available_width, tab_width);
+        for mut vline in lines.take_while(|l| l.parent_line <= last) {
+            if self.settings.squeezeblank && !vline.subline {
+                let blank = slice.slice(vline.range()).chars().all(char::is_whitespace);
+                // runs of blank lines show as one, the cursor can't land on the rest
+                if blank && after_blank {
+                    continue;
+                }
+                after_blank = blank;
+            }
+            match vlines.last_mut() {
+                Some(prev) if prev.truncated && prev.parent_line == vline.parent_line => {
+                    prev.end = vline.end;
+                }
+                _ => {
+                    let line = slice.line(vline.parent_line);
+                    vline.truncated = max_len > 0
+                        && 2 * line.len_chars() > max_len
+                        && fit_cells(line.chars(), max_len, tab_width) < line.len_chars();
+                    vlines.push(vline);
+                }
+            }
+        }
+        vlines
+    }
+
+    /// Brings the cursor and view back inside the buffer after it shrank.
+    ///
+    /// Must be called whenever text is removed or the rope is replaced,
+    /// `virtual_lines` is expected to be up to date.
+    fn clamp_state_to_buffer(&mut self) {
+        let last_vline = self.virtual_lines.len() - 1;
+        self.top_line = self.top_line.min(last_vline);
+        self.cur_vline = self.cur_vline.min(last_vline);
+        self.place_cursor(self.buf_cursor);
     }
 
     pub fn drive(&mut self) -> Result<()> {
         loop {
             self.interface()?;
-            if poll(Duration::from_millis(1000))? {
+            // check back on a running :grep often enough to list its results promptly
+            let mut timeout = Duration::from_millis(match self.quickfix.pending {
+                Some(_) => 50,
+                None => 1000,
+            });
+            if let Some(deadline) = self.pending_deadline() {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+            if poll(timeout)? {
                 let mode = self.handle_event(read()?)?;
-                self.mode = mode;
+                self.set_mode(mode)?;
+            } else if let Some(mode) = self.expire_pending(Instant::now())? {
+                self.set_mode(mode)?;
+            } else if let Err(e) = self.check_disk_changes() {
+                self.dbg = e.to_string();
             }
-            if self.redraw {
+            self.poll_grep(Duration::ZERO);
+            if self.redraw
+                || self.mode == Mode::Visual
+                || self.bracket_highlight() != self.highlighted_bracket
+            {
+                if self.redraw && self.panes.list.len() > 1 {
+                    self.render_other_panes()?;
+                }
                 self.redraw()?;
             }
             self.window.stdout.flush()?;
             match self.mode {
                 Mode::Normal => (),
                 Mode::Insert => (),
+                Mode::Command => (),
+                Mode::Visual => (),
                 Mode::Quit => break Ok(()),
             }
         }
     }
 
     fn redraw(&mut self) -> Result<()> {
-        for row in 0..self.window.height {
-            self.window
-                .stdout
-                .queue(MoveTo(Self::LINE_NUMBER_WIDTH as u16, row))?
-                .queue(Clear(ClearType::CurrentLine))?;
-            if let Some(line) = self.virtual_lines.get(row as usize + self.top_line) {
-                let line = self.buf.rope.slice(line.range());
-                self.window.stdout.queue(Print(line))?;
+        let column = self.text_column();
+        let text_width = self.text_width();
+        self.highlighted_bracket = self.bracket_highlight();
+        let highlight = self.highlighted_bracket.and_then(|(idx, color)| {
+            let (col, row) = self.screen_position(idx)?;
+            Some((idx, col, row, color))
+        });
+        let selection = self.selection();
+        let splash = self.buf.path.as_os_str().is_empty() && self.buf.rope.len_chars() == 0;
+        let filetype = match self.buf.listing {
+            true => Filetype::Text,
+            false => Filetype::from_path(&self.buf.path),
+        };
+        // the tokens of the last line drawn, shared by the rows it wraps to
+        let mut tokens: Option<(usize, Vec<_>)> = None;
+        for row in 0..self.pane.height {
+            let vline = row as usize + self.top_line;
+            let mut selected = None;
+            let mut spans = Vec::new();
+            let text = if let Some(text) = self.overlay.as_ref().and_then(|o| o.get(row as usize)) {
+                text.clone()
+            } else if let Some(text) = splash
+                .then(|| render::splash_row(row, text_width, self.pane.height))
+                .flatten()
+            {
+                text
+            } else if let Some(line) = self.virtual_lines.get(vline) {
+                let in_line = selection.as_ref().and_then(|s| {
+                    let start = s.start.max(line.start) - line.start;
+                    let end = s.end.min(line.end).checked_sub(line.start)?;
+                    (start < end).then_some(start..end)
+                });
+                let text = self.buf.rope.slice(line.range()).to_string();
+                let text = text.trim_end_matches(['\n', '\r']);
+                let (text, drawn_at) = self.display_text(text, in_line.as_ref());
+                let drawn = |offset: usize| drawn_at[offset.min(drawn_at.len() - 1)];
+                let skip = drawn(self.hscroll(vline));
+                selected = in_line.and_then(|s| {
+                    let (start, end) = (drawn(s.start).max(skip) - skip, drawn(s.end) - skip);
+                    (start < end).then_some(start..end)
+                });
+                let stale = tokens.as_ref().is_none_or(|(l, _)| *l != line.parent_line);
+                if filetype != Filetype::Text && stale {
+                    let text = self.buf.rope.line(line.parent_line).to_string();
+                    tokens = Some((line.parent_line, highlight_line(filetype, &text)));
+                }
+                let line_start = self.buf.rope.line_to_char(line.parent_line);
+                let (from, to) = (line.start - line_start, line.end - line_start);
+                // only the part of each token on this row, where it is drawn
+                spans = tokens
+                    .iter()
+                    .flat_map(|(_, t)| t)
+                    .filter_map(|(range, group)| {
+                        let start = range.start.max(from) - from;
+                        let end = range.end.min(to).checked_sub(from)?;
+                        let (start, end) =
+                            (drawn(start).max(skip) - skip, drawn(end).max(skip) - skip);
+                        (start < end).then_some((start..end, group.color()))
+                    })
+                    .collect();
+                if line.truncated {
+                    let shown = text_width - 1;
+                    let total = text.chars().count();
+                    let fits = fit_cells(text.chars().skip(skip), shown, 1);
+                    let mut text: String = text.chars().skip(skip).take(fits).collect();
+                    if skip + fits < total {
+                        text.push('…');
+                    }
+                    text
+                } else {
+                    text
+                }
+            } else if self.zen {
+                String::new()
             } else {
-                self.window.stdout.queue(Print("~"))?;
+                "~".to_string()
+            };
+            let directory = self.buf.listing && text.ends_with('/');
+            let content = render::TextRow {
+                column,
+                text,
+                color: directory.then_some(Color::Blue),
+                selected,
+                spans,
+                highlight: highlight
+                    .filter(|h| h.2 == self.pane.y + row)
+                    .map(|(_, col, _, color)| (col, color)),
+            };
+            if !self.render_cache.text.changed(row, &content) {
+                continue;
+            }
+            let row = self.pane.y + row;
+            self.window.stdout.queue(MoveTo(column, row))?;
+            // only what changes between pieces is sent
+            let (mut color, mut reversed) = (None, false);
+            for (text, piece_color, selected) in content.segments() {
+                if piece_color != color {
+                    let fg = piece_color.unwrap_or(Color::Reset);
+                    self.window.stdout.queue(SetForegroundColor(fg))?;
+                    color = piece_color;
+                }
+                if selected != reversed {
+                    let attribute = match selected {
+                        true => Attribute::Reverse,
+                        false => Attribute::NoReverse,
+                    };
+                    self.window.stdout.queue(SetAttribute(attribute))?;
+                    reversed = selected;
+                }
+                self.window.stdout.queue(Print(text))?;
+            }
+            if reversed {
+                self.window
+                    .stdout
+                    .queue(SetAttribute(Attribute::NoReverse))?;
+            }
+            if color.is_some() {
+                self.window.stdout.queue(SetForegroundColor(Color::Reset))?;
+            }
+            // pad instead of clearing to the end of the line, which would wipe the pane to the right
+            let width = grapheme_cells(content.text.chars(), 1)
+                .last()
+                .map_or(0, |(_, w)| w);
+            let padding = " ".repeat(text_width.saturating_sub(width));
+            self.window.stdout.queue(Print(padding))?;
+            if let Some((idx, col, _, color)) = highlight.filter(|h| h.2 == row) {
+                self.window
+                    .stdout
+                    .queue(MoveTo(col, row))?
+                    .queue(SetBackgroundColor(color))?
+                    .queue(Print(self.buf.rope.char(idx)))?
+                    .queue(SetBackgroundColor(Color::Reset))?;
             }
         }
 
@@ -365,44 +1755,282 @@ impl Editor {
         Ok(())
     }
 
+    /// The row `text` of a virtual line is drawn as: tabs expanded to spaces up to
+    /// the next tab stop, and spaces and tabs swapped for `·` and `→` where
+    /// the `whitespace` option shows them, `selected` being the chars inside the selection.
+    /// Also returns where each char of `text`, and its end, is in the drawn row.
+    fn display_text(&self, text: &str, selected: Option<&Range<usize>>) -> (String, Vec<usize>) {
+        let shown = |i: usize| match self.settings.show_whitespace {
+            ShowWhitespace::Off => false,
+            ShowWhitespace::All => true,
+            ShowWhitespace::Selection => selected.is_some_and(|s| s.contains(&i)),
+        };
+        let chars: Vec<char> = text.chars().collect();
+        let mut drawn = Vec::with_capacity(chars.len());
+        let mut drawn_at = Vec::with_capacity(chars.len() + 1);
+        let mut cells = grapheme_cells(chars.iter().copied(), self.settings.tab_width).peekable();
+        while let Some((offset, column)) = cells.next() {
+            let Some(&(next, next_column)) = cells.peek() else {
+                drawn_at.push(drawn.len());
+                break;
+            };
+            for (i, &ch) in chars.iter().enumerate().take(next).skip(offset) {
+                drawn_at.push(drawn.len());
+                match ch {
+                    ' ' if shown(i) => drawn.push('·'),
+                    '\t' => {
+                        drawn.push(if shown(i) { '→' } else { ' ' });
+                        drawn.extend(repeat_n(' ', next_column - column - 1));
+                    }
+                    ch => drawn.push(ch),
+                }
+            }
+        }
+        (drawn.into_iter().collect(), drawn_at)
+    }
+
+    /// The bracket to highlight for the one at (or in Insert mode, before) the cursor:
+    /// its match, or the bracket itself in red when it has none.
+    fn bracket_highlight(&self) -> Option<(usize, Color)> {
+        if !self.settings.showmatch || self.overlay.is_some() {
+            return None;
+        }
+        let rope = &self.buf.rope;
+        let on_bracket = |idx: usize| rope.get_char(idx).filter(|c| is_bracket(*c)).map(|_| idx);
+        let bracket = on_bracket(self.buf_cursor).or_else(|| match self.mode {
+            Mode::Insert => on_bracket(self.buf_cursor.checked_sub(1)?),
+            _ => None,
+        })?;
+        match matching_bracket(rope, bracket, Self::SHOWMATCH_LIMIT) {
+            Some(matching) => Some((matching, Color::DarkCyan)),
+            // a bracket whose match may just be further away isn't flagged
+            None => {
+                let scanned = match rope.char(bracket) {
+                    '(' | '[' | '{' => rope.len_chars() - bracket,
+                    _ => bracket + 1,
+                };
+                (scanned <= Self::SHOWMATCH_LIMIT).then_some((bracket, Color::Red))
+            }
+        }
+    }
+
+    /// Where the char at `idx` is drawn, if it is on screen.
+    fn screen_position(&self, idx: usize) -> Option<(u16, u16)> {
+        let vline = self
+            .virtual_lines
+            .partition_point(|l| l.start <= idx)
+            .checked_sub(1)?;
+        let row = vline.checked_sub(self.top_line)?;
+        if row >= self.pane.height as usize {
+            return None;
+        }
+        let col = self
+            .cell_column(vline, idx - self.virtual_lines[vline].start)
+            .checked_sub(self.cell_column(vline, self.hscroll(vline)))?;
+        if col >= self.text_width() {
+            return None;
+        }
+        Some((self.text_column() + col as u16, self.pane.y + row as u16))
+    }
+
     fn handle_event(&mut self, event: Event) -> Result<Mode> {
+        let confirming_quit = self.quit_requested;
+        let mode = self.dispatch_event(event)?;
+        if confirming_quit {
+            self.quit_requested = false;
+        }
+        self.commit_history(mode);
+        Ok(mode)
+    }
+
+    /// Whatever a key does outside Insert mode is undone at once,
+    /// a visit to Insert mode is undone as a whole.
+    fn commit_history(&mut self, mode: Mode) {
+        if matches!(mode, Mode::Normal | Mode::Visual) {
+            let settings = &self.settings;
+            self.buf
+                .history
+                .commit(settings.undolevels, settings.undosize);
+        }
+    }
+
+    /// When the keys typed so far stop waiting for the rest of a longer chord.
+    fn pending_deadline(&self) -> Option<Instant> {
+        let timeout = Duration::from_millis(self.settings.timeoutlen as u64);
+        Some(self.pending_since? + timeout)
+    }
+
+    /// Settles on the keys typed so far once `timeoutlen` has passed at `now`
+    /// without the next key of a chord, returning the mode it leads to.
+    fn expire_pending(&mut self, now: Instant) -> Result<Option<Mode>> {
+        if self
+            .pending_deadline()
+            .is_none_or(|deadline| now < deadline)
+        {
+            return Ok(None);
+        }
+        let mode = self.run_pending()?;
+        self.commit_history(mode);
+        self.redraw = true;
+        Ok(Some(mode))
+    }
+
+    /// Runs what the pending keys are bound to on their own, if anything, and starts over.
+    fn run_pending(&mut self) -> Result<Mode> {
+        let key = Chord(self.mode, std::mem::take(&mut self.pending));
+        self.pending_since = None;
+        let Some(command) = self.bindings.remove(&key) else {
+            self.count = None;
+            return Ok(self.mode);
+        };
+        let mode = command.execute(self);
+        self.bindings.insert(key, command);
+        self.count = None;
+        mode
+    }
+
+    fn dispatch_event(&mut self, event: Event) -> Result<Mode> {
         match event {
             Event::Key(KeyEvent {
                 code,
-                modifiers,
+                mut modifiers,
                 kind,
                 state: _,
             }) => match kind {
                 KeyEventKind::Press => {
+                    // the palette's list stays up while its query is typed
+                    let palette = self.mode == Mode::Command && self.prompt == Prompt::Palette;
+                    if self.overlay.take().is_some() && !palette {
+                        self.redraw = true;
+                        return Ok(self.mode);
+                    }
+                    let code = match code {
+                        KeyCode::Char(ch) if modifiers.contains(KeyModifiers::SHIFT) => {
+                            modifiers.remove(KeyModifiers::SHIFT);
+                            KeyCode::Char(ch.to_uppercase().next().unwrap())
+                        }
+                        KeyCode::BackTab => {
+                            modifiers.remove(KeyModifiers::SHIFT);
+                            code
+                        }
+                        code => code,
+                    };
                     let mode = self.mode;
                     match mode {
-                        Mode::Normal => {
-                            let key = (mode, modifiers, code);
-                            let command = self.bindings.remove(&key);
-                            if let Some(command) = command {
-                                let mode = command.execute(self);
-                                self.bindings.insert(key, command);
-                                return mode;
+                        Mode::Normal | Mode::Visual => {
+                            match code {
+                                KeyCode::Char(digit @ '0'..='9')
+                                    if self.pending.is_empty()
+                                        && modifiers == KeyModifiers::NONE
+                                        && (digit != '0' || self.count.is_some()) =>
+                                {
+                                    let digit = digit.to_digit(10).unwrap() as usize;
+                                    let count = self.count.unwrap_or(0);
+                                    self.count =
+                                        Some(count.saturating_mul(10).saturating_add(digit));
+                                    return Ok(mode);
+                                }
+                                _ => (),
+                            }
+                            self.pending.push((modifiers, code));
+                            let key = Chord(mode, self.pending.clone());
+                            if self.bindings.keys().any(|k| key.is_prefix_of(k)) {
+                                // wait for the next key, or settle on these after `timeoutlen`
+                                self.pending_since = Some(Instant::now());
+                                return Ok(mode);
+                            }
+                            if self.bindings.contains_key(&key) {
+                                return self.run_pending();
                             }
+                            // keys leading nowhere: a shorter chord they complete runs
+                            // and the last key starts over, anything else is dropped
+                            self.pending.pop();
+                            let prefix = Chord(mode, self.pending.clone());
+                            if !self.bindings.contains_key(&prefix) {
+                                self.pending.clear();
+                                self.pending_since = None;
+                                self.count = None;
+                                return Ok(mode);
+                            }
+                            let mode = self.run_pending()?;
+                            self.set_mode(mode)?;
+                            return self.dispatch_event(Event::Key(KeyEvent::new(code, modifiers)));
+                        }
+                        Mode::Insert if std::mem::take(&mut self.awaiting_register) => {
+                            let KeyCode::Char(name) = code else {
+                                return Ok(Mode::Insert);
+                            };
+                            if name != '=' {
+                                self.insert_register(name);
+                                return Ok(Mode::Insert);
+                            }
+                            self.command_buf.clear();
+                            self.prompt = Prompt::Expression;
+                            return Ok(Mode::Command);
                         }
                         Mode::Insert => match code {
-                            KeyCode::Esc => return Ok(Mode::Normal),
+                            KeyCode::Esc => {
+                                self.finish_block_insert();
+                                return Ok(Mode::Normal);
+                            }
+                            KeyCode::Char('r') if modifiers == KeyModifiers::CONTROL => {
+                                self.awaiting_register = true
+                            }
                             KeyCode::Enter if modifiers == KeyModifiers::NONE => {
-                                self.buf.rope.insert_char(self.buf_cursor, '\n');
-                                self.compute_virtual_lines();
-                                self.cursor_down();
-                                self.redraw = true;
+                                self.insert_newline()
                             }
-                            KeyCode::Char(ch) => {
-                                let ch = if modifiers == KeyModifiers::SHIFT {
-                                    ch.to_uppercase().next().unwrap()
-                                } else {
-                                    ch
+                            KeyCode::Backspace => self.delete_char_backward(),
+                            KeyCode::Char('w') if modifiers == KeyModifiers::CONTROL => {
+                                self.delete_word_before()
+                            }
+                            KeyCode::Char('u') if modifiers == KeyModifiers::CONTROL => {
+                                self.delete_to_line_start()
+                            }
+                            KeyCode::Delete => self.delete_char_forward(),
+                            KeyCode::Char(ch) => self.insert_char(ch),
+                            KeyCode::Tab => {
+                                let indent = self.indent_unit();
+                                self.insert_text(self.buf_cursor, &indent);
+                                for _ in 0..indent.len() {
+                                    self.cursor_right();
+                                }
+                            }
+                            _ => (),
+                        },
+                        Mode::Command => match code {
+                            KeyCode::Esc => return Ok(self.leave_prompt()),
+                            KeyCode::Enter => {
+                                let line = std::mem::take(&mut self.command_buf);
+                                return match self.prompt {
+                                    Prompt::Command => {
+                                        if !line.trim().is_empty() {
+                                            self.last_command = Some(line.clone());
+                                        }
+                                        self.execute_command(&line)
+                                    }
+                                    Prompt::Expression => self.insert_expression(&line),
+                                    Prompt::Search => {
+                                        self.move_cursor_to(self.search_origin);
+                                        self.search(&line, self.search_count);
+                                        Ok(Mode::Normal)
+                                    }
+                                    Prompt::Palette => self.run_palette(&line),
                                 };
-                                self.buf.rope.insert_char(self.buf_cursor, ch);
-                                self.compute_virtual_lines();
-                                self.cursor_right();
-                                self.redraw = true;
+                            }
+                            KeyCode::Backspace if self.command_buf.pop().is_none() => {
+                                return Ok(self.leave_prompt())
+                            }
+                            KeyCode::Backspace if self.prompt == Prompt::Search => {
+                                self.preview_search()
+                            }
+                            KeyCode::Backspace if palette => self.filter_palette(),
+                            KeyCode::Char(ch) => {
+                                self.command_buf.push(ch);
+                                match self.prompt {
+                                    Prompt::Search => self.preview_search(),
+                                    Prompt::Palette => self.filter_palette(),
+                                    _ => (),
+                                }
                             }
                             _ => (),
                         },
@@ -413,16 +2041,705 @@ impl Editor {
                 KeyEventKind::Release => (),
             },
             Event::Mouse(_) => (),
-            Event::Paste(_) => (),
+            Event::Paste(text) => self.paste_text(&text),
             Event::Resize(width, height) => {
                 self.window.height = height;
                 self.window.width = width;
-                self.redraw = true;
+                self.relayout();
                 return Ok(self.mode);
             }
-            Event::FocusGained => (),
-            Event::FocusLost => (),
+            Event::FocusGained => self.editing_time.resume(Instant::now()),
+            Event::FocusLost => self.editing_time.pause(Instant::now()),
         }
         Ok(self.mode)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ropey::Rope;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// A writer whose output stays readable after it's handed to the editor.
+    #[derive(Clone, Default)]
+    pub(super) struct Output(Rc<RefCell<Vec<u8>>>);
+
+    impl Output {
+        pub(super) fn take(&self) -> String {
+            String::from_utf8(self.0.take()).unwrap()
+        }
+    }
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    pub(super) fn editor_with(buf: FileBuf) -> Editor {
+        let window = Window {
+            height: 10,
+            width: 40,
+            stdout: Box::new(Vec::new()),
+        };
+        Editor::new(window, buf)
+    }
+
+    pub(super) fn editor(text: &str) -> Editor {
+        editor_with(FileBuf {
+            rope: Rope::from_str(text),
+            path: "test.txt".into(),
+            ..Default::default()
+        })
+    }
+
+    pub(super) fn press_with(e: &mut Editor, modifiers: KeyModifiers, code: KeyCode) {
+        let mode = e
+            .handle_event(Event::Key(KeyEvent::new(code, modifiers)))
+            .unwrap();
+        e.set_mode(mode).unwrap();
+    }
+
+    pub(super) fn press(e: &mut Editor, code: KeyCode) {
+        press_with(e, KeyModifiers::NONE, code);
+    }
+
+    pub(super) fn keys(e: &mut Editor, keys: &str) {
+        for ch in keys.chars() {
+            press(e, KeyCode::Char(ch));
+        }
+    }
+
+    #[test]
+    fn test_last_change_mark() {
+        let mut e = editor("first line\nsecond line\nthird line\n");
+        keys(&mut e, "sddi");
+        keys(&mut e, "X");
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf.rope.line(1).to_string(), "seXcond line\n");
+
+        keys(&mut e, "wwa");
+        assert_ne!(e.buf_cursor, 13);
+        keys(&mut e, "`.");
+        assert_eq!(e.buf_cursor, 13);
+        assert_eq!(e.cur_line, 1);
+        assert_eq!(e.scr_cursor.y, 2);
+    }
+
+    #[test]
+    fn test_cursor_style_from_config() {
+        let mut e = editor("text\n");
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        let config = Config::parse("[cursor]\nnormal = \"block\"\ninsert = \"bar blink\"\n");
+        e.configure(config).unwrap();
+        assert_eq!(out.take(), "\x1b[2 q");
+
+        press(&mut e, KeyCode::Char('i'));
+        assert_eq!(out.take(), "\x1b[5 q");
+        press(&mut e, KeyCode::Char('x'));
+        assert_eq!(out.take(), "");
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(out.take(), "\x1b[2 q");
+        keys(&mut e, "v");
+        assert_eq!(out.take(), "\x1b[0 q");
+    }
+
+    #[test]
+    fn test_squeeze_blank_lines() {
+        let text = "a\n\n  \n\nb\n";
+        let mut e = editor(text);
+        keys(&mut e, ":set squeezeblank");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.virtual_lines.len(), 3);
+        assert_eq!(e.buf.rope.to_string(), text);
+
+        keys(&mut e, "ss");
+        assert_eq!(e.cur_line, 4);
+        assert_eq!(e.buf.rope.char(e.buf_cursor), 'b');
+        keys(&mut e, "w");
+        assert_eq!(e.cur_line, 1);
+    }
+
+    #[test]
+    fn test_numbers_on_right() {
+        let mut e = editor("hello\nworld\n");
+        keys(&mut e, ":set numbersonright");
+        press(&mut e, KeyCode::Enter);
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[1;1Hhello"));
+        assert!(screen.contains("\x1b[2;1Hworld"));
+        assert!(screen.contains("\x1b[1;38H0  "));
+        assert!(screen.contains("\x1b[2;38H1  "));
+        assert_eq!(e.screen_position(1), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_auto_relative_numbers() {
+        let mut e = editor("a\nb\nc\n");
+        keys(&mut e, ":set autorelative");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "s");
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[1;1H1  \x1b[2;1H0  \x1b[3;1H1  "));
+
+        keys(&mut e, "i");
+        e.interface().unwrap();
+        let screen = out.take();
+        // the first row reads 1 either way and isn't repainted
+        assert!(screen.contains("\x1b[2;1H2  \x1b[3;1H3  "));
+        assert!(!screen.contains("\x1b[1;1H"));
+
+        press(&mut e, KeyCode::Esc);
+        e.interface().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[2;1H0  \x1b[3;1H1  "));
+    }
+
+    #[test]
+    fn test_text_under_cursor() {
+        let mut e = editor("let foo_bar = 1;\n\n");
+        e.move_cursor_to(5);
+        assert_eq!(e.char_under_cursor(), Some('o'));
+        assert_eq!(e.word_under_cursor().as_deref(), Some("foo_bar"));
+        assert_eq!(e.current_line_text(), "let foo_bar = 1;");
+
+        e.move_cursor_to(12);
+        assert_eq!(e.char_under_cursor(), Some('='));
+        assert_eq!(e.word_under_cursor(), None);
+
+        // end of line, right after a word
+        let mut e = editor("end\n");
+        e.move_cursor_to(3);
+        assert_eq!(e.char_under_cursor(), None);
+        assert_eq!(e.word_under_cursor().as_deref(), Some("end"));
+
+        let e = editor("");
+        assert_eq!(e.char_under_cursor(), None);
+        assert_eq!(e.word_under_cursor(), None);
+        assert_eq!(e.current_line_text(), "");
+    }
+
+    #[test]
+    fn test_vertical_motions_keep_column() {
+        let mut e = editor("0123456789\nab\n\n0123456789\n");
+        keys(&mut e, "dddddd");
+        assert_eq!(e.scr_cursor.y, 6);
+        keys(&mut e, "s");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (1, 2));
+        keys(&mut e, "s");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (2, 0));
+        keys(&mut e, "s");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (3, 6));
+
+        // a horizontal move sets the new column to keep
+        keys(&mut e, "awww");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (0, 5));
+
+        // End sticks to line ends, Home goes back to keeping a column
+        press(&mut e, KeyCode::End);
+        assert_eq!(e.scr_cursor.y, 10);
+        keys(&mut e, "s");
+        assert_eq!(e.scr_cursor.y, 2);
+        keys(&mut e, "ss");
+        assert_eq!(e.scr_cursor.y, 10);
+        press(&mut e, KeyCode::Home);
+        keys(&mut e, "www");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (0, 0));
+    }
+
+    #[test]
+    fn test_motions_across_wrapped_lines() {
+        let text = format!("{}\nshort\n", "x".repeat(50));
+        let mut e = editor(&text);
+        assert!(e.virtual_lines[1].subline);
+        e.move_cursor_to(36);
+        keys(&mut e, "d");
+        assert_eq!((e.cur_vline, e.scr_cursor.y, e.buf_cursor), (1, 0, 37));
+        keys(&mut e, "a");
+        assert_eq!((e.cur_vline, e.scr_cursor.y, e.buf_cursor), (0, 36, 36));
+        keys(&mut e, "ss");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (1, 5));
+        keys(&mut e, "w");
+        assert_eq!((e.cur_vline, e.scr_cursor.y), (1, 13));
+    }
+
+    #[test]
+    fn test_motions_over_graphemes() {
+        let mut e = editor("日本語e\u{301}x\nabc\n");
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (1, 2));
+        keys(&mut e, "dd");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 6));
+        // the accent is part of the e
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (5, 7));
+        keys(&mut e, "a");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 6));
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        assert!(out.take().contains("(0:3)"));
+
+        // vertical motions keep the cell column, landing on the char covering it
+        keys(&mut e, "s");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (10, 3));
+        keys(&mut e, "w");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 6));
+        keys(&mut e, "s");
+        press(&mut e, KeyCode::Home);
+        keys(&mut e, "dddw");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (1, 2));
+    }
+
+    #[test]
+    fn test_tabs_expand_to_tab_stops() {
+        let mut e = editor("\tx\ty\nabcdefgh\n");
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (1, 4));
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (2, 5));
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 8));
+        // landing inside a tab puts the cursor on its start
+        keys(&mut e, "saaw");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (2, 5));
+        keys(&mut e, "d");
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 8));
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        assert!(out.take().contains("    x   y"));
+
+        keys(&mut e, ":set tabstop=2");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.buf_cursor, e.scr_cursor.y), (3, 4));
+        let text = format!("{}\n", "\t".repeat(20));
+        e.buf.rope = Rope::from_str(&text);
+        e.compute_virtual_lines();
+        assert_eq!(e.virtual_lines.len(), 2);
+    }
+
+    #[test]
+    fn test_wide_chars_wrap_by_width() {
+        let mut e = editor(&format!("{}\n", "日".repeat(20)));
+        assert_eq!(e.virtual_lines[0].len(), 18);
+        assert!(e.virtual_lines[1].subline);
+        e.move_cursor_to(17);
+        keys(&mut e, "d");
+        assert_eq!((e.cur_vline, e.scr_cursor.y, e.buf_cursor), (1, 0, 18));
+    }
+
+    #[test]
+    fn test_word_motions() {
+        let text = format!("aa {} cc\nlast", "b".repeat(40));
+        let mut e = editor(&text);
+        keys(&mut e, "e");
+        assert_eq!(e.buf_cursor, 3);
+        keys(&mut e, "e");
+        assert_eq!((e.buf_cursor, e.cur_vline, e.scr_cursor.y), (44, 1, 7));
+        keys(&mut e, "e");
+        assert_eq!((e.buf_cursor, e.cur_line, e.cur_vline), (47, 1, 2));
+        keys(&mut e, "e");
+        assert_eq!(e.buf_cursor, 51);
+
+        keys(&mut e, "bb");
+        assert_eq!((e.buf_cursor, e.cur_vline), (44, 1));
+        keys(&mut e, "bbb");
+        assert_eq!((e.buf_cursor, e.cur_vline, e.scr_cursor.y), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_onemore_at_line_end() {
+        let mut e = editor("abc\nde\n");
+        keys(&mut e, "ddddd");
+        assert_eq!(e.buf_cursor, 3);
+        press(&mut e, KeyCode::End);
+        keys(&mut e, "s");
+        assert_eq!(e.buf_cursor, 6);
+
+        let mut e = editor("abc\nde\n");
+        keys(&mut e, ":set noonemore");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "ddddd");
+        assert_eq!(e.buf_cursor, 2);
+        keys(&mut e, "s");
+        assert_eq!(e.buf_cursor, 5);
+        press(&mut e, KeyCode::Home);
+        press(&mut e, KeyCode::End);
+        assert_eq!(e.buf_cursor, 5);
+
+        // turning it off pulls a cursor resting on the line break back
+        keys(&mut e, ":set onemore");
+        press(&mut e, KeyCode::Enter);
+        press(&mut e, KeyCode::End);
+        assert_eq!(e.buf_cursor, 6);
+        keys(&mut e, ":set noonemore");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf_cursor, 5);
+    }
+
+    #[test]
+    fn test_shrinking_resize_keeps_cursor_visible() {
+        let mut e = editor(&"line\n".repeat(30));
+        keys(&mut e, &"s".repeat(12));
+        assert_eq!((e.cur_vline, e.top_line, e.scr_cursor.x), (12, 3, 9));
+
+        e.handle_event(Event::Resize(40, 4)).unwrap();
+        assert_eq!(e.cur_vline, 12);
+        assert!(e.scr_cursor.x < 4);
+        assert_eq!(e.top_line + e.scr_cursor.x as usize, 12);
+
+        // no room at all, one column per row
+        e.handle_event(Event::Resize(2, 0)).unwrap();
+        assert_eq!((e.cur_line, e.scr_cursor.x), (12, 0));
+        assert_eq!(e.top_line, e.cur_vline);
+        e.handle_event(Event::Resize(40, 10)).unwrap();
+        assert_eq!(e.cur_vline, 12);
+        assert!(e.scr_cursor.x < 10);
+    }
+
+    #[test]
+    fn test_edits_rewrap_only_changed_lines() {
+        let mut e = editor(&"some text on a line\n".repeat(10_000));
+        e.goto_line(5_000);
+        keys(&mut e, "i");
+        keys(&mut e, &"long enough to wrap ".repeat(3));
+        press(&mut e, KeyCode::Enter);
+        press(&mut e, KeyCode::Backspace);
+        press(&mut e, KeyCode::Backspace);
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.virtual_lines[4_999].parent_line, 4_999);
+        assert!(e.virtual_lines[5_000].subline);
+        let rewrapped = std::mem::take(&mut e.virtual_lines);
+        e.compute_virtual_lines();
+        assert_eq!(rewrapped, e.virtual_lines);
+
+        // joining the halves of a CRLF
+        let mut e = editor("one\rtwo\nthree\n");
+        e.move_cursor_to(4);
+        keys(&mut e, "i");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "ggx");
+        let rewrapped = std::mem::take(&mut e.virtual_lines);
+        e.compute_virtual_lines();
+        assert_eq!(rewrapped, e.virtual_lines);
+    }
+
+    #[test]
+    fn test_page_up_and_down() {
+        let mut e = editor(&"line\n".repeat(30));
+        e.settings.scroll_past_end = true;
+        keys(&mut e, "dd");
+        press(&mut e, KeyCode::PageDown);
+        assert_eq!((e.top_line, e.cur_line, e.scr_cursor.y), (9, 9, 2));
+        press(&mut e, KeyCode::PageDown);
+        press(&mut e, KeyCode::PageDown);
+        assert_eq!((e.top_line, e.cur_line), (27, 27));
+        press(&mut e, KeyCode::PageDown);
+        assert_eq!((e.top_line, e.cur_line), (29, 29));
+        press(&mut e, KeyCode::PageUp);
+        assert_eq!((e.top_line, e.cur_line), (20, 20));
+        press(&mut e, KeyCode::PageUp);
+        press(&mut e, KeyCode::PageUp);
+        press(&mut e, KeyCode::PageUp);
+        assert_eq!((e.top_line, e.cur_line, e.buf_cursor), (0, 0, 2));
+    }
+
+    #[test]
+    fn test_chord_prefix_times_out() {
+        let mut e = editor("one\ntwo\n");
+        let later = || Instant::now() + Duration::from_secs(2);
+        keys(&mut e, "x");
+        assert_eq!(e.expire_pending(Instant::now()).unwrap(), None);
+        assert_eq!(e.expire_pending(later()).unwrap(), Some(Mode::Normal));
+        assert!(e.pending.is_empty());
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\n");
+        keys(&mut e, "xx");
+        assert_eq!(e.buf.rope.to_string(), "two\n");
+
+        // a key bound on its own as well waits for the timeout, or for a key it can't be followed by
+        let config = crate::config::Config::parse("[keys.normal]\nx = \"cursor_right\"\n");
+        e.configure(config).unwrap();
+        keys(&mut e, "x");
+        assert_eq!(e.buf_cursor, 0);
+        e.expire_pending(later()).unwrap();
+        assert_eq!(e.buf_cursor, 1);
+        keys(&mut e, "xd");
+        assert_eq!(e.buf_cursor, 3);
+        assert!(e.pending.is_empty());
+    }
+
+    #[test]
+    fn test_scroll_past_end() {
+        let mut e = editor(&"line\n".repeat(30));
+        for _ in 0..4 {
+            press(&mut e, KeyCode::PageDown);
+        }
+        assert_eq!((e.top_line, e.cur_line), (20, 29));
+
+        keys(&mut e, ":set scrollpastend");
+        press(&mut e, KeyCode::Enter);
+        press(&mut e, KeyCode::PageDown);
+        assert_eq!(e.top_line, e.virtual_lines.len() - 1);
+        assert_eq!(e.scr_cursor.x, 0);
+    }
+
+    #[test]
+    fn test_home_and_end_on_wrapped_lines() {
+        let mut e = editor(&format!("{}\nshort\n", "x".repeat(50)));
+        e.move_cursor_to(40);
+        press(&mut e, KeyCode::Home);
+        assert_eq!((e.cur_vline, e.buf_cursor), (1, 37));
+        press(&mut e, KeyCode::End);
+        assert_eq!((e.cur_vline, e.buf_cursor), (1, 50));
+        keys(&mut e, "w");
+        press(&mut e, KeyCode::End);
+        assert_eq!((e.cur_vline, e.buf_cursor), (0, 36));
+    }
+
+    #[test]
+    fn test_narrowing_resize_rewraps_lines() {
+        let mut e = editor(&format!("{}\nshort\n", "x".repeat(50)));
+        e.move_cursor_to(30);
+        assert_eq!((e.cur_vline, e.scr_cursor.y), (0, 30));
+
+        e.handle_event(Event::Resize(20, 10)).unwrap();
+        assert_eq!(e.text_width(), 17);
+        assert_eq!(e.virtual_lines.len(), 4);
+        assert_eq!((e.buf_cursor, e.cur_vline, e.scr_cursor.y), (30, 1, 13));
+
+        e.handle_event(Event::Resize(40, 10)).unwrap();
+        assert_eq!((e.buf_cursor, e.cur_vline, e.scr_cursor.y), (30, 0, 30));
+    }
+
+    #[test]
+    fn test_window_narrower_than_gutter() {
+        let mut e = editor("hello\n\nworld\n");
+        e.handle_event(Event::Resize(1, 10)).unwrap();
+        assert_eq!(e.text_width(), 0);
+        assert_eq!(e.virtual_lines.len(), 13);
+        keys(&mut e, "ss");
+        assert_eq!((e.cur_line, e.cur_vline), (0, 2));
+        e.redraw().unwrap();
+
+        let mut e = editor("");
+        e.handle_event(Event::Resize(1, 10)).unwrap();
+        assert_eq!(e.virtual_lines.len(), 1);
+    }
+
+    #[test]
+    fn test_gutter_separator() {
+        let mut e = editor("hello\n");
+        assert_eq!(e.text_width(), 37);
+        keys(&mut e, ":set gutterseparator=│");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.text_width(), 36);
+        assert_eq!(e.screen_position(0), Some((4, 0)));
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[1;4H│"));
+        assert!(screen.contains("\x1b[1;5Hhello"));
+        // the cursor goes where the text is
+        assert!(screen.contains("\x1b[1;5H\x1b[48;5;0m"));
+
+        keys(&mut e, ":set numbersonright");
+        press(&mut e, KeyCode::Enter);
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[1;37H│"));
+        assert!(screen.contains("\x1b[1;38H0  "));
+    }
+
+    #[test]
+    fn test_zen_mode() {
+        let mut e = editor(&format!("{}\n", "x".repeat(50)));
+        assert_eq!(e.virtual_lines.len(), 2);
+        keys(&mut e, ":set zenwidth=30");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, ":zen");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.gutter_width(), e.text_width()), (0, 30));
+        assert_eq!(e.screen_position(0), Some((5, 0)));
+        assert_eq!(e.virtual_lines.len(), 2);
+        assert_eq!(e.virtual_lines[0].range(), 0..30);
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(!screen.contains("[NORMAL]") && !screen.contains('~'));
+
+        keys(&mut e, ":zen");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.gutter_width(), e.text_width()), (3, 37));
+        assert_eq!(e.virtual_lines[0].range(), 0..37);
+    }
+
+    #[test]
+    fn test_ctrl_l_repaints_everything() {
+        let mut e = editor("hello\nworld\n");
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        out.take();
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('l'));
+        assert!(e.redraw);
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        let clear = screen.find("\x1b[2J").unwrap();
+        for drawn in ["hello", "world", "0  ", "1  ", "~", "[NORMAL]"] {
+            assert!(screen[clear..].contains(drawn), "{drawn} not repainted");
+        }
+    }
+
+    #[test]
+    fn test_whitespace_shown_in_selection() {
+        let mut e = editor("a\tb c\td\n");
+        keys(&mut e, ":set whitespace=selection");
+        press(&mut e, KeyCode::Enter);
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        keys(&mut e, "vdd");
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("a→  b") && screen.contains(" c d"));
+        assert!(!screen.contains('·'));
+
+        keys(&mut e, ":set whitespace=all");
+        press(&mut e, KeyCode::Enter);
+        e.redraw().unwrap();
+        assert!(out.take().contains("a→  b·c→d"));
+    }
+
+    #[test]
+    fn test_long_line_is_truncated() {
+        let text = format!("{}\nshort\n", "x".repeat(1000));
+        let mut e = editor(&text);
+        assert_eq!(e.virtual_lines.len(), 2);
+        assert!(e.virtual_lines[0].truncated);
+        assert_eq!(e.virtual_lines[0].range(), 0..1001);
+        assert!(!e.virtual_lines[1].truncated);
+
+        e.move_cursor_to(500);
+        assert_eq!(e.hscroll(0), 465);
+        assert_eq!(e.screen_position(500), Some((38, 0)));
+        assert_eq!(e.screen_position(10), None);
+
+        keys(&mut e, ":set maxwrap=0");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.virtual_lines.len(), 29);
+    }
+
+    #[test]
+    fn test_nowrap_scrolls_sideways() {
+        let text = format!("{}\n{}\nshort\n", "x".repeat(50), "y".repeat(100));
+        let mut e = editor(&text);
+        assert_eq!(e.virtual_lines.len(), 6);
+
+        keys(&mut e, "zw");
+        assert!(!e.settings.wrap);
+        assert_eq!(e.virtual_lines.len(), 3);
+        assert!(e.virtual_lines[..2].iter().all(|l| l.truncated));
+        assert!(!e.virtual_lines[2].truncated);
+
+        keys(&mut e, &"d".repeat(40));
+        assert_eq!(e.buf_cursor, 40);
+        assert_eq!(e.hscroll(0), 5);
+        assert_eq!(e.screen_position(40), Some((38, 0)));
+        keys(&mut e, "s");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 91));
+        assert_eq!((e.hscroll(0), e.hscroll(1)), (0, 5));
+        assert_eq!(e.screen_position(0), Some((3, 0)));
+
+        keys(&mut e, "zw");
+        assert_eq!(e.virtual_lines.len(), 6);
+        assert_eq!(e.cur_line, 1);
+    }
+
+    #[test]
+    fn test_clamp_after_shrinking() {
+        let mut e = editor(&"a fairly long line of text\n".repeat(40));
+        e.move_cursor_to(35 * 27 + 5);
+        assert!(e.top_line > 0);
+
+        for text in ["short\n", ""] {
+            e.buf.rope = Rope::from_str(text);
+            e.compute_virtual_lines();
+            e.clamp_state_to_buffer();
+            e.interface().unwrap();
+            e.redraw().unwrap();
+            assert!(e.buf_cursor <= e.buf.rope.len_chars());
+            assert!(e.cur_vline < e.virtual_lines.len());
+            assert!(e.top_line <= e.cur_vline);
+            assert_eq!(e.scr_cursor.x as usize, e.cur_vline - e.top_line);
+        }
+    }
+
+    #[test]
+    fn test_showmatch_highlights_closing_bracket() {
+        let mut e = editor("let x = f(a, (b));\nnext\n");
+        e.move_cursor_to(9);
+        let (idx, color) = e.bracket_highlight().unwrap();
+        assert_eq!(idx, 16);
+        assert_eq!(color, Color::DarkCyan);
+        let gutter = Editor::LINE_NUMBER_WIDTH as u16;
+        assert_eq!(e.screen_position(idx), Some((gutter + 16, 0)));
+
+        e.redraw().unwrap();
+        assert_eq!(e.highlighted_bracket, Some((16, Color::DarkCyan)));
+        e.move_cursor_to(2);
+        assert_eq!(e.bracket_highlight(), None);
+
+        e.settings.showmatch = false;
+        e.move_cursor_to(9);
+        assert_eq!(e.bracket_highlight(), None);
+
+        let mut e = editor("a)\n");
+        e.move_cursor_to(1);
+        assert_eq!(e.bracket_highlight(), Some((1, Color::Red)));
+
+        // past how far it looks, a missing match isn't flagged
+        let far = Editor::SHOWMATCH_LIMIT;
+        let mut e = editor(&format!("(\n{}\n", "x".repeat(far)));
+        assert_eq!(e.bracket_highlight(), None);
+        e.buf.rope.remove(2..12);
+        assert_eq!(e.bracket_highlight(), Some((0, Color::Red)));
+    }
+
+    #[test]
+    fn test_restore_position() {
+        let mut session = Session::default();
+        let mut e = editor("one\ntwo\nthree\n");
+        e.move_cursor_to(9);
+        e.save_positions(&mut session);
+
+        let mut e = editor("one\ntwo\nthree\n");
+        e.restore_position(&session, true);
+        assert_eq!(e.buf_cursor, 9);
+        assert_eq!(e.cur_line, 2);
+    }
+}
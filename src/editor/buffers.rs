@@ -0,0 +1,790 @@
+use color_eyre::{eyre::eyre, Result};
+use std::{fs, path::Path};
+
+use super::{Editor, Mode};
+use crate::{
+    editorconfig::Properties,
+    filetype::Filetype,
+    util::{Encoding, FileBuf},
+};
+
+/// Where the cursor and view were left in a buffer.
+#[derive(Debug, Default, Clone, Copy)]
+pub(super) struct View {
+    pub cursor: usize,
+    pub top_line: usize,
+}
+
+/// An open buffer. The contents of the active one live in `Editor::buf`,
+/// its slot only holds a placeholder until another buffer is switched to.
+#[derive(Default)]
+pub(super) struct Buffer {
+    pub buf: FileBuf,
+    pub view: View,
+}
+
+impl Editor {
+    pub fn open_buffer(&mut self, buf: FileBuf) {
+        self.buffers.push(Buffer {
+            buf,
+            view: View::default(),
+        });
+        self.switch_buffer(self.buffers.len() - 1);
+    }
+
+    /// Switches to the buffer for `path`, opening it if it isn't open yet.
+    pub(super) fn open_path(&mut self, path: &Path) -> Result<()> {
+        match self.find_buffer(path) {
+            Some(index) => self.switch_buffer(index),
+            None => self.open_buffer(FileBuf::new(path)?),
+        }
+        Ok(())
+    }
+
+    fn find_buffer(&self, path: &Path) -> Option<usize> {
+        let canonical = |p: &Path| fs::canonicalize(p).unwrap_or_else(|_| p.to_path_buf());
+        let path = canonical(path);
+        (0..self.buffers.len()).find(|&i| canonical(&self.buffer_at(i).path) == path)
+    }
+
+    pub fn switch_buffer(&mut self, index: usize) {
+        if index == self.active || index >= self.buffers.len() {
+            return;
+        }
+        if self.is_scratch() && self.buf.modified {
+            if let Err(e) = self.save_buffer(false) {
+                self.dbg = e.to_string();
+            }
+        }
+        self.alternate = Some(self.active);
+        self.activate_buffer(index);
+        self.load_view(self.buffers[index].view);
+    }
+
+    /// Which of the open buffers is active, like `[2/3] name`, when there is more than one.
+    pub(super) fn buffer_indicator(&self) -> Option<String> {
+        if self.buffers.len() < 2 {
+            return None;
+        }
+        let name = buffer_name(&self.buf);
+        Some(format!(
+            "[{}/{}] {name}",
+            self.active + 1,
+            self.buffers.len()
+        ))
+    }
+
+    /// Lists the open buffers in an overlay, marking the current one with `%`,
+    /// the alternate one with `#` and those with unsaved changes with `+`.
+    pub(super) fn list_buffers(&mut self) {
+        let lines = (0..self.buffers.len())
+            .map(|i| {
+                let buf = self.buffer_at(i);
+                let marker = match i {
+                    _ if i == self.active => '%',
+                    _ if Some(i) == self.alternate => '#',
+                    _ => ' ',
+                };
+                let modified = if buf.modified { '+' } else { ' ' };
+                format!("{:>3} {marker}{modified} \"{}\"", i + 1, buffer_name(buf))
+            })
+            .collect();
+        self.overlay = Some(lines);
+        self.redraw = true;
+    }
+
+    /// Switches to the buffer numbered `target` in `:ls`, or the only one whose name contains it.
+    pub(super) fn goto_buffer(&mut self, target: &str) -> Result<()> {
+        let target = target.trim();
+        let index = match target.parse::<usize>() {
+            Ok(number) => number
+                .checked_sub(1)
+                .filter(|&i| i < self.buffers.len())
+                .ok_or_else(|| eyre!("no buffer {number}"))?,
+            Err(_) => {
+                let mut matching = (0..self.buffers.len())
+                    .filter(|&i| buffer_name(self.buffer_at(i)).contains(target));
+                match (matching.next(), matching.next()) {
+                    (Some(index), None) => index,
+                    (None, _) => return Err(eyre!("no matching buffer for {target}")),
+                    (Some(_), Some(_)) => return Err(eyre!("more than one match for {target}")),
+                }
+            }
+        };
+        self.switch_buffer(index);
+        Ok(())
+    }
+
+    /// The buffer at `index`, whether it's the active one or parked.
+    fn buffer_at(&self, index: usize) -> &FileBuf {
+        if index == self.active {
+            &self.buf
+        } else {
+            &self.buffers[index].buf
+        }
+    }
+
+    /// Parks the active buffer and brings buffer `index` into `Editor::buf`.
+    pub(super) fn activate_buffer(&mut self, index: usize) {
+        let view = View {
+            cursor: self.buf_cursor,
+            top_line: self.top_line,
+        };
+        let parked = &mut self.buffers[self.active];
+        parked.view = view;
+        std::mem::swap(&mut self.buf, &mut parked.buf);
+
+        self.active = index;
+        std::mem::swap(&mut self.buf, &mut self.buffers[index].buf);
+        self.apply_buffer_settings();
+    }
+
+    /// Writes the active buffer to its file, returning the number of bytes written.
+    /// A read-only buffer is only written when `force` is set.
+    pub(super) fn save_buffer(&mut self, force: bool) -> Result<usize> {
+        if self.buf.readonly && !force {
+            return Err(eyre!(
+                "{} is read-only (add ! to override)",
+                self.buf.path.display()
+            ));
+        }
+        if !self.settings.keep_bom && self.buf.encoding == Encoding::Utf8Bom {
+            self.buf.encoding = Encoding::Utf8;
+        }
+        if let Some(ending) = self.settings.end_of_line {
+            self.buf.line_ending = ending;
+        }
+        if let Some(encoding) = self.settings.charset {
+            self.buf.encoding = encoding;
+        }
+        if self.settings.trim_trailing_whitespace {
+            self.trim_trailing_whitespace();
+        }
+        if self.buf.readonly {
+            self.buf.force_save()
+        } else {
+            self.buf.save()
+        }
+    }
+
+    /// Strips spaces and tabs from the end of every line, as an undoable edit.
+    fn trim_trailing_whitespace(&mut self) {
+        for line in (0..self.buf.rope.len_lines()).rev() {
+            let start = self.buf.rope.line_to_char(line);
+            let text = self.buf.rope.line(line).to_string();
+            let content = text.trim_end_matches(['\n', '\r']);
+            let kept = content.trim_end_matches([' ', '\t']);
+            let end = start + content.chars().count();
+            self.remove_text(start + kept.chars().count()..end);
+        }
+    }
+
+    /// Saves the active buffer and reports how much was written.
+    ///
+    /// Filetypes formatted on save go through their formatter first, and aren't written
+    /// if it fails. It runs once per write, so a formatter that never settles can't loop.
+    pub(super) fn write_buffer(&mut self, force: bool) -> Result<()> {
+        let filetype = Filetype::from_path(&self.buf.path);
+        let writable = !self.buf.readonly || force;
+        if writable
+            && self.format_on_save.contains(&filetype)
+            && self.formatters.contains_key(&filetype)
+        {
+            self.format_buffer(None)?;
+        }
+        let written = self.save_buffer(force)?;
+        self.dbg = format!("{}: written {written} bytes", self.buf.path.display());
+        Ok(())
+    }
+
+    /// `:w` with a file name: names a buffer that has none and saves it,
+    /// otherwise writes a copy there. Overwriting another file has to be forced.
+    pub(super) fn write_to(&mut self, path: &Path, force: bool) -> Result<()> {
+        if path.exists() && !force && self.find_buffer(path) != Some(self.active) {
+            return Err(eyre!("{} exists (add ! to override)", path.display()));
+        }
+        if self.buf.path.as_os_str().is_empty() {
+            self.buf.path = path.to_path_buf();
+            self.buf.editorconfig = Properties::for_file(path);
+            self.apply_buffer_settings();
+            return self.write_buffer(force);
+        }
+        let contents = self.buf.contents();
+        fs::write(path, &contents)?;
+        self.dbg = format!("{}: written {} bytes", path.display(), contents.len());
+        Ok(())
+    }
+
+    fn is_scratch(&self) -> bool {
+        self.scratch_path.as_ref() == Some(&self.buf.path)
+    }
+
+    /// Opens the quick-note buffer, or goes back to where it was opened from.
+    pub(super) fn toggle_scratch(&mut self) -> Result<()> {
+        let Some(path) = self.scratch_path.clone() else {
+            return Err(eyre!("no place to keep notes"));
+        };
+        if self.is_scratch() {
+            let back = self.before_scratch.take().unwrap_or(0);
+            self.switch_buffer(back.min(self.buffers.len() - 1));
+            return Ok(());
+        }
+        if !path.exists() {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(&path, "")?;
+        }
+        let from = self.active;
+        self.open_path(&path)?;
+        self.before_scratch = Some(from);
+        Ok(())
+    }
+
+    /// Quits, unless a buffer has unsaved changes and this isn't the second `q` in a row.
+    pub(super) fn quit(&mut self) -> Mode {
+        let unsaved = self.buf.modified || self.buffers.iter().any(|b| b.buf.modified);
+        if unsaved && !self.quit_requested {
+            self.quit_requested = true;
+            self.dbg = "unsaved changes, press q again to quit".into();
+            return Mode::Normal;
+        }
+        Mode::Quit
+    }
+
+    /// Quits from the `:` prompt, refusing while a buffer has unsaved changes unless forced.
+    pub(super) fn quit_command(&mut self, force: bool) -> Result<Mode> {
+        let unsaved = self.buf.modified || self.buffers.iter().any(|b| b.buf.modified);
+        if unsaved && !force {
+            return Err(eyre!("unsaved changes (add ! to override)"));
+        }
+        Ok(Mode::Quit)
+    }
+
+    /// Closes the active buffer, switching to the previous one.
+    /// Closing the last buffer quits.
+    pub(super) fn close_buffer(&mut self, force: bool) -> Result<Mode> {
+        if self.buf.modified && !force {
+            return Err(eyre!("buffer has unsaved changes (add ! to override)"));
+        }
+        if self.buffers.len() == 1 {
+            return Ok(Mode::Quit);
+        }
+        let closed = self.active;
+        let next = closed.saturating_sub(1).max(usize::from(closed == 0));
+        self.buf = std::mem::take(&mut self.buffers[next].buf);
+        let view = self.buffers[next].view;
+        self.buffers.remove(closed);
+        self.active = if next > closed { next - 1 } else { next };
+        self.alternate = match self.alternate {
+            Some(alternate) if alternate > closed => Some(alternate - 1),
+            Some(alternate) if alternate == closed || alternate == self.active => None,
+            alternate => alternate,
+        };
+        for pane in &mut self.panes.list {
+            if pane.buffer == closed {
+                pane.buffer = self.active;
+            } else if pane.buffer > closed {
+                pane.buffer -= 1;
+            }
+        }
+        self.load_view(view);
+        Ok(Mode::Normal)
+    }
+
+    /// Sets up the editor state for a buffer that just became active.
+    pub(super) fn load_view(&mut self, view: View) {
+        self.apply_buffer_settings();
+        self.compute_virtual_lines();
+        self.panes.invalidate(Some(self.active));
+        self.top_line = view
+            .top_line
+            .min(self.virtual_lines.len().saturating_sub(1));
+        self.move_cursor_to(view.cursor);
+        self.dbg = self.buf.path.display().to_string();
+        self.redraw = true;
+    }
+
+    /// Reacts to the active buffer's file changing on disk: a buffer without unsaved
+    /// changes is reloaded if `autoreload` is set, otherwise the change is only reported.
+    pub(super) fn check_disk_changes(&mut self) -> Result<()> {
+        let Some(mtime) = self.buf.changed_on_disk() else {
+            return Ok(());
+        };
+        // report each change once
+        self.buf.mtime = Some(mtime);
+        let path = self.buf.path.display().to_string();
+        if self.buf.modified || !self.settings.autoreload {
+            self.dbg = format!("{path} changed on disk");
+            return Ok(());
+        }
+        self.reload_buffer()?;
+        self.dbg = format!("reloaded {path}");
+        Ok(())
+    }
+
+    /// Reads the active buffer's file again. With `keepview` the cursor stays on its line
+    /// and the screen on its top line as far as the new contents allow,
+    /// otherwise both go back to the start.
+    pub(super) fn reload_buffer(&mut self) -> Result<()> {
+        let (top_line, cur_line) = (self.top_line, self.cur_line);
+        let column = self.buf_cursor - self.buf.rope.line_to_char(cur_line);
+        self.buf = FileBuf::open(&self.buf.path, !self.buf.replace_link)?;
+        self.apply_buffer_settings();
+        self.compute_virtual_lines();
+        self.panes.invalidate(Some(self.active));
+        if self.settings.keep_view {
+            let line = cur_line.min(self.buf.rope.len_lines() - 1);
+            let column = match self.settings.keep_column {
+                true => column.min(self.line_length(line)),
+                false => 0,
+            };
+            self.top_line = top_line;
+            self.buf_cursor = self.buf.rope.line_to_char(line) + column;
+        } else {
+            self.top_line = 0;
+            self.buf_cursor = 0;
+        }
+        self.clamp_state_to_buffer();
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Reads the active buffer's file again, `force` throws away unsaved changes.
+    pub(super) fn edit_again(&mut self, force: bool) -> Result<()> {
+        if self.buf.modified && !force {
+            return Err(eyre!("buffer has unsaved changes (add ! to override)"));
+        }
+        self.reload_buffer()?;
+        self.dbg = format!("reloaded {}", self.buf.path.display());
+        Ok(())
+    }
+
+    pub(super) fn next_buffer(&mut self) {
+        self.switch_buffer((self.active + 1) % self.buffers.len());
+    }
+
+    pub(super) fn prev_buffer(&mut self) {
+        self.switch_buffer((self.active + self.buffers.len() - 1) % self.buffers.len());
+    }
+}
+
+fn buffer_name(buf: &FileBuf) -> String {
+    match buf.path.as_os_str().is_empty() {
+        true => "[No Name]".into(),
+        false => buf.path.display().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Editor, Mode};
+    use crate::{
+        config::Config,
+        util::{FileBuf, TempDir},
+    };
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use ropey::Rope;
+    use std::{fs, time::SystemTime};
+
+    fn scratch(text: &str) -> FileBuf {
+        FileBuf {
+            rope: Rope::from_str(text),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_tab_cycles_buffers() {
+        let mut e = editor("first\nbuffer\n");
+        e.open_buffer(FileBuf {
+            rope: Rope::from_str("second\n"),
+            ..Default::default()
+        });
+        e.switch_buffer(0);
+        keys(&mut e, "sd");
+        assert_eq!(e.buf_cursor, 7);
+
+        press(&mut e, KeyCode::Tab);
+        assert_eq!(e.active, 1);
+        assert_eq!(e.buf.rope.to_string(), "second\n");
+        assert_eq!(e.buf_cursor, 0);
+
+        press(&mut e, KeyCode::Tab);
+        assert_eq!(e.active, 0);
+        assert_eq!(e.buf_cursor, 7);
+
+        press_with(&mut e, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!(e.active, 1);
+        assert_eq!(e.buffer_indicator().as_deref(), Some("[2/2] [No Name]"));
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        press(&mut e, KeyCode::Tab);
+        e.interface().unwrap();
+        assert!(out.take().contains("[1/2] test.txt (1:1)"));
+
+        // both keys can be bound to something else
+        let config =
+            Config::parse("[keys.normal]\ntab = \"prev_buffer\"\nbacktab = \"cursor_right\"\n");
+        e.configure(config).unwrap();
+        press_with(&mut e, KeyModifiers::SHIFT, KeyCode::BackTab);
+        assert_eq!((e.active, e.buf_cursor), (0, 8));
+        press(&mut e, KeyCode::Tab);
+        assert_eq!(e.active, 1);
+    }
+
+    #[test]
+    fn test_list_and_goto_buffers() {
+        let mut e = editor("first\n");
+        e.open_buffer(FileBuf {
+            path: "src/second.rs".into(),
+            ..Default::default()
+        });
+        e.open_buffer(scratch("third\n"));
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+
+        keys(&mut e, ":ls");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.overlay.as_deref().unwrap(),
+            [
+                "  1    \"test.txt\"",
+                "  2 #  \"src/second.rs\"",
+                "  3 %+ \"[No Name]\"",
+            ]
+        );
+        press(&mut e, KeyCode::Esc);
+
+        keys(&mut e, ":b 2");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.active, 1);
+        keys(&mut e, ":b test");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.active, 0);
+        assert_eq!(e.alternate, Some(1));
+        keys(&mut e, ":b 4");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "no buffer 4");
+        keys(&mut e, ":b s");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "more than one match for s");
+    }
+
+    #[test]
+    fn test_ctrl_n_toggles_scratch() {
+        let dir = TempDir::new("scratch");
+        let path = dir.0.join("notes").join("scratch.txt");
+        let mut e = editor("some\nwork\n");
+        e.scratch_path = Some(path.clone());
+        keys(&mut e, "sd");
+        assert_eq!(e.buf_cursor, 6);
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('n'));
+        assert_eq!(e.buf.path, path);
+        assert_eq!(e.buf.rope.len_chars(), 0);
+        keys(&mut e, "itodo");
+        press(&mut e, KeyCode::Esc);
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('n'));
+        assert_eq!(e.active, 0);
+        assert_eq!(e.buf.rope.to_string(), "some\nwork\n");
+        assert_eq!(e.buf_cursor, 6);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "todo");
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('n'));
+        assert_eq!(e.active, 1);
+        assert_eq!(e.buf.rope.to_string(), "todo");
+    }
+
+    #[test]
+    fn test_ctrl_s_writes_file() {
+        let dir = TempDir::new("write");
+        let path = dir.0.join("new").join("file.txt");
+        let mut e = editor("hello\n");
+        e.buf.path = path.clone();
+
+        keys(&mut e, "i¡");
+        press(&mut e, KeyCode::Esc);
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('s'));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "¡hello\n");
+        assert_eq!(e.dbg, format!("{}: written 8 bytes", path.display()));
+        assert!(!e.buf.modified);
+
+        e.buf.path.clear();
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('s'));
+        assert_eq!(e.dbg, "no file name");
+    }
+
+    #[test]
+    fn test_quit_with_unsaved_changes() {
+        let dir = TempDir::new("quit");
+        let mut e = editor("text\n");
+        e.buf.path = dir.0.join("file.txt");
+        keys(&mut e, "q");
+        assert_eq!(e.mode, Mode::Quit);
+
+        let mut e = editor("text\n");
+        e.buf.path = dir.0.join("file.txt");
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "q");
+        assert_eq!(e.mode, Mode::Normal);
+        assert_eq!(e.dbg, "unsaved changes, press q again to quit");
+        // anything in between asks again
+        keys(&mut e, "dq");
+        assert_eq!(e.mode, Mode::Normal);
+        keys(&mut e, "q");
+        assert_eq!(e.mode, Mode::Quit);
+
+        let mut e = editor("text\n");
+        e.buf.path = dir.0.join("file.txt");
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('s'));
+        keys(&mut e, "q");
+        assert_eq!(e.mode, Mode::Quit);
+    }
+
+    #[test]
+    fn test_format_on_save() {
+        let dir = TempDir::new("format-on-save");
+        let path = dir.0.join("notes.txt");
+        let mut e = editor("one\ntwo\n");
+        e.buf.path = path.clone();
+        let config = "[formatters]\ntext = \"tr a-z A-Z\"\n[format_on_save]\ntext = true\n";
+        e.configure(Config::parse(config)).unwrap();
+        e.move_cursor_to(5);
+        keys(&mut e, ":w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\nTWO\n");
+        assert_eq!(e.buf.rope.to_string(), "ONE\nTWO\n");
+        assert_eq!(e.buf_cursor, 5);
+        assert!(!e.buf.modified);
+
+        let config =
+            "[formatters]\ntext = \"echo broken >&2; exit 1\"\n[format_on_save]\ntext = true\n";
+        e.configure(Config::parse(config)).unwrap();
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "broken");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ONE\nTWO\n");
+        assert!(e.buf.modified);
+    }
+
+    #[test]
+    fn test_quit_commands() {
+        let dir = TempDir::new("quit-commands");
+        let path = dir.0.join("file.txt");
+        let mut e = editor("one\ntwo\nthree\n");
+        e.buf.path = path.clone();
+        keys(&mut e, ":3");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.cur_line, 2);
+
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":q");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Normal);
+        assert_eq!(e.dbg, "unsaved changes (add ! to override)");
+        keys(&mut e, ":frobnicate");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "unknown command: frobnicate");
+
+        keys(&mut e, ":wq");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Quit);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "one\ntwo\n!three\n");
+
+        let mut e = editor("text\n");
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":q!");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Quit);
+    }
+
+    #[test]
+    fn test_write_read_only_buffer() {
+        let dir = TempDir::new("readonly");
+        let path = dir.0.join("file.txt");
+        fs::write(&path, "old\n").unwrap();
+        let mut permissions = fs::metadata(&path).unwrap().permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(&path, permissions).unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        assert!(e.buf.readonly);
+
+        keys(&mut e, "inew ");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.dbg,
+            format!("{} is read-only (add ! to override)", path.display())
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "old\n");
+        assert!(e.buf.modified);
+
+        keys(&mut e, ":w!");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new old\n");
+        assert!(!e.buf.modified);
+        assert!(fs::metadata(&path).unwrap().permissions().readonly());
+    }
+
+    #[test]
+    fn test_reload_when_changed_on_disk() {
+        let dir = TempDir::new("reload");
+        let path = dir.0.join("file.txt");
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        keys(&mut e, "sd");
+        assert_eq!(e.buf_cursor, 5);
+
+        // pretend the file was read long ago, so it looks changed on disk
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        e.buf.mtime = Some(SystemTime::UNIX_EPOCH);
+        e.check_disk_changes().unwrap();
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\nthree\n");
+        assert_eq!(e.cur_line, 1);
+        assert_eq!(e.dbg, format!("reloaded {}", path.display()));
+
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        fs::write(&path, "gone\n").unwrap();
+        e.buf.mtime = Some(SystemTime::UNIX_EPOCH);
+        e.check_disk_changes().unwrap();
+        assert_eq!(e.buf.rope.to_string(), "one\nt!wo\nthree\n");
+        assert_eq!(e.dbg, format!("{} changed on disk", path.display()));
+
+        // the warning isn't repeated for the same change
+        e.dbg.clear();
+        e.check_disk_changes().unwrap();
+        assert!(e.dbg.is_empty());
+    }
+
+    #[test]
+    fn test_reload_keeps_view() {
+        let dir = TempDir::new("keepview");
+        let path = dir.0.join("file.txt");
+        let lines = |word| (0..30).map(|i| format!("{word} {i}\n")).collect::<String>();
+        fs::write(&path, lines("old")).unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        keys(&mut e, &"s".repeat(20));
+        keys(&mut e, "dd");
+        let (top_line, cur_line) = (e.top_line, e.cur_line);
+        assert!(top_line > 0);
+
+        fs::write(&path, lines("new")).unwrap();
+        keys(&mut e, ":e");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.current_line_text(), "new 20");
+        assert_eq!((e.top_line, e.cur_line), (top_line, cur_line));
+
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":e");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "buffer has unsaved changes (add ! to override)");
+
+        keys(&mut e, ":set nokeepview");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, ":e!");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), lines("new"));
+        assert_eq!((e.top_line, e.cur_line), (0, 0));
+    }
+
+    #[test]
+    fn test_write_unnamed_buffer_to_file() {
+        let dir = TempDir::new("write-to");
+        let path = dir.0.join("notes.txt");
+        let buf = FileBuf::new(&path).unwrap();
+        assert_eq!((buf.rope.len_chars(), &buf.path), (0, &path));
+
+        let mut e = editor_with(FileBuf::default());
+        keys(&mut e, "ihello");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "no file name");
+        keys(&mut e, &format!(":w {}", path.display()));
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello");
+        assert_eq!(e.buf.path, path);
+        assert!(!e.buf.modified);
+
+        // a named buffer writes a copy, and won't overwrite another file unforced
+        let copy = dir.0.join("copy.txt");
+        fs::write(&copy, "old").unwrap();
+        keys(&mut e, &format!(":w {}", copy.display()));
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.dbg,
+            format!("{} exists (add ! to override)", copy.display())
+        );
+        keys(&mut e, &format!(":w! {}", copy.display()));
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(fs::read_to_string(&copy).unwrap(), "hello");
+        assert_eq!(e.buf.path, path);
+    }
+
+    #[test]
+    fn test_reload_keeps_column() {
+        let dir = TempDir::new("keepcolumn");
+        let path = dir.0.join("file.txt");
+        fs::write(&path, "one\na line of text\n").unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        keys(&mut e, &format!("s{}", "d".repeat(9)));
+        assert_eq!(e.buf_cursor, 13);
+
+        let reload = |e: &mut Editor, text: &str| {
+            fs::write(&path, text).unwrap();
+            keys(e, ":e");
+            press(e, KeyCode::Enter);
+        };
+        reload(&mut e, "one\na longer line of text\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 13));
+        reload(&mut e, "one\nshort\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 9));
+
+        keys(&mut e, ":set nokeepcolumn");
+        press(&mut e, KeyCode::Enter);
+        reload(&mut e, "one\nshort line\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 4));
+    }
+
+    #[test]
+    fn test_bd_closes_buffer() {
+        let mut e = editor("first\n");
+        e.open_buffer(scratch("second\n"));
+        e.open_buffer(scratch("third\n"));
+        e.switch_buffer(1);
+
+        keys(&mut e, "i!");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":bd");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buffers.len(), 3);
+        assert_eq!(e.dbg, "buffer has unsaved changes (add ! to override)");
+
+        keys(&mut e, ":bd!");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buffers.len(), 2);
+        assert_eq!(e.active, 0);
+        assert_eq!(e.buf.rope.to_string(), "first\n");
+        press(&mut e, KeyCode::Tab);
+        assert_eq!(e.buf.rope.to_string(), "third\n");
+
+        keys(&mut e, ":bd");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, ":bd");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Quit);
+    }
+}
@@ -0,0 +1,1195 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use crossterm::style::Color;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+use super::{Editor, Mode};
+use crate::{
+    config::Config,
+    diff::{diff_lines, DiffLine},
+    editorconfig::Properties,
+    expr::{self, Context},
+    filetype::Filetype,
+    highlight::highlight_line,
+    text::{display_width, indentation, normalize, wrap_paragraphs, Normalization},
+    timer::{format_date, format_duration},
+    util::{pipe_through, set_log_level, Encoding, Sign},
+};
+
+impl Editor {
+    /// Runs a line entered in command mode.
+    pub(super) fn execute_command(&mut self, command: &str) -> Result<Mode> {
+        if command.trim() == "%" {
+            self.select_all();
+            return Ok(Mode::Visual);
+        }
+        let (lines, command) = self.parse_line_range(command.trim());
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        let mut mode = Mode::Normal;
+        let result = match name {
+            "" => Ok(()),
+            "b" | "buffer" => self.goto_buffer(args),
+            "ls" | "buffers" => {
+                self.list_buffers();
+                Ok(())
+            }
+            "bd" | "bd!" => self.close_buffer(name == "bd!").map(|m| mode = m),
+            "char" => self.insert_code_point(args),
+            "diff" => self.show_diff(),
+            "e" | "e!" if args.trim().is_empty() => self.edit_again(name == "e!"),
+            "e" => self.open_path(Path::new(args.trim())),
+            "Explore" => self.explore(),
+            "fmt" if args.trim().is_empty() => self.format_buffer(None),
+            "fmt!" => self.format_buffer(Some(args.trim())),
+            "goto" => self.goto(args),
+            "grep" => self.grep(args.trim()),
+            "indent" => self.reindent(args),
+            "longest" => {
+                self.jump_to_longest_line();
+                Ok(())
+            }
+            "w" | "w!" if args.trim().is_empty() => self.write_buffer(name == "w!"),
+            "w" | "w!" => self.write_to(Path::new(args.trim()), name == "w!"),
+            "unwrap" => self.hard_wrap(lines, usize::MAX),
+            "wrap" => match args.trim().parse() {
+                Ok(width) => self.hard_wrap(lines, width),
+                Err(_) => Err(eyre!("expected a width, got {args}")),
+            },
+            "surround" => self.surround_selection(args),
+            "yankpath" => self.yank_path(args),
+            "zen" => self.toggle_zen(),
+            "q" | "q!" => self.quit_command(name == "q!").map(|m| mode = m),
+            "wq" | "wq!" => self
+                .write_buffer(name == "wq!")
+                .and_then(|()| self.quit_command(name == "wq!"))
+                .map(|m| mode = m),
+            "normalize" => self.normalize_lines(lines, args),
+            "reverse" => self.reverse_lines(lines),
+            "ruler" => self.insert_ruler(args),
+            "transpose" => match args.trim() {
+                "" => {
+                    self.transpose_lines();
+                    Ok(())
+                }
+                "w" => {
+                    self.transpose_words();
+                    Ok(())
+                }
+                _ => Err(eyre!("expected w or nothing, got {args}")),
+            },
+            _ if !name.is_empty() && name.chars().all(|c| c.is_ascii_digit()) => name
+                .parse()
+                .map(|line| self.goto_line(line))
+                .map_err(Into::into),
+            _ if name.starts_with("s/") => self.substitute(lines, &command[1..]),
+            "uniq" | "uniq!" => self.unique_lines(lines, name == "uniq!"),
+            "set" => self.set_options(args),
+            "source" | "reloadconfig" => self.reload_config(),
+            "split" | "vsplit" => {
+                self.split_pane(name == "vsplit");
+                Ok(())
+            }
+            "synstack" => {
+                self.show_highlight_group();
+                Ok(())
+            }
+            "time" => {
+                let elapsed = self.editing_time.elapsed(Instant::now());
+                self.dbg = format!("editing for {}", format_duration(elapsed));
+                Ok(())
+            }
+            _ => {
+                self.dbg = format!("unknown command: {name}");
+                Ok(())
+            }
+        };
+        if let Err(e) = result {
+            self.dbg = e.to_string();
+        }
+        Ok(mode)
+    }
+
+    /// Runs the last command entered at the `:` prompt again.
+    pub(super) fn repeat_command(&mut self) -> Result<Mode> {
+        match self.last_command.clone() {
+            Some(command) => self.execute_command(&command),
+            None => {
+                self.dbg = "no previous command".into();
+                Ok(Mode::Normal)
+            }
+        }
+    }
+
+    /// Splits off a leading `%` (every line) or `'<,'>` (the lines of the last selection),
+    /// returning the 0-based lines it stands for.
+    fn parse_line_range<'c>(&self, command: &'c str) -> (Option<RangeInclusive<usize>>, &'c str) {
+        let rope = &self.buf.rope;
+        if let Some(rest) = command.strip_prefix('%') {
+            let last = rope.len_lines().saturating_sub(1);
+            // a trailing line break doesn't start another line
+            let last = if rope.line(last).len_chars() == 0 {
+                last.saturating_sub(1)
+            } else {
+                last
+            };
+            (Some(0..=last), rest)
+        } else if let Some(rest) = command.strip_prefix("'<,'>") {
+            let lines = self.marked_selection().map(|selection| {
+                rope.char_to_line(selection.start)
+                    ..=rope.char_to_line(selection.end.saturating_sub(1).max(selection.start))
+            });
+            (lines, rest)
+        } else {
+            (None, command)
+        }
+    }
+
+    /// Reverses the order of `lines`, or of the whole buffer. Line endings stay
+    /// where they are, so a missing final newline stays missing.
+    fn reverse_lines(&mut self, lines: Option<RangeInclusive<usize>>) -> Result<()> {
+        let lines = match lines {
+            Some(lines) => lines,
+            None => self.parse_line_range("%").0.expect("`%` is a line range"),
+        };
+        let rope = &self.buf.rope;
+        let start = rope.line_to_char(*lines.start());
+        let end = rope.line_to_char(*lines.end() + 1);
+        let text = rope.slice(start..end).to_string();
+        let (contents, endings): (Vec<_>, Vec<_>) = text
+            .split_inclusive('\n')
+            .map(|line| {
+                let content = line.trim_end_matches(['\n', '\r']);
+                (content, &line[content.len()..])
+            })
+            .unzip();
+        let reversed: String = contents
+            .iter()
+            .rev()
+            .zip(&endings)
+            .flat_map(|(content, ending)| [*content, *ending])
+            .collect();
+        self.remove_text(start..end);
+        self.insert_text(start, &reversed);
+        self.move_cursor_to(start);
+        Ok(())
+    }
+
+    /// Rewraps the paragraphs in `lines`, or the whole buffer, to `width` columns.
+    /// With no limit, each paragraph is joined into a single line.
+    fn hard_wrap(&mut self, lines: Option<RangeInclusive<usize>>, width: usize) -> Result<()> {
+        let lines = match lines {
+            Some(lines) => lines,
+            None => self.parse_line_range("%").0.expect("`%` is a line range"),
+        };
+        let rope = &self.buf.rope;
+        let start = rope.line_to_char(*lines.start());
+        let end = rope.line_to_char(*lines.end() + 1);
+        let text = rope.slice(start..end).to_string();
+        let wrapped = wrap_paragraphs(&text, width.max(1), self.buf.line_ending.as_str());
+        if wrapped != text {
+            self.remove_text(start..end);
+            self.insert_text(start, &wrapped);
+        }
+        self.move_cursor_to(start);
+        Ok(())
+    }
+
+    /// Brings accented letters in `lines`, or the whole buffer, into the `nfc` or `nfd` form.
+    fn normalize_lines(&mut self, lines: Option<RangeInclusive<usize>>, args: &str) -> Result<()> {
+        let form = match args.trim() {
+            "nfc" | "NFC" => Normalization::Composed,
+            "nfd" | "NFD" => Normalization::Decomposed,
+            _ => return Err(eyre!("expected nfc or nfd, got {args}")),
+        };
+        let lines = match lines {
+            Some(lines) => lines,
+            None => self.parse_line_range("%").0.expect("`%` is a line range"),
+        };
+        let rope = &self.buf.rope;
+        let start = rope.line_to_char(*lines.start());
+        let end = rope.line_to_char(*lines.end() + 1);
+        let text = rope.slice(start..end).to_string();
+        let normalized = normalize(&text, form);
+        if normalized == text {
+            self.dbg = "already normalized".into();
+            return Ok(());
+        }
+        self.remove_text(start..end);
+        self.insert_text(start, &normalized);
+        self.move_cursor_to(start);
+        Ok(())
+    }
+
+    /// Replaces the whole buffer with what `command`, or the formatter configured for
+    /// the buffer's filetype, prints when given it. The cursor stays on the same line
+    /// and column, and the view doesn't move.
+    pub(super) fn format_buffer(&mut self, command: Option<&str>) -> Result<()> {
+        let command = match command {
+            Some("") => return Err(eyre!("expected a command")),
+            Some(command) => command.to_string(),
+            None => {
+                let filetype = Filetype::from_path(&self.buf.path);
+                let formatter = self.formatters.get(&filetype);
+                formatter
+                    .cloned()
+                    .ok_or_else(|| eyre!("no formatter for {}", filetype.name()))?
+            }
+        };
+        let text = self.buf.rope.to_string();
+        let formatted = pipe_through(&command, &text)?;
+        if formatted == text {
+            self.dbg = "already formatted".into();
+            return Ok(());
+        }
+        let rope = &self.buf.rope;
+        let line = self.cur_line;
+        let column = self.buf_cursor - rope.line_to_char(line);
+        let top_line = self.top_line;
+        self.remove_text(0..rope.len_chars());
+        self.insert_text(0, &formatted);
+
+        let rope = &self.buf.rope;
+        let line = line.min(rope.len_lines() - 1);
+        let line_len = rope
+            .line(line)
+            .chars()
+            .take_while(|&c| c != '\n' && c != '\r')
+            .count();
+        let cursor = rope.line_to_char(line) + column.min(line_len);
+        self.top_line = top_line;
+        self.clamp_state_to_buffer();
+        self.move_cursor_to(cursor);
+        Ok(())
+    }
+
+    /// Removes lines repeating the one before them from `lines`, or from the whole buffer.
+    /// With `everywhere`, a line is removed when it appeared anywhere above.
+    fn unique_lines(
+        &mut self,
+        lines: Option<RangeInclusive<usize>>,
+        everywhere: bool,
+    ) -> Result<()> {
+        let lines = match lines {
+            Some(lines) => lines,
+            None => self.parse_line_range("%").0.expect("`%` is a line range"),
+        };
+        let rope = &self.buf.rope;
+        let content = |line: usize| {
+            let text = rope.line(line).to_string();
+            text.trim_end_matches(['\n', '\r']).to_string()
+        };
+        let mut seen = HashSet::new();
+        let mut previous = None;
+        let mut duplicates: Vec<Range<usize>> = Vec::new();
+        let mut removed = 0;
+        for line in lines.clone() {
+            let text = content(line);
+            if line == rope.len_lines() - 1 && text.is_empty() && line > 0 {
+                // the empty "line" after a final newline
+                break;
+            }
+            let duplicate = match everywhere {
+                true => !seen.insert(text.clone()),
+                false => previous.as_ref() == Some(&text),
+            };
+            if duplicate {
+                let start = rope.line_to_char(line);
+                let end = rope.line_to_char(line + 1);
+                match duplicates.last_mut() {
+                    Some(run) if run.end == start => run.end = end,
+                    _ => duplicates.push(start..end),
+                }
+                removed += 1;
+            }
+            previous = Some(text);
+        }
+        // a run reaching the end of a buffer without a final newline
+        // takes the line ending before it along instead
+        if let Some(run) = duplicates.last_mut() {
+            let last = rope.len_lines() - 1;
+            if run.end == rope.len_chars() && !content(last).is_empty() {
+                let before = rope.char_to_line(run.start) - 1;
+                let ending = rope.line(before).len_chars() - content(before).chars().count();
+                run.start -= ending;
+            }
+        }
+        for range in duplicates.into_iter().rev() {
+            self.remove_text(range);
+        }
+        self.dbg = format!("{removed} lines removed");
+        self.move_cursor_to(self.buf.rope.line_to_char(*lines.start()));
+        Ok(())
+    }
+
+    /// Replaces text in `lines`, or the cursor line, as given by `/pattern/replacement/flags`:
+    /// the first match on each line, or every one with the `g` flag.
+    /// An empty pattern reuses the last search.
+    fn substitute(&mut self, lines: Option<RangeInclusive<usize>>, args: &str) -> Result<()> {
+        let mut parts = args[1..].split('/');
+        let pattern = match parts.next().unwrap_or_default() {
+            "" => self
+                .last_search
+                .clone()
+                .ok_or_else(|| eyre!("no previous search"))?,
+            pattern => pattern.to_string(),
+        };
+        let replacement = parts
+            .next()
+            .ok_or_else(|| eyre!("usage: s/pattern/replacement/"))?;
+        let global = parts.next().unwrap_or_default().contains('g');
+        let lines = lines.unwrap_or(self.cur_line..=self.cur_line);
+
+        let mut count = 0;
+        for line in lines.clone().rev() {
+            let start = self.buf.rope.line_to_char(line);
+            let text = self.buf.rope.line(line).to_string();
+            let text = text.trim_end_matches(['\n', '\r']);
+            let matches = text.matches(pattern.as_str()).count();
+            if matches == 0 {
+                continue;
+            }
+            let replaced = match global {
+                true => text.replace(&pattern, replacement),
+                false => text.replacen(&pattern, replacement, 1),
+            };
+            count += if global { matches } else { 1 };
+            self.remove_text(start..start + text.chars().count());
+            self.insert_text(start, &replaced);
+        }
+        if count == 0 {
+            return Err(eyre!("pattern not found: {pattern}"));
+        }
+        self.last_search = Some(pattern);
+        self.dbg = format!("{count} substitutions");
+        self.move_cursor_to(self.buf.rope.line_to_char(*lines.start()));
+        Ok(())
+    }
+
+    /// Rewrites every line's indentation as `tabs` or `spaces` levels of the given size,
+    /// given as `<style> [size]`. The buffer's own indentation unit is taken to be a tab
+    /// or the shortest space indent, spaces left over stay for alignment.
+    fn reindent(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let expand_tab = match args.next() {
+            Some("tabs") => false,
+            Some("spaces") => true,
+            _ => return Err(eyre!("usage: indent tabs|spaces [size]")),
+        };
+        let size = match args.next() {
+            Some(size) => size
+                .parse()
+                .ok()
+                .filter(|&size| size > 0)
+                .ok_or_else(|| eyre!("invalid size: {size}"))?,
+            None => self.settings.tab_width,
+        };
+        let unit = match expand_tab {
+            true => " ".repeat(size),
+            false => "\t".to_string(),
+        };
+
+        let rope = &self.buf.rope;
+        let lines: Vec<String> = rope.lines().map(|l| l.to_string()).collect();
+        let spaces = |indent: &str| indent.chars().filter(|c| *c == ' ').count();
+        let old_unit = lines
+            .iter()
+            .map(|line| indentation(line))
+            .filter(|indent| !indent.contains('\t'))
+            .map(spaces)
+            .filter(|&n| n > 0)
+            .min()
+            .unwrap_or(self.settings.tab_width);
+
+        let mut changed = 0;
+        for (line, text) in lines.iter().enumerate().rev() {
+            let indent = indentation(text.trim_end_matches(['\n', '\r']));
+            let tabs = indent.chars().filter(|c| *c == '\t').count();
+            let levels = tabs + spaces(indent) / old_unit;
+            let new = unit.repeat(levels) + &" ".repeat(spaces(indent) % old_unit);
+            if new != indent {
+                let start = self.buf.rope.line_to_char(line);
+                self.remove_text(start..start + indent.chars().count());
+                self.insert_text(start, &new);
+                changed += 1;
+            }
+        }
+        let style = if expand_tab {
+            "expandtab"
+        } else {
+            "noexpandtab"
+        };
+        self.set_options(&format!("{style} tabstop={size}"))?;
+        self.dbg = format!("reindented {changed} lines");
+        Ok(())
+    }
+
+    /// Yanks where the buffer is: its path relative to the working directory,
+    /// or with `full`, `name` or `line` its absolute path, file name or `path:line`.
+    fn yank_path(&mut self, args: &str) -> Result<()> {
+        let path = &self.buf.path;
+        let full = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+        let relative = std::env::current_dir()
+            .and_then(fs::canonicalize)
+            .ok()
+            .and_then(|dir| full.strip_prefix(dir).ok().map(PathBuf::from))
+            .unwrap_or_else(|| path.clone());
+        let text = match args.trim() {
+            "" => relative.display().to_string(),
+            "full" => full.display().to_string(),
+            "name" => path
+                .file_name()
+                .ok_or_else(|| eyre!("buffer has no file name"))?
+                .to_string_lossy()
+                .into_owned(),
+            "line" => format!("{}:{}", relative.display(), self.cur_line + 1),
+            other => return Err(eyre!("usage: yankpath [full|name|line], not {other}")),
+        };
+        self.dbg = format!("yanked {text}");
+        self.yank_text(text, false);
+        Ok(())
+    }
+
+    /// Jumps to the line that takes up the most columns and reports its width.
+    fn jump_to_longest_line(&mut self) {
+        let tab_width = self.settings.tab_width;
+        let (line, width) = self
+            .buf
+            .rope
+            .lines()
+            .map(|line| display_width(line.chars(), tab_width))
+            .enumerate()
+            // the first of equally long lines
+            .max_by(|(a_line, a), (b_line, b)| a.cmp(b).then(b_line.cmp(a_line)))
+            .unwrap_or_default();
+        self.goto_line(line + 1);
+        self.dbg = format!("line {} is the longest, {width} columns", line + 1);
+    }
+
+    /// Inserts the value of an expression entered after `Ctrl+R =` at the cursor.
+    pub(super) fn insert_expression(&mut self, expression: &str) -> Result<Mode> {
+        let context = Context {
+            file: self.buf.path.display().to_string(),
+            line: self.cur_line + 1,
+            date: format_date(SystemTime::now()),
+        };
+        match expr::evaluate(expression, &context) {
+            Ok(value) => {
+                self.insert_text(self.buf_cursor, &value);
+                self.move_cursor_to(self.buf_cursor + value.chars().count());
+            }
+            Err(e) => self.dbg = e.to_string(),
+        }
+        Ok(Mode::Insert)
+    }
+
+    /// Jumps to a `path:line:column`, `line:column` or `line` location,
+    /// as found in compiler messages.
+    fn goto(&mut self, spec: &str) -> Result<()> {
+        let location = Location::parse(spec).ok_or_else(|| eyre!("invalid location: {spec}"))?;
+        if let Some(path) = &location.path {
+            self.open_path(path)?;
+        }
+        self.goto_line(location.line);
+        if let Some(column) = location.column {
+            let line = self.buf.rope.line(self.cur_line);
+            let len = line.len_chars() - usize::from(line.chars().last() == Some('\n'));
+            let column = (column.max(1) - 1).min(len);
+            self.move_cursor_to(self.buf.rope.line_to_char(self.cur_line) + column);
+        }
+        Ok(())
+    }
+
+    /// Inserts the character with the hex code point `hex` at the cursor.
+    fn insert_code_point(&mut self, hex: &str) -> Result<()> {
+        let hex = hex.trim();
+        let digits = hex
+            .strip_prefix("U+")
+            .or_else(|| hex.strip_prefix("u+"))
+            .unwrap_or(hex);
+        let ch = u32::from_str_radix(digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| eyre!("invalid code point: {hex}"))?;
+        self.insert_text(self.buf_cursor, ch.encode_utf8(&mut [0; 4]));
+        self.move_cursor_to(self.buf_cursor + 1);
+        Ok(())
+    }
+
+    /// Inserts a line of `width` times `ch`, `textwidth` dashes by default, above the cursor line
+    /// and keeps the cursor where it was in the text.
+    fn insert_ruler(&mut self, args: &str) -> Result<()> {
+        let mut args = args.split_whitespace();
+        let width = match args.next() {
+            Some(width) => width.parse().map_err(|_| eyre!("invalid width: {width}"))?,
+            None => self.settings.textwidth,
+        };
+        let ch = match args.next().map(|ch| (ch, ch.chars().collect::<Vec<_>>())) {
+            Some((_, chars)) if chars.len() == 1 => chars[0],
+            Some((ch, _)) => return Err(eyre!("expected a single char, got {ch}")),
+            None => '-',
+        };
+        let at = self.buf.rope.line_to_char(self.cur_line);
+        let ruler = format!(
+            "{}{}",
+            ch.to_string().repeat(width),
+            self.buf.line_ending.as_str()
+        );
+        let cursor = self.buf_cursor + ruler.chars().count();
+        self.insert_text(at, &ruler);
+        self.move_cursor_to(cursor);
+        Ok(())
+    }
+
+    /// Replaces the last selection with `template`, its `$0` standing for the selected text.
+    fn surround_selection(&mut self, template: &str) -> Result<()> {
+        if !template.contains("$0") {
+            return Err(eyre!("template has no $0: {template}"));
+        }
+        let selection = self
+            .marked_selection()
+            .ok_or_else(|| eyre!("no selection"))?;
+        let selected = self.buf.rope.slice(selection.clone()).to_string();
+        let wrapped = template.replacen("$0", &selected, 1);
+        self.remove_text(selection.clone());
+        self.insert_text(selection.start, &wrapped);
+        self.move_cursor_to(selection.start + wrapped.chars().count());
+        Ok(())
+    }
+
+    pub(super) fn set_options(&mut self, args: &str) -> Result<()> {
+        if args.trim().is_empty() {
+            self.overlay = Some(self.settings.describe());
+        }
+        for arg in args.split_whitespace() {
+            self.settings.set(arg)?;
+            self.overrides.push(arg.to_string());
+        }
+        set_log_level(self.settings.log_level);
+        self.compute_virtual_lines();
+        self.panes.invalidate(None);
+        self.clamp_state_to_buffer();
+        self.redraw = true;
+        if self.zen {
+            // the margins may have grown over drawn text
+            self.repaint_screen()?;
+        }
+        Ok(())
+    }
+
+    /// Reads the config file and every buffer's `.editorconfig` again, replacing
+    /// the options set so far. Problems in the config file are reported, not fatal.
+    fn reload_config(&mut self) -> Result<()> {
+        let config = match &self.config_path {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+        let files = self.buffers.iter_mut().map(|b| &mut b.buf);
+        for buf in files.chain([&mut self.buf]) {
+            if !buf.path.as_os_str().is_empty() {
+                buf.editorconfig = Properties::for_file(&buf.path);
+            }
+        }
+        self.overrides.clear();
+        self.dbg = "config reloaded".into();
+        self.configure(config)?;
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Reports the highlight group of the char under the cursor.
+    fn show_highlight_group(&mut self) {
+        let line = self.buf.rope.line(self.cur_line).to_string();
+        let column = self.buf_cursor - self.buf.rope.line_to_char(self.cur_line);
+        let group = highlight_line(Filetype::from_path(&self.buf.path), &line)
+            .into_iter()
+            .find(|(range, _)| range.contains(&column));
+        self.dbg = match group {
+            Some((_, group)) => group.name().into(),
+            None => "no highlight group".into(),
+        };
+    }
+
+    /// Shows the changes made since the file was last written in an overlay,
+    /// and marks the changed lines in the sign column.
+    fn show_diff(&mut self) -> Result<()> {
+        let (_, on_disk) = Encoding::decode(&fs::read(&self.buf.path)?)?;
+        let in_memory = self.buf.rope.to_string();
+        let old: Vec<_> = on_disk.split_inclusive('\n').collect();
+        let new: Vec<_> = in_memory.split_inclusive('\n').collect();
+        let diff = diff_lines(&old, &new);
+
+        self.buf.signs = diff_signs(&diff);
+        let (mut old_line, mut new_line) = (0, 0);
+        let mut overlay = Vec::new();
+        for line in diff {
+            match line {
+                DiffLine::Equal(_) => {
+                    old_line += 1;
+                    new_line += 1;
+                }
+                DiffLine::Added(text) => {
+                    new_line += 1;
+                    overlay.push(format!("+{new_line:>4} {}", text.trim_end_matches('\n')));
+                }
+                DiffLine::Removed(text) => {
+                    old_line += 1;
+                    overlay.push(format!("-{old_line:>4} {}", text.trim_end_matches('\n')));
+                }
+            }
+        }
+        if overlay.is_empty() {
+            self.dbg = "no changes".into();
+        } else {
+            self.overlay = Some(overlay);
+        }
+        self.compute_virtual_lines();
+        self.redraw = true;
+        Ok(())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Location {
+    path: Option<PathBuf>,
+    line: usize,
+    column: Option<usize>,
+}
+
+impl Location {
+    fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim().trim_end_matches(':');
+        let mut parts: Vec<_> = spec.split(':').collect();
+        let mut numbers = Vec::new();
+        while numbers.len() < 2 {
+            match parts.last().map(|p| p.trim().parse::<usize>()) {
+                Some(Ok(n)) => {
+                    numbers.push(n);
+                    parts.pop();
+                }
+                _ => break,
+            }
+        }
+        numbers.reverse();
+        let path = parts.join(":");
+        let path = (!path.is_empty()).then(|| PathBuf::from(path));
+        match numbers[..] {
+            [line] => Some(Self {
+                path,
+                line,
+                column: None,
+            }),
+            [line, column] => Some(Self {
+                path,
+                line,
+                column: Some(column),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Signs for the lines of the new text: `+` added, `-` lines removed above, `~` replaced.
+fn diff_signs(diff: &[DiffLine]) -> BTreeMap<usize, Sign> {
+    let new_len = diff
+        .iter()
+        .filter(|l| !matches!(l, DiffLine::Removed(_)))
+        .count();
+    let mut signs = BTreeMap::new();
+    let mut line = 0;
+    for op in diff {
+        match op {
+            DiffLine::Equal(_) => line += 1,
+            DiffLine::Added(_) => {
+                let sign = match signs.get(&line) {
+                    Some(Sign { symbol: '-', .. }) => Sign {
+                        symbol: '~',
+                        color: Color::Yellow,
+                        message: "changed".into(),
+                    },
+                    _ => Sign {
+                        symbol: '+',
+                        color: Color::Green,
+                        message: "added".into(),
+                    },
+                };
+                signs.insert(line, sign);
+                line += 1;
+            }
+            DiffLine::Removed(_) => {
+                signs
+                    .entry(line.min(new_len.saturating_sub(1)))
+                    .or_insert(Sign {
+                        symbol: '-',
+                        color: Color::Red,
+                        message: "lines removed above".into(),
+                    });
+            }
+        }
+    }
+    signs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::{super::Mode, Location};
+    use crate::{
+        config::Config,
+        util::{FileBuf, LineEnding, TempDir},
+    };
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use ropey::Rope;
+    use std::fs;
+
+    #[test]
+    fn test_diff_against_disk() {
+        let dir = TempDir::new("diff");
+        let path = dir.0.join("notes.txt");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+
+        keys(&mut e, "si");
+        keys(&mut e, "new");
+        press(&mut e, KeyCode::Enter);
+        press(&mut e, KeyCode::Esc);
+        e.buf.rope.remove(12..18);
+        assert_eq!(e.buf.rope.to_string(), "one\nnew\ntwo\n");
+        e.compute_virtual_lines();
+
+        keys(&mut e, ":diff");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.overlay,
+            Some(vec!["+   2 new".to_string(), "-   3 three".to_string()])
+        );
+        let signs: Vec<_> = e.buf.signs.iter().map(|(l, s)| (*l, s.symbol)).collect();
+        assert_eq!(signs, vec![(1, '+'), (2, '-')]);
+    }
+
+    #[test]
+    fn test_editorconfig_overrides_defaults() {
+        let dir = TempDir::new("editorconfig-buffer");
+        fs::write(dir.0.join(".editorconfig"), "[*.rs]\nindent_size = 2\n").unwrap();
+        let path = dir.0.join("main.rs");
+        fs::write(&path, "fn main() {}\n").unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        assert_eq!(e.settings.tab_width, 2);
+
+        let other = dir.0.join("notes.txt");
+        fs::write(&other, "").unwrap();
+        e.open_buffer(FileBuf::new(&other).unwrap());
+        assert_eq!(e.settings.tab_width, 4);
+
+        // an explicit `:set` wins over `.editorconfig`, in every buffer
+        keys(&mut e, ":set ts=8");
+        press(&mut e, KeyCode::Enter);
+        e.switch_buffer(0);
+        assert_eq!(e.settings.tab_width, 8);
+        e.switch_buffer(1);
+        assert_eq!(e.settings.tab_width, 8);
+
+        // the line ending and charset it asks for win over the ones the file had
+        fs::write(
+            dir.0.join(".editorconfig"),
+            "[*]\nend_of_line = lf\ncharset = utf-8-bom\ntrim_trailing_whitespace = true\n",
+        )
+        .unwrap();
+        let dos = dir.0.join("dos.txt");
+        fs::write(&dos, "a \t\r\nb\r\n").unwrap();
+        e.open_buffer(FileBuf::new(&dos).unwrap());
+        e.save_buffer(false).unwrap();
+        assert_eq!(fs::read(&dos).unwrap(), b"\xef\xbb\xbfa\nb\n");
+        assert_eq!(e.buf.rope.to_string(), "a\r\nb\r\n");
+    }
+
+    #[test]
+    fn test_synstack() {
+        let mut e = editor_with(FileBuf {
+            rope: Rope::from_str("let s = \"x\"; // done\n"),
+            path: "main.rs".into(),
+            ..Default::default()
+        });
+        let mut group_at = |column| {
+            e.move_cursor_to(column);
+            keys(&mut e, ":synstack");
+            press(&mut e, KeyCode::Enter);
+            e.dbg.clone()
+        };
+        assert_eq!(group_at(1), "keyword");
+        assert_eq!(group_at(4), "no highlight group");
+        assert_eq!(group_at(9), "string");
+        assert_eq!(group_at(16), "comment");
+    }
+
+    #[test]
+    fn test_reload_config() {
+        let dir = TempDir::new("reload-config");
+        let config = dir.0.join("config.toml");
+        let path = dir.0.join("notes.txt");
+        fs::write(&path, "text\n").unwrap();
+        let mut e = editor_with(FileBuf::new(&path).unwrap());
+        e.config_path = Some(config.clone());
+        assert_eq!(e.settings.textwidth, 79);
+
+        fs::write(&config, "textwidth = 60\n").unwrap();
+        fs::write(dir.0.join(".editorconfig"), "[*]\nindent_size = 2\n").unwrap();
+        keys(&mut e, ":source");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "config reloaded");
+        assert_eq!((e.settings.textwidth, e.settings.tab_width), (60, 2));
+
+        fs::write(&config, "textwidth = 50\nmaxwrap = lots\n").unwrap();
+        keys(&mut e, ":reloadconfig");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "config: expected a number, got lots");
+        assert_eq!(e.settings.textwidth, 50);
+    }
+
+    #[test]
+    fn test_char_inserts_code_point() {
+        let mut e = editor("caf\n");
+        e.move_cursor_to(3);
+        keys(&mut e, ":char 00e9");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "café\n");
+        assert_eq!(e.buf_cursor, 4);
+
+        keys(&mut e, ":char d800");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "invalid code point: d800");
+        assert_eq!(e.buf.rope.to_string(), "café\n");
+    }
+
+    #[test]
+    fn test_ruler_inserts_separator_line() {
+        let mut e = editor("text\n");
+        e.move_cursor_to(2);
+        keys(&mut e, ":ruler 10 =");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "==========\ntext\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 13));
+        assert!(e.buf.modified);
+
+        keys(&mut e, ":ruler");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.buf.rope.line(1).to_string(),
+            format!("{}\n", "-".repeat(79))
+        );
+
+        keys(&mut e, ":ruler 5 ab");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "expected a single char, got ab");
+    }
+
+    #[test]
+    fn test_surround_selection_with_template() {
+        let mut e = editor("let y = x;\n");
+        e.move_cursor_to(8);
+        keys(&mut e, "v:surround f($0)");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "let y = f(x);\n");
+        assert_eq!(e.buf_cursor, 12);
+
+        keys(&mut e, ":surround f()");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "template has no $0: f()");
+    }
+
+    #[test]
+    fn test_ctrl_r_inserts_expression() {
+        let mut e = editor("name: \n");
+        e.move_cursor_to(6);
+        keys(&mut e, "i");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        keys(&mut e, "=%");
+        assert_eq!(e.mode, Mode::Command);
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Insert);
+        assert_eq!(e.buf.rope.to_string(), "name: test.txt\n");
+        assert_eq!(e.buf_cursor, 14);
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        keys(&mut e, "=. * 10");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "name: test.txt10\n");
+    }
+
+    #[test]
+    fn test_hard_wrap_keeps_paragraphs() {
+        let first = "The quick brown fox jumps over the lazy dog and keeps on running.";
+        let second = "  A second paragraph, indented, that is also rather long.";
+        let mut e = editor(&format!("{first}\n\n{second}\n"));
+        keys(&mut e, ":wrap 30");
+        press(&mut e, KeyCode::Enter);
+        let text = e.buf.rope.to_string();
+        assert!(text.lines().all(|l| l.chars().count() <= 30));
+        assert_eq!(
+            text,
+            "The quick brown fox jumps over\nthe lazy dog and keeps on\nrunning.\n\n  \
+             A second paragraph,\n  indented, that is also\n  rather long.\n"
+        );
+        assert!(e.buf.modified);
+
+        keys(&mut e, ":wrap 80");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), format!("{first}\n\n{second}\n"));
+
+        let mut e = editor("one two\r\nthree\r\n\r\nfour\r\n");
+        e.buf.line_ending = LineEnding::CrLf;
+        keys(&mut e, ":wrap 20");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "one two three\r\n\r\nfour\r\n");
+
+        keys(&mut e, ":wrap wide");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "expected a width, got wide");
+    }
+
+    #[test]
+    fn test_unwrap_paragraphs() {
+        let mut e = editor("  one two\nthree\n   four\n\nfive\nsix\n");
+        keys(&mut e, ":unwrap");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "  one two three four\n\nfive six\n");
+        assert_eq!(e.virtual_lines.len(), 3);
+        assert!(e.buf.modified);
+    }
+
+    #[test]
+    fn test_reverse_lines() {
+        let mut e = editor("a\nb\nc\n");
+        keys(&mut e, ":reverse");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "c\nb\na\n");
+
+        let mut e = editor("x\na\nb\nc");
+        keys(&mut e, "svss:");
+        assert_eq!(e.command_buf, "'<,'>");
+        keys(&mut e, "reverse");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "x\nc\nb\na");
+        assert_eq!(e.cur_line, 1);
+    }
+
+    #[test]
+    fn test_normalize_nfc() {
+        let mut e = editor("caf\u{65}\u{301}\nnai\u{308}ve\n");
+        keys(&mut e, "v:");
+        keys(&mut e, "normalize nfc");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "café\nnai\u{308}ve\n");
+        assert!(e.buf.modified);
+
+        keys(&mut e, ":normalize nfc");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "café\nnaïve\n");
+        assert_eq!(e.buf.rope.len_chars(), 11);
+    }
+
+    #[test]
+    fn test_uniq() {
+        let mut e = editor("a\na\nb\na\nb\nb\nb");
+        keys(&mut e, ":uniq");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "a\nb\na\nb");
+        assert!(e.buf.modified);
+
+        keys(&mut e, ":uniq!");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "a\nb");
+
+        let mut e = editor("x\nx\ny\ny\nz\nz\n");
+        keys(&mut e, "ssvs:");
+        keys(&mut e, "uniq");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "x\nx\ny\nz\nz\n");
+    }
+
+    #[test]
+    fn test_substitute() {
+        let mut e = editor("a a\na\nb\n");
+        keys(&mut e, ":s/a/b/");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "b a\na\nb\n");
+
+        keys(&mut e, ":%s/a/xy/g");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "b xy\nxy\nb\n");
+        assert_eq!(e.dbg, "2 substitutions");
+
+        keys(&mut e, ":s/q/r/");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "pattern not found: q");
+    }
+
+    #[test]
+    fn test_repeat_last_command() {
+        let mut e = editor("aaa\n");
+        keys(&mut e, "@@");
+        assert_eq!(e.dbg, "nothing run with @ yet");
+        keys(&mut e, "@:");
+        assert_eq!(e.dbg, "no previous command");
+        keys(&mut e, ":s/a/b/");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "baa\n");
+        keys(&mut e, "@:");
+        assert_eq!(e.buf.rope.to_string(), "bba\n");
+        keys(&mut e, "@@");
+        assert_eq!(e.buf.rope.to_string(), "bbb\n");
+    }
+
+    #[test]
+    fn test_longest_line() {
+        let mut e = editor("short\nthe longest one\n日本語日本語日本語\n\tab\nthe longest two\n");
+        keys(&mut e, ":longest");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "line 3 is the longest, 18 columns");
+        assert_eq!(e.cur_line, 2);
+
+        let mut e = editor("\t\t\tx\nshort\n");
+        keys(&mut e, ":longest");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "line 1 is the longest, 13 columns");
+        assert_eq!(e.cur_line, 0);
+    }
+
+    #[test]
+    fn test_reindent() {
+        let mut e = editor("fn a() {\n  if x {\n    y\n   z\n  }\n}\n");
+        keys(&mut e, ":indent tabs");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.buf.rope.to_string(),
+            "fn a() {\n\tif x {\n\t\ty\n\t z\n\t}\n}\n"
+        );
+        assert_eq!(e.dbg, "reindented 4 lines");
+        assert!(!e.settings.expand_tab);
+
+        keys(&mut e, ":indent spaces 4");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(
+            e.buf.rope.to_string(),
+            "fn a() {\n    if x {\n        y\n     z\n    }\n}\n"
+        );
+        assert_eq!(e.settings.tab_width, 4);
+
+        // the options stay set for the buffer, like ones set with `:set`
+        e.open_buffer(FileBuf::default());
+        e.switch_buffer(0);
+        assert!(e.settings.expand_tab);
+        assert_eq!(e.settings.tab_width, 4);
+
+        keys(&mut e, ":indent spaces 0");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "invalid size: 0");
+        assert!(e.buf.rope.to_string().contains("        y\n"));
+    }
+
+    #[test]
+    fn test_yank_path() {
+        let mut e = editor_with(FileBuf {
+            rope: Rope::from_str(&"line\n".repeat(50)),
+            path: "src/foo.rs".into(),
+            ..Default::default()
+        });
+        e.goto_line(42);
+        keys(&mut e, ":yankpath line");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.registers[&'"'].text, "src/foo.rs:42");
+        keys(&mut e, ":yankpath name");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.registers[&'"'].text, "foo.rs");
+        keys(&mut e, ":yankpath");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.registers[&'"'].text, "src/foo.rs");
+    }
+
+    #[test]
+    fn test_format_buffer() {
+        let mut e = editor("one\n  two\nthree\n");
+        e.configure(Config::parse("[formatters]\ntext = \"cat\"\n"))
+            .unwrap();
+        e.move_cursor_to(6);
+        keys(&mut e, ":fmt");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "already formatted");
+        assert_eq!(e.buf.rope.to_string(), "one\n  two\nthree\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 6));
+
+        keys(&mut e, ":fmt! tr a-z A-Z");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "ONE\n  TWO\nTHREE\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 6));
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "one\n  two\nthree\n");
+
+        keys(&mut e, ":fmt! echo oops >&2; exit 1");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "oops");
+        assert_eq!(e.buf.rope.to_string(), "one\n  two\nthree\n");
+
+        e.buf.path = "main.rs".into();
+        keys(&mut e, ":fmt");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "no formatter for rust");
+    }
+
+    #[test]
+    fn test_parse_locations() {
+        assert_eq!(
+            Location::parse("src/file.rs:120:15"),
+            Some(Location {
+                path: Some("src/file.rs".into()),
+                line: 120,
+                column: Some(15),
+            })
+        );
+        assert_eq!(
+            Location::parse("120:15"),
+            Some(Location {
+                path: None,
+                line: 120,
+                column: Some(15),
+            })
+        );
+        assert_eq!(
+            Location::parse("120"),
+            Some(Location {
+                path: None,
+                line: 120,
+                column: None,
+            })
+        );
+        assert_eq!(Location::parse("src/file.rs"), None);
+        assert_eq!(Location::parse(""), None);
+    }
+
+    #[test]
+    fn test_goto_locations() {
+        let mut e = editor("one\ntwo\nthree\nfour\n");
+        keys(&mut e, ":goto 3:2");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.cur_line, e.buf_cursor), (2, 9));
+        keys(&mut e, ":goto 2");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 4));
+        keys(&mut e, ":goto nonsense");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "invalid location: nonsense");
+
+        let dir = TempDir::new("goto");
+        let path = dir.0.join("other.rs");
+        fs::write(&path, "fn a() {}\nfn b() {}\n").unwrap();
+        keys(&mut e, &format!(":goto {}:2:4:", path.display()));
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.active, 1);
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 13));
+    }
+}
@@ -0,0 +1,713 @@
+use std::ops::Range;
+
+use super::Editor;
+use crate::{
+    filetype::Filetype,
+    text::{comment_prefix, number_at, reflow},
+    textobject,
+    util::{char_class, prev_word_start, CharClass},
+};
+
+/// Brackets completed by `autopairs`.
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+impl Editor {
+    pub(super) fn undo(&mut self) {
+        let Some(group) = self.buf.history.undo() else {
+            self.dbg = "already at oldest change".into();
+            return;
+        };
+        for edit in group.iter().rev() {
+            self.apply_remove(edit.at..edit.at + edit.inserted.chars().count());
+            self.apply_insert(edit.at, &edit.removed);
+        }
+        self.move_cursor_to(group[0].at);
+    }
+
+    pub(super) fn redo(&mut self) {
+        let Some(group) = self.buf.history.redo() else {
+            self.dbg = "already at newest change".into();
+            return;
+        };
+        for edit in &group {
+            self.apply_remove(edit.at..edit.at + edit.removed.chars().count());
+            self.apply_insert(edit.at, &edit.inserted);
+        }
+        self.move_cursor_to(group[0].at);
+    }
+
+    /// One level of indentation, as inserted by Tab.
+    pub(super) fn indent_unit(&self) -> String {
+        match self.settings.expand_tab {
+            true => " ".repeat(self.settings.tab_width),
+            false => "\t".into(),
+        }
+    }
+
+    /// Types `ch` at the cursor. With `autopairs`, an opening bracket brings its closing
+    /// one along and typing a closing bracket steps over the one already there.
+    pub(super) fn insert_char(&mut self, ch: char) {
+        let at = self.buf_cursor;
+        let rope = &self.buf.rope;
+        if self.settings.autopairs {
+            if let Some((open, close)) = PAIRS.into_iter().find(|(open, _)| *open == ch) {
+                self.insert_text(at, &format!("{open}{close}"));
+                self.move_cursor_to(at + 1);
+                return;
+            }
+            if PAIRS.iter().any(|(_, close)| *close == ch) && rope.get_char(at) == Some(ch) {
+                self.move_cursor_to(at + 1);
+                return;
+            }
+        }
+        self.insert_text(at, ch.encode_utf8(&mut [0; 4]));
+        self.move_cursor_to(at + 1);
+    }
+
+    /// Breaks the line at the cursor. With `autoindent` the new line keeps the indentation,
+    /// and between an empty pair of brackets with `autopairs` too, the closing one goes
+    /// on its own line below an indented blank one.
+    pub(super) fn insert_newline(&mut self) {
+        let at = self.buf_cursor;
+        let rope = &self.buf.rope;
+        let indent: String = match self.settings.autoindent {
+            true => rope
+                .line(self.cur_line)
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect(),
+            false => String::new(),
+        };
+        let in_pair = at > 0
+            && PAIRS.iter().any(|&(open, close)| {
+                rope.get_char(at - 1) == Some(open) && rope.get_char(at) == Some(close)
+            });
+        let (text, cursor) = if in_pair && self.settings.autopairs && self.settings.autoindent {
+            let inner = indent.clone() + &self.indent_unit();
+            let cursor = at + 1 + inner.chars().count();
+            (format!("\n{inner}\n{indent}"), cursor)
+        } else {
+            let cursor = at + 1 + indent.chars().count();
+            (format!("\n{indent}"), cursor)
+        };
+        self.insert_text(at, &text);
+        self.move_cursor_to(cursor);
+    }
+
+    /// Opens a new line below the cursor line and puts the cursor on it,
+    /// indented like the cursor line with `autoindent`. The new line ends like the
+    /// cursor line, and below a last line without a line break both get one.
+    pub(super) fn open_line_below(&mut self) {
+        let rope = &self.buf.rope;
+        let length = self.line_length(self.cur_line);
+        let at = rope.line_to_char(self.cur_line) + length;
+        let line = rope.line(self.cur_line).to_string();
+        let indent: String = match self.settings.autoindent {
+            true => line
+                .chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect(),
+            false => String::new(),
+        };
+        let ending: String = line.chars().skip(length).collect();
+        let text = match ending.is_empty() {
+            true => format!("\n{indent}\n"),
+            false => format!("{ending}{indent}"),
+        };
+        self.insert_text(at, &text);
+        let ending = ending.chars().count().max(1);
+        self.move_cursor_to(at + ending + indent.chars().count());
+    }
+
+    /// Moves past the last char of the cursor line, however many rows it wraps to,
+    /// for `A` to append there.
+    pub(super) fn goto_logical_line_end(&mut self) {
+        let start = self.buf.rope.line_to_char(self.cur_line);
+        self.move_cursor_to(start + self.line_length(self.cur_line));
+    }
+
+    /// Moves to the first char of the cursor line that isn't a space or tab, for `I`.
+    pub(super) fn goto_first_non_blank(&mut self) {
+        let start = self.buf.rope.line_to_char(self.cur_line);
+        let indent = self
+            .buf
+            .rope
+            .line(self.cur_line)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        self.move_cursor_to(start + indent);
+    }
+
+    /// Deletes the char before the cursor. With `autoindent` a blank line loses all of
+    /// its indentation at once, with `autopairs` an empty pair of brackets goes as a whole.
+    pub(super) fn delete_char_backward(&mut self) {
+        let at = self.buf_cursor;
+        if at == 0 {
+            return;
+        }
+        let rope = &self.buf.rope;
+        let line_start = rope.line_to_char(self.cur_line);
+        let line = rope.line(self.cur_line).to_string();
+        let blank = line.trim_end_matches(['\n', '\r']);
+        let blank = !blank.is_empty() && blank.chars().all(|c| c == ' ' || c == '\t');
+        let before = rope.char(at - 1);
+        let start = if self.settings.autoindent && blank && at > line_start {
+            line_start
+        } else if before == '\n' && at >= 2 && rope.char(at - 2) == '\r' {
+            at - 2
+        } else {
+            at - 1
+        };
+        let end = match rope.get_char(at) {
+            Some(next) if self.settings.autopairs && PAIRS.contains(&(before, next)) => at + 1,
+            _ => at,
+        };
+        self.remove_text(start..end);
+        self.move_cursor_to(start);
+    }
+
+    /// Adds `amount` to the number under or after the cursor on its line.
+    pub(super) fn increment_number(&mut self, amount: i64) {
+        let start = self.buf.rope.line_to_char(self.cur_line);
+        let col = self.buf_cursor - start;
+        if let Some(end) = self.add_to_number(self.cur_line, col..usize::MAX, amount) {
+            self.move_cursor_to(start + end - 1);
+        }
+    }
+
+    /// Adds `amount` to the first number on each selected line, or with `accumulate`,
+    /// `amount` times the line's position in the selection, counting from one.
+    pub(super) fn increment_selection(&mut self, amount: i64, accumulate: bool) {
+        let Some(selection) = self.selection() else {
+            return;
+        };
+        let rope = &self.buf.rope;
+        let first = rope.char_to_line(selection.start);
+        let last = rope.char_to_line(selection.end.saturating_sub(1).max(selection.start));
+        let lines: Vec<_> = (first..=last)
+            .map(|line| {
+                let start = rope.line_to_char(line);
+                let from = selection.start.saturating_sub(start);
+                (line, from..selection.end - start)
+            })
+            .filter(|(line, cols)| {
+                let text = rope.line(*line).to_string();
+                number_at(&text, cols.start).is_some_and(|(range, _)| range.end <= cols.end)
+            })
+            .collect();
+        for (step, (line, cols)) in lines.into_iter().enumerate().rev() {
+            let step = if accumulate { step as i64 + 1 } else { 1 };
+            self.add_to_number(line, cols, amount.saturating_mul(step));
+        }
+        self.move_cursor_to(selection.start);
+        self.redraw = true;
+    }
+
+    /// Rewrites the first number of `line` starting within `cols`,
+    /// returning the column after it.
+    fn add_to_number(&mut self, line: usize, cols: Range<usize>, amount: i64) -> Option<usize> {
+        let text = self.buf.rope.line(line).to_string();
+        let (range, value) = number_at(&text, cols.start).filter(|(r, _)| r.end <= cols.end)?;
+        let start = self.buf.rope.line_to_char(line);
+        let new = value.saturating_add(amount).to_string();
+        self.remove_text(start + range.start..start + range.end);
+        self.insert_text(start + range.start, &new);
+        Some(range.start + new.chars().count())
+    }
+
+    /// Deletes the word before the cursor, or the line break when at the start of a line.
+    pub(super) fn delete_word_before(&mut self) {
+        let end = self.buf_cursor;
+        let rope = &self.buf.rope;
+        let start = match prev_word_start(rope, end) {
+            // a CRLF goes as a whole
+            start if start == end && end >= 2 && rope.slice(end - 2..end) == "\r\n" => end - 2,
+            start if start == end => end.saturating_sub(1),
+            start => start,
+        };
+        self.remove_text(start..end);
+        self.move_cursor_to(start);
+    }
+
+    /// Deletes the char under the cursor, joining the next line when it's a line break.
+    pub(super) fn delete_char_forward(&mut self) {
+        let at = self.buf_cursor;
+        let len = match self.buf.rope.get_char(at) {
+            None => return,
+            Some('\r') if self.buf.rope.get_char(at + 1) == Some('\n') => 2,
+            Some(_) => 1,
+        };
+        self.remove_text(at..at + len);
+        self.move_cursor_to(at);
+    }
+
+    /// Deletes back to the first non-blank of the line, or to its start when already there.
+    pub(super) fn delete_to_line_start(&mut self) {
+        let end = self.buf_cursor;
+        let line_start = self.buf.rope.line_to_char(self.cur_line);
+        let indent = self
+            .buf
+            .rope
+            .line(self.cur_line)
+            .chars()
+            .take_while(|c| *c == ' ' || *c == '\t')
+            .count();
+        let start = match line_start + indent {
+            first_non_blank if first_non_blank < end => first_non_blank,
+            _ => line_start,
+        };
+        self.remove_text(start..end);
+        self.move_cursor_to(start);
+    }
+
+    /// Rewraps the paragraph under the cursor to `textwidth`, keeping comment leaders.
+    pub(super) fn reflow_paragraph(&mut self) {
+        let leader = Filetype::from_path(&self.buf.path).comment_leader();
+        let rope = &self.buf.rope;
+        let text = |line: usize| rope.line(line).to_string();
+        let is_comment = |line: &str| leader.and_then(|l| comment_prefix(line, l)).is_some();
+        let is_blank = |line: &str| {
+            let line = line.trim();
+            line.is_empty() || Some(line) == leader
+        };
+
+        let current = text(self.cur_line);
+        if is_blank(&current) {
+            return;
+        }
+        let comment = is_comment(&current);
+        let belongs = |line: usize| {
+            let line = text(line);
+            !is_blank(&line) && is_comment(&line) == comment
+        };
+        let mut first = self.cur_line;
+        while first > 0 && belongs(first - 1) {
+            first -= 1;
+        }
+        let mut last = self.cur_line;
+        while last + 1 < rope.len_lines() && belongs(last + 1) {
+            last += 1;
+        }
+
+        let lines: Vec<_> = (first..=last).map(text).collect();
+        let lines: Vec<_> = lines
+            .iter()
+            .map(|l| l.trim_end_matches(['\n', '\r']))
+            .collect();
+        let ending = self.buf.line_ending.as_str();
+        let reflowed = reflow(&lines, self.settings.textwidth, leader).join(ending);
+        let start = rope.line_to_char(first);
+        let end = rope.line_to_char(last) + lines[lines.len() - 1].chars().count();
+
+        self.remove_text(start..end);
+        self.insert_text(start, &reflowed);
+        self.move_cursor_to(start);
+    }
+
+    /// Swaps the cursor line with the one above it, the cursor moves up along with its line.
+    /// Line endings stay where they are.
+    pub(super) fn transpose_lines(&mut self) {
+        let Some(above) = self.cur_line.checked_sub(1) else {
+            self.dbg = "no line above".into();
+            return;
+        };
+        let rope = &self.buf.rope;
+        let start = rope.line_to_char(above);
+        let middle = rope.line_to_char(self.cur_line);
+        let end = rope.line_to_char(self.cur_line + 1);
+        let column = self.buf_cursor - middle;
+        let upper = rope.slice(start..middle).to_string();
+        let lower = rope.slice(middle..end).to_string();
+        let split = |line: &str| line.len() - line.trim_end_matches(['\n', '\r']).len();
+        let (upper, upper_ending) = upper.split_at(upper.len() - split(&upper));
+        let (lower, lower_ending) = lower.split_at(lower.len() - split(&lower));
+        let swapped = [lower, upper_ending, upper, lower_ending].concat();
+
+        self.remove_text(start..end);
+        self.insert_text(start, &swapped);
+        self.move_cursor_to(start + column);
+    }
+
+    /// Swaps the word under the cursor with the next one on its line,
+    /// leaving the cursor at the start of the word it was on.
+    pub(super) fn transpose_words(&mut self) {
+        let rope = &self.buf.rope;
+        let is_word = |idx: usize| {
+            rope.get_char(idx)
+                .is_some_and(|c| char_class(c) == CharClass::Word)
+        };
+        let Some(first) =
+            textobject::word(rope, self.buf_cursor).filter(|_| is_word(self.buf_cursor))
+        else {
+            self.dbg = "no word under the cursor".into();
+            return;
+        };
+        let line_end = rope.line_to_char(self.cur_line) + rope.line(self.cur_line).len_chars();
+        let Some(second) = (first.end..line_end)
+            .find(|&idx| is_word(idx))
+            .and_then(|idx| textobject::word(rope, idx))
+        else {
+            self.dbg = "no word after the cursor".into();
+            return;
+        };
+        let text = |range: Range<usize>| rope.slice(range).to_string();
+        let (word, between, next) = (
+            text(first.clone()),
+            text(first.end..second.start),
+            text(second.clone()),
+        );
+        let moved_to = first.start + next.chars().count() + between.chars().count();
+
+        self.remove_text(first.start..second.end);
+        self.insert_text(first.start, &[next, between, word].concat());
+        self.move_cursor_to(moved_to);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Mode};
+    use crate::util::{FileBuf, LineEnding};
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use ropey::Rope;
+
+    #[test]
+    fn test_open_line_below() {
+        let mut e = editor("  one\r\ntwo");
+        keys(&mut e, ":set autoindent");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "o");
+        assert_eq!(e.mode, Mode::Insert);
+        keys(&mut e, "x");
+        assert_eq!(e.buf.rope.to_string(), "  one\r\n  x\r\ntwo");
+        assert_eq!(e.cur_line, 1);
+
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, ":set noautoindent");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "so");
+        keys(&mut e, "y");
+        assert_eq!(e.buf.rope.to_string(), "  one\r\n  x\r\ntwo\ny\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (3, 17));
+    }
+
+    #[test]
+    fn test_append_and_insert_at_line() {
+        let mut e = editor(&format!("  {}\r\n\n", "x".repeat(50)));
+        keys(&mut e, "A");
+        assert_eq!(e.mode, Mode::Insert);
+        keys(&mut e, "!");
+        assert_eq!(
+            e.buf.rope.line(0).to_string(),
+            format!("  {}!\r\n", "x".repeat(50))
+        );
+
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "I");
+        keys(&mut e, "?");
+        assert!(e.buf.rope.to_string().starts_with("  ?x"));
+
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "sssA");
+        assert_eq!((e.cur_line, e.mode), (1, Mode::Insert));
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "I");
+        keys(&mut e, "y");
+        assert!(e.buf.rope.to_string().ends_with("\r\ny\n"));
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let mut e = editor("text\n");
+        keys(&mut e, "iab");
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "dic");
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf.rope.to_string(), "abtcext\n");
+
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "abtext\n");
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "text\n");
+        keys(&mut e, "u");
+        assert_eq!(e.dbg, "already at oldest change");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        assert_eq!(e.buf.rope.to_string(), "abtext\n");
+
+        keys(&mut e, "ix");
+        press(&mut e, KeyCode::Esc);
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        assert_eq!(e.dbg, "already at newest change");
+    }
+
+    #[test]
+    fn test_undo_moves_cursor_to_change() {
+        let mut e = editor("one\ntwo\nthree\n");
+        keys(&mut e, "ssi");
+        press(&mut e, KeyCode::Enter);
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf.rope.len_lines(), 5);
+        keys(&mut e, "ww");
+        assert_eq!(e.cur_line, 1);
+
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\nthree\n");
+        assert_eq!(e.virtual_lines.len(), 3);
+        assert_eq!((e.buf_cursor, e.cur_line, e.cur_vline), (8, 2, 2));
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\n\nthree\n");
+        assert_eq!((e.cur_line, e.cur_vline), (2, 2));
+    }
+
+    #[test]
+    fn test_undolevels_drops_oldest_change() {
+        let mut e = editor("\n");
+        e.settings.undolevels = 2;
+        for ch in ["a", "b", "c"] {
+            keys(&mut e, &format!("i{ch}"));
+            press(&mut e, KeyCode::Esc);
+        }
+        assert_eq!(e.buf.rope.to_string(), "abc\n");
+        keys(&mut e, "uuu");
+        assert_eq!(e.buf.rope.to_string(), "a\n");
+        assert_eq!(e.dbg, "already at oldest change");
+    }
+
+    #[test]
+    fn test_ctrl_w_deletes_word() {
+        let mut e = editor("");
+        keys(&mut e, "ifoo bar");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        assert_eq!(e.buf.rope.to_string(), "foo ");
+        assert_eq!(e.buf_cursor, 4);
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        assert_eq!(e.buf.rope.to_string(), "");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        assert_eq!(e.buf.rope.to_string(), "");
+    }
+
+    #[test]
+    fn test_delete_removes_char_under_cursor() {
+        let mut e = editor("abc\r\ndef");
+        e.move_cursor_to(1);
+        keys(&mut e, "i");
+        press(&mut e, KeyCode::Delete);
+        assert_eq!(e.buf.rope.to_string(), "ac\r\ndef");
+        assert_eq!(e.buf_cursor, 1);
+
+        e.move_cursor_to(2);
+        press(&mut e, KeyCode::Delete);
+        assert_eq!(e.buf.rope.to_string(), "acdef");
+        e.move_cursor_to(5);
+        press(&mut e, KeyCode::Delete);
+        assert_eq!(e.buf.rope.to_string(), "acdef");
+        assert_eq!(e.buf_cursor, 5);
+    }
+
+    #[test]
+    fn test_backspace_joins_lines() {
+        let mut e = editor("ab\ncd\n");
+        keys(&mut e, "i");
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "ab\ncd\n");
+        assert_eq!(e.buf_cursor, 0);
+
+        e.move_cursor_to(3);
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "abcd\n");
+        assert_eq!(e.buf_cursor, 2);
+        assert_eq!(e.cur_line, 0);
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "acd\n");
+        assert_eq!(e.buf_cursor, 1);
+    }
+
+    #[test]
+    fn test_backspace_at_line_start_joins_crlf_lines() {
+        let mut e = editor("first\r\nsecond\r\nthird");
+        keys(&mut e, "si");
+        assert_eq!(e.buf_cursor, 7);
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "firstsecond\r\nthird");
+        assert_eq!(e.buf_cursor, 5);
+        assert_eq!((e.cur_line, e.cur_vline), (0, 0));
+        assert_eq!(e.virtual_lines.len(), 2);
+    }
+
+    #[test]
+    fn test_ctrl_u_deletes_to_indent_then_line_start() {
+        let mut e = editor("    foobar\n");
+        e.move_cursor_to(7);
+        keys(&mut e, "i");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('u'));
+        assert_eq!(e.buf.rope.to_string(), "    bar\n");
+        assert_eq!(e.buf_cursor, 4);
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('u'));
+        assert_eq!(e.buf.rope.to_string(), "bar\n");
+        assert_eq!(e.buf_cursor, 0);
+    }
+
+    #[test]
+    fn test_ctrl_w_at_line_start_joins_lines() {
+        let mut e = editor("foo\nbar\n");
+        keys(&mut e, "si");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        assert_eq!(e.buf.rope.to_string(), "foobar\n");
+        assert_eq!(e.buf_cursor, 3);
+
+        let mut e = editor("foo\r\nbar\r\n");
+        keys(&mut e, "si");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        assert_eq!(e.buf.rope.to_string(), "foobar\r\n");
+        assert_eq!(e.buf_cursor, 3);
+    }
+
+    #[test]
+    fn test_enter_between_braces_opens_indented_line() {
+        let mut e = editor("    if x\n");
+        e.settings.expand_tab = true;
+        e.settings.autoindent = true;
+        e.settings.autopairs = true;
+        keys(&mut e, "ddddddddi {");
+        assert_eq!(e.buf.rope.to_string(), "    if x {}\n");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "    if x {\n        \n    }\n");
+        assert_eq!((e.cur_line, e.scr_cursor.y), (1, 8));
+        keys(&mut e, "f(x)");
+        assert_eq!(e.buf.rope.to_string(), "    if x {\n        f(x)\n    }\n");
+    }
+
+    #[test]
+    fn test_backspace_removes_indentation_and_empty_pairs() {
+        let mut e = editor("    {\n        \n    }\n");
+        e.settings.autoindent = true;
+        e.settings.autopairs = true;
+        keys(&mut e, "s");
+        e.move_cursor_to(14);
+        keys(&mut e, "i");
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "    {\n\n    }\n");
+        assert_eq!(e.buf_cursor, 6);
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "    {\n    }\n");
+
+        keys(&mut e, "(");
+        assert_eq!(e.buf.rope.to_string(), "    {()\n    }\n");
+        press(&mut e, KeyCode::Backspace);
+        press(&mut e, KeyCode::Backspace);
+        assert_eq!(e.buf.rope.to_string(), "    \n    }\n");
+    }
+
+    #[test]
+    fn test_increment_numbers() {
+        let mut e = editor("x = 9, y = 1\n");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        assert_eq!(e.buf.rope.to_string(), "x = 10, y = 1\n");
+        assert_eq!(e.buf_cursor, 5);
+        keys(&mut e, "5");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('x'));
+        assert_eq!(e.buf.rope.to_string(), "x = 5, y = 1\n");
+        keys(&mut e, "20");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('x'));
+        assert_eq!(e.buf.rope.to_string(), "x = -15, y = 1\n");
+
+        // a count too long to fit stops growing
+        let mut e = editor("1\n");
+        keys(&mut e, &"9".repeat(24));
+        assert_eq!(e.count, Some(usize::MAX));
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        assert_eq!(e.buf.rope.to_string(), format!("{}\n", i64::MAX));
+    }
+
+    #[test]
+    fn test_increment_selected_numbers() {
+        let mut e = editor("0\n0\n0\n");
+        keys(&mut e, "vss");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        assert_eq!(e.buf.rope.to_string(), "1\n1\n1\n");
+
+        let mut e = editor("0\n0\n0\n");
+        keys(&mut e, "vssg");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        assert_eq!(e.buf.rope.to_string(), "1\n2\n3\n");
+        assert_eq!(e.buf_cursor, 0);
+
+        // numbers outside the selection stay as they are
+        let mut e = editor("a 5 b 5\nc 5\n");
+        keys(&mut e, "ddvdddd");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        assert_eq!(e.buf.rope.to_string(), "a 6 b 5\nc 5\n");
+
+        // huge counts and steps saturate
+        let mut e = editor("0\n0\n0\n");
+        keys(&mut e, "vss4611686018427387904g");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('a'));
+        let max = i64::MAX;
+        assert_eq!(
+            e.buf.rope.to_string(),
+            format!("4611686018427387904\n{max}\n{max}\n")
+        );
+        let mut e = editor("0\n");
+        keys(&mut e, "vs18446744073709551615");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('x'));
+        assert_eq!(e.buf.rope.to_string(), format!("-{max}\n"));
+    }
+
+    #[test]
+    fn test_transpose_lines() {
+        let mut e = editor("first\r\nsecond\nthird");
+        keys(&mut e, "ssdd:transpose");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "first\r\nthird\nsecond");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 9));
+
+        keys(&mut e, ":transpose");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "third\r\nfirst\nsecond");
+        assert_eq!(e.buf_cursor, 2);
+        keys(&mut e, ":transpose");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "no line above");
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "first\r\nthird\nsecond");
+    }
+
+    #[test]
+    fn test_transpose_words() {
+        let mut e = editor("let foo = bar;\nbaz\n");
+        keys(&mut e, "dddd:transpose w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "let bar = foo;\nbaz\n");
+        assert_eq!(e.buf_cursor, 10);
+
+        keys(&mut e, ":transpose w");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.dbg, "no word after the cursor");
+        assert_eq!(e.buf.rope.to_string(), "let bar = foo;\nbaz\n");
+    }
+
+    #[test]
+    fn test_gq_reflows_comment() {
+        let text = "fn main() {\n    // The quick brown fox jumps over the lazy dog and then\n    // runs away.\n    let x = 1;\n}\n";
+        let mut e = editor_with(FileBuf {
+            rope: Rope::from_str(text),
+            path: "main.rs".into(),
+            ..Default::default()
+        });
+        e.settings.textwidth = 40;
+        keys(&mut e, "ssgq");
+        assert_eq!(
+            e.buf.rope.to_string(),
+            "fn main() {\n    // The quick brown fox jumps over\n    // the lazy dog and then runs away.\n    let x = 1;\n}\n"
+        );
+        assert_eq!(e.cur_line, 1);
+
+        let mut e = editor("one two\r\nthree four\r\nfive\r\n");
+        e.buf.line_ending = LineEnding::CrLf;
+        e.settings.textwidth = 14;
+        keys(&mut e, "gq");
+        assert_eq!(e.buf.rope.to_string(), "one two three\r\nfour five\r\n");
+    }
+}
@@ -0,0 +1,143 @@
+use color_eyre::Result;
+use ropey::Rope;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{buffers::View, Editor};
+use crate::util::FileBuf;
+
+/// A read-only buffer listing the entries of `dir`, directories first and marked
+/// with a trailing `/`.
+fn listing(dir: &Path) -> Result<FileBuf> {
+    let mut dirs = Vec::new();
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match entry.file_type()?.is_dir() {
+            true => dirs.push(name + "/"),
+            false => files.push(name),
+        }
+    }
+    dirs.sort();
+    files.sort();
+    let text: String = dirs.into_iter().chain(files).map(|e| e + "\n").collect();
+    Ok(FileBuf {
+        rope: Rope::from_str(&text),
+        path: dir.to_path_buf(),
+        listing: true,
+        ..Default::default()
+    })
+}
+
+/// The directory a buffer's path is in, or the one it is for a listing.
+fn directory_of(buf: &FileBuf) -> PathBuf {
+    let dir = match buf.listing {
+        true => &buf.path,
+        false => buf.path.parent().unwrap_or(Path::new("")),
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf())
+}
+
+impl Editor {
+    /// Opens a listing of the directory the active file is in.
+    pub(super) fn explore(&mut self) -> Result<()> {
+        let dir = directory_of(&self.buf);
+        self.open_buffer(listing(&dir)?);
+        Ok(())
+    }
+
+    /// Goes up a level: from a file to its directory, from a listing to the parent's.
+    pub(super) fn explore_parent(&mut self) -> Result<()> {
+        if !self.buf.listing {
+            return self.explore();
+        }
+        let dir = directory_of(&self.buf);
+        let Some(parent) = dir.parent() else {
+            return Ok(());
+        };
+        self.show_listing(parent)?;
+        // land on the directory just left
+        let name = dir.file_name().map(|n| n.to_string_lossy() + "/");
+        let line = (0..self.buf.rope.len_lines())
+            .find(|&l| Some(self.buf.rope.line(l).to_string().trim_end().into()) == name);
+        if let Some(line) = line {
+            self.move_cursor_to(self.buf.rope.line_to_char(line));
+        }
+        Ok(())
+    }
+
+    /// Descends into the directory under the cursor of a listing, or opens the file.
+    pub(super) fn open_entry(&mut self) -> Result<()> {
+        if !self.buf.listing {
+            return Ok(());
+        }
+        let entry = self.current_line_text();
+        if entry.is_empty() {
+            return Ok(());
+        }
+        let path = self.buf.path.join(entry.trim_end_matches('/'));
+        match entry.ends_with('/') {
+            true => self.show_listing(&path),
+            false => self.open_path(&path),
+        }
+    }
+
+    /// Replaces the listing in the active buffer with the one for `dir`.
+    fn show_listing(&mut self, dir: &Path) -> Result<()> {
+        self.buf = listing(dir)?;
+        self.load_view(View::default());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use crate::util::{FileBuf, TempDir};
+    use crossterm::event::KeyCode;
+    use std::fs;
+
+    #[test]
+    fn test_explore_directories() {
+        let dir = TempDir::new("explore");
+        fs::create_dir(dir.0.join("sub")).unwrap();
+        fs::write(dir.0.join("sub/inner.txt"), "inner\n").unwrap();
+        fs::write(dir.0.join("b.txt"), "b\n").unwrap();
+        fs::write(dir.0.join("a.txt"), "a\n").unwrap();
+        let mut e = editor_with(FileBuf::new(dir.0.join("b.txt")).unwrap());
+
+        keys(&mut e, ":Explore");
+        press(&mut e, KeyCode::Enter);
+        assert!(e.buf.listing);
+        assert_eq!(e.buf.rope.to_string(), "sub/\na.txt\nb.txt\n");
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("\x1b[38;5;12msub/"));
+        assert!(screen.contains("\x1b[2;4Ha.txt"));
+
+        keys(&mut e, "ix");
+        assert_eq!(e.buf.rope.to_string(), "sub/\na.txt\nb.txt\n");
+        press(&mut e, KeyCode::Esc);
+
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf.rope.to_string(), "inner.txt\n");
+        keys(&mut e, "-");
+        assert_eq!(e.buf.rope.to_string(), "sub/\na.txt\nb.txt\n");
+        assert_eq!(e.cur_line, 0);
+
+        keys(&mut e, "s");
+        press(&mut e, KeyCode::Enter);
+        assert!(!e.buf.listing);
+        assert_eq!(e.buf.rope.to_string(), "a\n");
+        assert_eq!(e.buffers.len(), 3);
+    }
+}
@@ -0,0 +1,301 @@
+use color_eyre::{eyre::eyre, Result};
+
+use super::{Chord, Editor, Mode, Prompt, RedCmd};
+use crate::config::KeyBinding;
+
+/// A built-in command that key bindings can refer to by name.
+pub(super) struct Action {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Runs the command from the mode it's bound in.
+    pub run: fn(&mut Editor, Mode) -> Result<Mode>,
+}
+
+/// Every named command, used for the built-in key bindings, the config file's
+/// and the command palette.
+pub(super) const ACTIONS: &[Action] = &[
+    Action {
+        name: "cursor_left",
+        description: "move the cursor left",
+        run: |e, mode| {
+            e.cursor_left();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "cursor_right",
+        description: "move the cursor right",
+        run: |e, mode| {
+            e.cursor_right();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "cursor_up",
+        description: "move the cursor up",
+        run: |e, mode| {
+            e.cursor_up();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "cursor_down",
+        description: "move the cursor down",
+        run: |e, mode| {
+            e.cursor_down();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "line_start",
+        description: "go to the start of the line",
+        run: |e, mode| {
+            e.line_start();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "line_end",
+        description: "go to the end of the line",
+        run: |e, mode| {
+            e.line_end();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "page_up",
+        description: "scroll up a page",
+        run: |e, mode| {
+            e.page_up();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "page_down",
+        description: "scroll down a page",
+        run: |e, mode| {
+            e.page_down();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "next_word",
+        description: "go to the next word",
+        run: |e, mode| {
+            e.next_word();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "prev_word",
+        description: "go to the previous word",
+        run: |e, mode| {
+            e.prev_word();
+            Ok(mode)
+        },
+    },
+    Action {
+        name: "insert_mode",
+        description: "start inserting text",
+        run: |_, _| Ok(Mode::Insert),
+    },
+    Action {
+        name: "normal_mode",
+        description: "go back to Normal mode",
+        run: |e, _| {
+            e.redraw = true;
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "visual_mode",
+        description: "start a selection",
+        run: |e, _| {
+            e.enter_visual();
+            Ok(Mode::Visual)
+        },
+    },
+    Action {
+        name: "command_mode",
+        description: "open the : prompt",
+        run: |e, _| Ok(e.start_command("")),
+    },
+    Action {
+        name: "search",
+        description: "search forwards",
+        run: |e, _| Ok(e.start_search()),
+    },
+    Action {
+        name: "undo",
+        description: "undo the last change",
+        run: |e, _| {
+            e.undo();
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "redo",
+        description: "redo the last undone change",
+        run: |e, _| {
+            e.redo();
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "paste",
+        description: "paste after the cursor",
+        run: |e, _| {
+            e.paste(false);
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "next_buffer",
+        description: "switch to the next buffer",
+        run: |e, _| {
+            e.next_buffer();
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "prev_buffer",
+        description: "switch to the previous buffer",
+        run: |e, _| {
+            e.prev_buffer();
+            Ok(Mode::Normal)
+        },
+    },
+    Action {
+        name: "quit",
+        description: "quit red",
+        run: |e, _| Ok(e.quit()),
+    },
+    // the `:` commands, for the palette and key bindings
+    Action {
+        name: "write",
+        description: "write the buffer to its file",
+        run: |e, _| e.execute_command("w"),
+    },
+    Action {
+        name: "split",
+        description: "split the pane horizontally",
+        run: |e, _| e.execute_command("split"),
+    },
+    Action {
+        name: "vsplit",
+        description: "split the pane vertically",
+        run: |e, _| e.execute_command("vsplit"),
+    },
+    Action {
+        name: "grep",
+        description: "search files for a pattern",
+        run: |e, _| Ok(e.start_command("grep ")),
+    },
+    Action {
+        name: "set",
+        description: "change an option",
+        run: |e, _| Ok(e.start_command("set ")),
+    },
+    Action {
+        name: "buffers",
+        description: "list the open buffers",
+        run: |e, _| e.execute_command("ls"),
+    },
+    Action {
+        name: "diff",
+        description: "show the unsaved changes",
+        run: |e, _| e.execute_command("diff"),
+    },
+    Action {
+        name: "explore",
+        description: "list the files next to this one",
+        run: |e, _| e.execute_command("Explore"),
+    },
+    Action {
+        name: "format",
+        description: "run the formatter over the buffer",
+        run: |e, _| e.execute_command("fmt"),
+    },
+    Action {
+        name: "zen",
+        description: "toggle zen mode",
+        run: |e, _| e.execute_command("zen"),
+    },
+    Action {
+        name: "reload_config",
+        description: "read the config file again",
+        run: |e, _| e.execute_command("reloadconfig"),
+    },
+    Action {
+        name: "synstack",
+        description: "show how the text under the cursor is colored",
+        run: |e, _| e.execute_command("synstack"),
+    },
+    Action {
+        name: "editing_time",
+        description: "show how long red has been open",
+        run: |e, _| e.execute_command("time"),
+    },
+];
+
+/// The built-in command called `name` in the config file, run from `mode`.
+/// A name starting with `:` runs the rest as if entered at the `:` prompt.
+pub(super) fn named_command(name: &str, mode: Mode) -> Option<RedCmd> {
+    if let Some(command) = name.strip_prefix(':') {
+        let command = command.to_string();
+        return Some(RedCmd(Box::new(move |e| e.execute_command(&command))));
+    }
+    let run = ACTIONS.iter().find(|action| action.name == name)?.run;
+    Some(RedCmd(Box::new(move |e| run(e, mode))))
+}
+
+impl Editor {
+    /// Opens the `:` prompt with `text` already typed.
+    fn start_command(&mut self, text: &str) -> Mode {
+        self.command_buf = text.into();
+        self.prompt = Prompt::Command;
+        Mode::Command
+    }
+
+    /// Adds the key bindings from the config file, replacing built-in ones for the same keys.
+    pub(super) fn bind_keys(&mut self, keys: &[KeyBinding]) -> Result<()> {
+        for binding in keys {
+            let command = named_command(&binding.command, binding.mode)
+                .ok_or_else(|| eyre!("unknown command in key binding: {}", binding.command))?;
+            let chord = Chord(binding.mode, binding.keys.clone());
+            self.bindings.insert(chord, command);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::ACTIONS;
+    use crate::config::Config;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_keys_from_config() {
+        let mut e = editor("one\ntwo\n");
+        let config = Config::parse(
+            "[keys.normal]\nl = \"cursor_right\"\nj = \"cursor_down\"\n\"g w\" = \":set showtimer\"\n",
+        );
+        e.configure(config).unwrap();
+        keys(&mut e, "lj");
+        assert_eq!(e.buf_cursor, 5);
+        // the built-in keys still work
+        keys(&mut e, "a");
+        assert_eq!(e.buf_cursor, 4);
+        keys(&mut e, "gw");
+        assert!(e.settings.show_timer);
+
+        let config = Config::parse("[keys.visual]\nx = \"explode\"\n");
+        let err = e.configure(config).unwrap_err();
+        assert_eq!(err.to_string(), "unknown command in key binding: explode");
+
+        let names: HashSet<_> = ACTIONS.iter().map(|action| action.name).collect();
+        assert_eq!(names.len(), ACTIONS.len());
+    }
+}
@@ -0,0 +1,96 @@
+use color_eyre::Result;
+
+use super::{
+    keymap::{named_command, ACTIONS},
+    Editor, Mode, Prompt,
+};
+use crate::text::fuzzy_score;
+
+/// The commands matching `query`, best match first.
+fn palette_matches(query: &str) -> Vec<(&'static str, &'static str)> {
+    let mut matches: Vec<_> = ACTIONS
+        .iter()
+        .filter_map(|action| {
+            let (name, description) = (action.name, action.description);
+            let score = fuzzy_score(query, name).max(fuzzy_score(query, description))?;
+            Some((score, name, description))
+        })
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(b.1)));
+    matches.into_iter().map(|(_, n, d)| (n, d)).collect()
+}
+
+impl Editor {
+    /// Opens the `>` prompt, listing every command narrowed down as the query is typed.
+    pub(super) fn open_palette(&mut self) -> Mode {
+        self.command_buf.clear();
+        self.prompt = Prompt::Palette;
+        self.filter_palette();
+        Mode::Command
+    }
+
+    /// Lists the commands matching what was typed at the palette prompt.
+    pub(super) fn filter_palette(&mut self) {
+        let matches = palette_matches(&self.command_buf);
+        let lines = matches
+            .iter()
+            .map(|(name, description)| format!("{name:<14} {description}"));
+        self.overlay = Some(lines.collect());
+        self.redraw = true;
+    }
+
+    /// Runs the best match for `query`.
+    pub(super) fn run_palette(&mut self, query: &str) -> Result<Mode> {
+        self.redraw = true;
+        let Some(&(name, _)) = palette_matches(query).first() else {
+            self.dbg = format!("no command matches {query}");
+            return Ok(Mode::Normal);
+        };
+        match named_command(name, Mode::Normal) {
+            Some(command) => command.execute(self),
+            None => Ok(Mode::Normal),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Mode};
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_palette_ranks_matches() {
+        let names = |query| {
+            palette_matches(query)
+                .into_iter()
+                .map(|(n, _)| n)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names("pbuf"), ["prev_buffer", "buffers"]);
+        assert_eq!(names("up"), ["page_up", "cursor_up"]);
+        assert_eq!(names("undo")[0], "undo");
+        assert!(names("zzz").is_empty());
+        assert_eq!(names("").len(), ACTIONS.len());
+        assert_eq!(names("vsplit")[0], "vsplit");
+
+        let mut e = editor("one two\n");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('p'));
+        assert_eq!(e.mode, Mode::Command);
+        keys(&mut e, "nxtwrd");
+        assert_eq!(
+            e.overlay.as_ref().unwrap()[0],
+            "next_word      go to the next word"
+        );
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.mode, e.buf_cursor), (Mode::Normal, 4));
+        assert!(e.overlay.is_none());
+
+        // commands that take arguments leave them to be typed at the `:` prompt
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('p'));
+        keys(&mut e, "grep");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.mode, e.prompt), (Mode::Command, Prompt::Command));
+        assert_eq!(e.command_buf, "grep ");
+    }
+}
@@ -0,0 +1,519 @@
+use color_eyre::{eyre::eyre, Result};
+use crossterm::{cursor::MoveTo, style::Print, QueueableCommand};
+
+use super::{buffers::View, render::RenderCache, Cursor, Editor, Mode, VirtualLine};
+
+/// A part of the screen, in terminal cells.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// How the screen is divided between panes.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Layout {
+    /// Index into `Panes::list`.
+    Pane(usize),
+    /// Children side by side when `vertical`, stacked otherwise.
+    Split {
+        vertical: bool,
+        children: Vec<Layout>,
+    },
+}
+
+impl Layout {
+    /// Divides `rect` between the panes, leaving a row or column between neighbours
+    /// for a separator.
+    fn arrange(&self, rect: Rect, panes: &mut [Pane], separators: &mut Vec<Rect>) {
+        let (vertical, children) = match self {
+            Self::Pane(index) => {
+                panes[*index].rect = rect;
+                return;
+            }
+            Self::Split { vertical, children } => (*vertical, children),
+        };
+        let count = children.len() as u16;
+        let total = if vertical { rect.width } else { rect.height };
+        let share = total.saturating_sub(count - 1) / count;
+        let mut offset = 0;
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let size = if last {
+                total.saturating_sub(offset)
+            } else {
+                share
+            };
+            let (part, separator) = if vertical {
+                let part = Rect {
+                    x: rect.x + offset,
+                    width: size,
+                    ..rect
+                };
+                (
+                    part,
+                    Rect {
+                        x: part.x + size,
+                        width: 1,
+                        ..rect
+                    },
+                )
+            } else {
+                let part = Rect {
+                    y: rect.y + offset,
+                    height: size,
+                    ..rect
+                };
+                (
+                    part,
+                    Rect {
+                        y: part.y + size,
+                        height: 1,
+                        ..rect
+                    },
+                )
+            };
+            child.arrange(part, panes, separators);
+            if !last {
+                separators.push(separator);
+            }
+            offset += size + 1;
+        }
+    }
+
+    /// Puts `new` next to the pane `at`, in a split of the given direction.
+    fn split(&mut self, at: usize, new: usize, direction: bool) -> bool {
+        match self {
+            Self::Pane(index) if *index == at => {
+                let children = vec![Self::Pane(at), Self::Pane(new)];
+                *self = Self::Split {
+                    vertical: direction,
+                    children,
+                };
+                true
+            }
+            Self::Pane(_) => false,
+            Self::Split { vertical, children } => {
+                let position = children.iter().position(|c| *c == Self::Pane(at));
+                match position {
+                    Some(i) if *vertical == direction => {
+                        children.insert(i + 1, Self::Pane(new));
+                        true
+                    }
+                    _ => children.iter_mut().any(|c| c.split(at, new, direction)),
+                }
+            }
+        }
+    }
+
+    /// Takes the pane `index` out, renumbering the ones after it.
+    fn remove(&mut self, index: usize) {
+        let Self::Split { children, .. } = self else {
+            return;
+        };
+        children.retain(|c| *c != Self::Pane(index));
+        for child in children.iter_mut() {
+            match child {
+                Self::Pane(i) if *i > index => *i -= 1,
+                Self::Pane(_) => (),
+                split => split.remove(index),
+            }
+        }
+        if let [only] = children.as_mut_slice() {
+            let only = std::mem::replace(only, Self::Pane(0));
+            *self = only;
+        }
+    }
+}
+
+/// A view onto a buffer. The focused one's state lives in the editor,
+/// its slot holds whatever it had when focus last moved away.
+pub(super) struct Pane {
+    pub buffer: usize,
+    pub view: View,
+    pub rect: Rect,
+    pub cache: RenderCache,
+    pub placement: Placement,
+}
+
+/// Where a parked pane's buffer and cursor went on screen, so it can be drawn
+/// again without wrapping the whole buffer. Empty once it's out of date.
+#[derive(Default)]
+pub(super) struct Placement {
+    virtual_lines: Vec<VirtualLine>,
+    cur_vline: usize,
+    cur_line: usize,
+    scr_cursor: Cursor,
+}
+
+pub(super) struct Panes {
+    pub list: Vec<Pane>,
+    pub layout: Layout,
+    pub focused: usize,
+    pub separators: Vec<Rect>,
+}
+
+impl Panes {
+    pub fn new(rect: Rect) -> Self {
+        Self {
+            list: vec![Pane {
+                buffer: 0,
+                view: View::default(),
+                rect,
+                cache: RenderCache::default(),
+                placement: Placement::default(),
+            }],
+            layout: Layout::Pane(0),
+            focused: 0,
+            separators: Vec::new(),
+        }
+    }
+
+    /// Drops the placements of the panes showing `buffer`, or of all of them.
+    pub fn invalidate(&mut self, buffer: Option<usize>) {
+        let stale = self
+            .list
+            .iter_mut()
+            .filter(|pane| buffer.is_none_or(|b| b == pane.buffer));
+        for pane in stale {
+            pane.placement.virtual_lines.clear();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(super) enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+impl Editor {
+    /// Splits the focused pane in two showing the same buffer, and focuses the new half.
+    pub(super) fn split_pane(&mut self, vertical: bool) {
+        let new = self.panes.list.len();
+        self.panes.list.push(Pane {
+            buffer: self.active,
+            view: View {
+                cursor: self.buf_cursor,
+                top_line: self.top_line,
+            },
+            rect: Rect::default(),
+            cache: RenderCache::default(),
+            placement: Placement::default(),
+        });
+        self.panes.layout.split(self.panes.focused, new, vertical);
+        self.relayout();
+        self.focus_pane(new);
+    }
+
+    pub(super) fn close_pane(&mut self) -> Result<()> {
+        if self.panes.list.len() == 1 {
+            return Err(eyre!("can't close the last pane"));
+        }
+        let closed = self.panes.focused;
+        let next = if closed == 0 { 1 } else { closed - 1 };
+        self.focus_pane(next);
+        self.panes.list.remove(closed);
+        self.panes.layout.remove(closed);
+        if self.panes.focused > closed {
+            self.panes.focused -= 1;
+        }
+        self.relayout();
+        Ok(())
+    }
+
+    /// Moves focus to the nearest pane in `direction`.
+    pub(super) fn focus_direction(&mut self, direction: Direction) {
+        let from = self.pane;
+        let overlaps = |a: u16, a_len: u16, b: u16, b_len: u16| a < b + b_len && b < a + a_len;
+        let target = (0..self.panes.list.len())
+            .filter(|&i| i != self.panes.focused)
+            .filter_map(|i| {
+                let to = self.panes.list[i].rect;
+                let distance = match direction {
+                    Direction::Left if to.x + to.width <= from.x => from.x - to.x - to.width,
+                    Direction::Right if to.x >= from.x + from.width => to.x - from.x - from.width,
+                    Direction::Up if to.y + to.height <= from.y => from.y - to.y - to.height,
+                    Direction::Down if to.y >= from.y + from.height => to.y - from.y - from.height,
+                    _ => return None,
+                };
+                let aligned = match direction {
+                    Direction::Left | Direction::Right => {
+                        overlaps(from.y, from.height, to.y, to.height)
+                    }
+                    Direction::Up | Direction::Down => overlaps(from.x, from.width, to.x, to.width),
+                };
+                aligned.then_some((distance, i))
+            })
+            .min();
+        if let Some((_, index)) = target {
+            self.focus_pane(index);
+        }
+    }
+
+    pub(super) fn focus_pane(&mut self, index: usize) {
+        if index == self.panes.focused {
+            return;
+        }
+        self.park_pane();
+        self.unpark_pane(index);
+        self.render_cache.status = None;
+        self.redraw = true;
+    }
+
+    /// Scrolls the pane after the focused one by `lines`, leaving focus where it is.
+    pub(super) fn scroll_other_pane(&mut self, lines: isize) {
+        let count = self.panes.list.len();
+        if count == 1 {
+            self.dbg = "no other pane".into();
+            return;
+        }
+        let focused = self.panes.focused;
+        let other = (focused + 1) % count;
+        let desired = self.desired_position;
+        self.park_pane();
+        self.load_pane(other);
+        self.scroll_lines(lines);
+        self.store_pane(other);
+        self.load_pane(focused);
+        self.desired_position = desired;
+        self.redraw = true;
+    }
+
+    /// Saves the focused pane's state into its slot.
+    fn park_pane(&mut self) {
+        self.store_pane(self.panes.focused);
+    }
+
+    /// Moves the buffer and view in the editor into the slot of pane `index`.
+    fn store_pane(&mut self, index: usize) {
+        let pane = &mut self.panes.list[index];
+        pane.buffer = self.active;
+        pane.view = View {
+            cursor: self.buf_cursor,
+            top_line: self.top_line,
+        };
+        std::mem::swap(&mut pane.cache, &mut self.render_cache);
+        let mut placement = std::mem::take(&mut pane.placement);
+        self.swap_placement(&mut placement);
+        self.panes.list[index].placement = placement;
+    }
+
+    /// Loads the state of pane `index` into the editor.
+    fn unpark_pane(&mut self, index: usize) {
+        self.panes.focused = index;
+        self.load_pane(index);
+        self.set_desired_column(self.scr_cursor.y);
+    }
+
+    /// Puts the buffer and view of pane `index` in the editor, laying the buffer
+    /// out again only if the pane's placement is out of date.
+    fn load_pane(&mut self, index: usize) {
+        let pane = &mut self.panes.list[index];
+        std::mem::swap(&mut pane.cache, &mut self.render_cache);
+        let mut placement = std::mem::take(&mut pane.placement);
+        let (buffer, view) = (pane.buffer, pane.view);
+        self.pane = pane.rect;
+        if buffer != self.active {
+            self.activate_buffer(buffer);
+        }
+        self.swap_placement(&mut placement);
+        self.top_line = view.top_line;
+        self.buf_cursor = view.cursor;
+        if self.virtual_lines.is_empty() {
+            self.compute_virtual_lines();
+            self.top_line = view
+                .top_line
+                .min(self.virtual_lines.len().saturating_sub(1));
+            self.place_cursor(view.cursor);
+        }
+    }
+
+    /// Takes the focused pane's layout out of the editor, or puts it back.
+    fn swap_placement(&mut self, placement: &mut Placement) {
+        std::mem::swap(&mut self.virtual_lines, &mut placement.virtual_lines);
+        std::mem::swap(&mut self.cur_vline, &mut placement.cur_vline);
+        std::mem::swap(&mut self.cur_line, &mut placement.cur_line);
+        std::mem::swap(&mut self.scr_cursor, &mut placement.scr_cursor);
+    }
+
+    /// Recomputes where each pane goes after the layout or the terminal size changed.
+    pub(super) fn relayout(&mut self) {
+        let screen = Rect {
+            x: 0,
+            y: 0,
+            width: self.window.width,
+            height: self.window.height,
+        };
+        self.panes.separators.clear();
+        self.panes
+            .layout
+            .arrange(screen, &mut self.panes.list, &mut self.panes.separators);
+        self.pane = self.panes.list[self.panes.focused].rect;
+        for pane in &mut self.panes.list {
+            pane.cache.invalidate();
+        }
+        self.panes.invalidate(None);
+        self.render_cache.invalidate();
+        self.compute_virtual_lines();
+        self.clamp_state_to_buffer();
+        self.redraw = true;
+    }
+
+    /// Draws the panes that don't have focus and the lines between panes.
+    pub(super) fn render_other_panes(&mut self) -> Result<()> {
+        let focused = self.panes.focused;
+        let mode = std::mem::replace(&mut self.mode, Mode::Normal);
+        self.park_pane();
+        for index in (0..self.panes.list.len()).filter(|&i| i != focused) {
+            self.load_pane(index);
+            self.gutter()?;
+            self.redraw()?;
+            self.store_pane(index);
+        }
+        self.load_pane(focused);
+        self.mode = mode;
+
+        for separator in &self.panes.separators {
+            let symbol = if separator.width == 1 { '│' } else { '─' };
+            for y in separator.y..separator.y + separator.height {
+                for x in separator.x..separator.x + separator.width {
+                    self.window
+                        .stdout
+                        .queue(MoveTo(x, y))?
+                        .queue(Print(symbol))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Editor};
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_vsplit_panes_are_independent() {
+        let mut e = editor("0123456789012345678901234567890\nsecond line\nthird\n");
+        keys(&mut e, ":vsplit");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.panes.list.len(), 2);
+        assert_eq!(e.panes.focused, 1);
+        let (left, right) = (e.panes.list[0].rect, e.panes.list[1].rect);
+        assert_eq!((left.x, left.width), (0, 19));
+        assert_eq!((right.x, right.width), (20, 20));
+        assert_eq!(e.panes.separators.len(), 1);
+        assert!(e.virtual_lines.iter().all(|l| l.len() <= 17));
+
+        keys(&mut e, "sss");
+        let right_cursor = e.buf_cursor;
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        keys(&mut e, "h");
+        assert_eq!(e.panes.focused, 0);
+        assert_eq!(e.buf_cursor, 0);
+        keys(&mut e, "d");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        keys(&mut e, "l");
+        assert_eq!(e.buf_cursor, right_cursor);
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.render_other_panes().unwrap();
+        e.redraw().unwrap();
+        let screen = out.take();
+        // text of the left pane starts after its gutter, the right pane's after the separator
+        assert!(screen.contains("\x1b[1;4H0123456789012345"));
+        assert!(screen.contains("\x1b[1;24H01234567890123456"));
+        assert!(screen.contains('│'));
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        keys(&mut e, "c");
+        assert_eq!(e.panes.list.len(), 1);
+        assert_eq!(e.pane.width, 40);
+        assert_eq!(e.buf_cursor, 1);
+    }
+
+    #[test]
+    fn test_parked_panes_keep_their_layout() {
+        let mut e = editor("one\ntwo\n");
+        keys(&mut e, ":vsplit");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "s");
+        e.render_other_panes().unwrap();
+        assert_eq!(e.panes.list[0].placement.virtual_lines.len(), 2);
+        assert_eq!(e.buf_cursor, 4);
+
+        // moving around leaves the other pane as it was laid out
+        keys(&mut e, "d");
+        e.render_other_panes().unwrap();
+        assert_eq!(e.panes.list[0].placement.virtual_lines.len(), 2);
+        assert_eq!((e.buf_cursor, e.cur_vline), (5, 1));
+
+        // an edit to the buffer it shows lays it out again
+        keys(&mut e, "onew");
+        press(&mut e, KeyCode::Esc);
+        assert!(e.panes.list[0].placement.virtual_lines.is_empty());
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.render_other_panes().unwrap();
+        assert_eq!(e.panes.list[0].placement.virtual_lines.len(), 3);
+        assert_eq!(e.panes.list[0].placement.cur_vline, 0);
+        assert!(out.take().contains("\x1b[3;4Hnew"));
+        assert_eq!((e.buf_cursor, e.cur_vline), (11, 2));
+    }
+
+    #[test]
+    fn test_scroll_other_pane() {
+        let mut e = editor(&"line\n".repeat(30));
+        keys(&mut e, ":split");
+        press(&mut e, KeyCode::Enter);
+        let scroll = |e: &mut Editor, key| {
+            press_with(e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+            press_with(e, KeyModifiers::CONTROL, KeyCode::Char(key));
+        };
+        for _ in 0..3 {
+            scroll(&mut e, 'e');
+        }
+        scroll(&mut e, 'y');
+        assert_eq!(e.panes.focused, 1);
+        assert_eq!((e.top_line, e.buf_cursor), (0, 0));
+        // the cursor moves along only once it would leave the pane
+        assert_eq!(e.panes.list[0].view.top_line, 2);
+        assert_eq!(e.panes.list[0].view.cursor, 15);
+
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.render_other_panes().unwrap();
+        assert_eq!(e.panes.list[0].view.top_line, 2);
+        assert_eq!((e.top_line, e.buf_cursor), (0, 0));
+    }
+
+    #[test]
+    fn test_nested_splits() {
+        let mut e = editor("text\n");
+        keys(&mut e, ":split");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, ":vsplit");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.panes.list.len(), 3);
+        let rects: Vec<_> = e.panes.list.iter().map(|p| p.rect).collect();
+        assert_eq!((rects[0].y, rects[0].height), (0, 4));
+        assert_eq!((rects[1].y, rects[1].height, rects[1].width), (5, 5, 19));
+        assert_eq!((rects[2].x, rects[2].y), (20, 5));
+
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        keys(&mut e, "k");
+        assert_eq!(e.panes.focused, 0);
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('w'));
+        keys(&mut e, "c");
+        assert_eq!(e.panes.list.len(), 2);
+        assert_eq!(e.pane.height, 10);
+        assert_eq!(e.pane.width, 19);
+    }
+}
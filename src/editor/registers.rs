@@ -0,0 +1,292 @@
+use std::ops::Range;
+
+use super::{Editor, Mode};
+
+/// Text kept by a yank or delete.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct Register {
+    pub text: String,
+    /// Holds whole lines, which are put back as lines of their own.
+    pub linewise: bool,
+}
+
+impl Editor {
+    /// Copies `range` into the unnamed register `"` and the yank register `0`.
+    pub(super) fn yank(&mut self, range: Range<usize>, linewise: bool) {
+        let text = self.buf.rope.slice(range).to_string();
+        self.yank_text(text, linewise);
+    }
+
+    /// Puts `text` into the registers a yank goes to.
+    pub(super) fn yank_text(&mut self, text: String, linewise: bool) {
+        let register = Register { text, linewise };
+        self.registers.insert('0', register.clone());
+        self.registers.insert('"', register);
+    }
+
+    /// Yanks `count` lines starting at the cursor line, as whole lines.
+    pub(super) fn yank_lines(&mut self, count: usize) {
+        let rope = &self.buf.rope;
+        let last = (self.cur_line + count.max(1)).min(rope.len_lines());
+        let range = rope.line_to_char(self.cur_line)..rope.line_to_char(last);
+        self.yank(range, true);
+    }
+
+    /// Deletes `count` lines starting at the cursor line, keeping them in the registers
+    /// a delete goes to, and leaves the cursor at the start of the line after them.
+    pub(super) fn delete_lines(&mut self, count: usize) {
+        let rope = &self.buf.rope;
+        let last = self.cur_line + count.max(1);
+        let mut start = rope.line_to_char(self.cur_line);
+        let end = match last < rope.len_lines() {
+            true => rope.line_to_char(last),
+            false => rope.len_chars(),
+        };
+        if start == end {
+            return;
+        }
+        let text = rope.slice(start..end).to_string();
+        // a last line without a line break takes the one before it along
+        if end == rope.len_chars() && !text.ends_with('\n') && self.cur_line > 0 {
+            start = rope.line_to_char(self.cur_line - 1) + self.line_length(self.cur_line - 1);
+        }
+        self.registers.insert(
+            '"',
+            Register {
+                text,
+                linewise: true,
+            },
+        );
+        self.remove_text(start..end);
+        let rope = &self.buf.rope;
+        let line = match rope.line_to_char(self.cur_line.min(rope.len_lines() - 1)) {
+            // nothing follows, so land on the line above
+            at if at == rope.len_chars() && self.cur_line > 0 => self.cur_line - 1,
+            _ => self.cur_line,
+        };
+        self.move_cursor_to(rope.line_to_char(line.min(rope.len_lines() - 1)));
+    }
+
+    /// Puts the unnamed register after the cursor, or below the cursor line when it
+    /// holds lines. With `before`, at the cursor or above the line instead.
+    pub(super) fn paste(&mut self, before: bool) {
+        let Some(register) = self.registers.get(&'"').cloned() else {
+            self.dbg = "nothing to paste".into();
+            return;
+        };
+        let rope = &self.buf.rope;
+        if register.linewise {
+            let mut text = register.text;
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            let line = self.cur_line + usize::from(!before);
+            let at = match line < rope.len_lines() {
+                true => rope.line_to_char(line),
+                false => rope.len_chars(),
+            };
+            // below a last line that has no line break yet
+            let at_end = at == rope.len_chars() && at > 0 && rope.char(at - 1) != '\n';
+            if at_end {
+                text.pop();
+                text.insert(0, '\n');
+            }
+            self.insert_text(at, &text);
+            self.move_cursor_to(at + usize::from(at_end));
+        } else {
+            let on_line_break = self.char_under_cursor().is_none();
+            let at = self.buf_cursor + usize::from(!before && !on_line_break);
+            self.insert_text(at, &register.text);
+            let len = register.text.chars().count();
+            self.move_cursor_to(at + len.saturating_sub(1));
+        }
+    }
+
+    /// Yanks the selection and leaves the cursor at its start.
+    pub(super) fn yank_selection(&mut self) {
+        if let Some(selection) = self.selection() {
+            self.yank(selection.clone(), false);
+            self.move_cursor_to(selection.start);
+            self.redraw = true;
+        }
+    }
+
+    /// Deletes the selection into the unnamed register.
+    pub(super) fn delete_selection(&mut self) {
+        if let Some(selection) = self.selection() {
+            let register = Register {
+                text: self.buf.rope.slice(selection.clone()).to_string(),
+                linewise: false,
+            };
+            self.registers.insert('"', register);
+            self.remove_text(selection.clone());
+            self.move_cursor_to(selection.start);
+        }
+    }
+
+    /// Takes text pasted into the terminal in one go: into the buffer in Insert mode,
+    /// or its first line into the prompt in Command mode.
+    pub(super) fn paste_text(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        match self.mode {
+            Mode::Insert => {
+                self.insert_text(self.buf_cursor, &text);
+                self.move_cursor_to(self.buf_cursor + text.chars().count());
+            }
+            Mode::Command => {
+                let line = text.lines().next().unwrap_or_default();
+                self.command_buf.push_str(line);
+            }
+            _ => (),
+        }
+    }
+
+    /// Inserts the contents of register `name` at the cursor, as after `Ctrl+R` in Insert mode.
+    pub(super) fn insert_register(&mut self, name: char) {
+        let Some(register) = self.registers.get(&name) else {
+            self.dbg = format!("register {name} is empty");
+            return;
+        };
+        let text = register.text.clone();
+        self.insert_text(self.buf_cursor, &text);
+        self.move_cursor_to(self.buf_cursor + text.chars().count());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Editor, Mode};
+    use crossterm::event::{Event, KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_select_all_and_yank() {
+        let text = "first\nsecond\nthird";
+        let mut e = editor(text);
+        keys(&mut e, "s:%");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.mode, Mode::Visual);
+        assert_eq!(e.selection(), Some(0..text.len()));
+
+        keys(&mut e, "y");
+        assert_eq!(e.mode, Mode::Normal);
+        assert_eq!(e.registers[&'"'].text, text);
+        assert_eq!(e.registers[&'0'].text, text);
+        assert_eq!(e.buf_cursor, 0);
+
+        keys(&mut e, "i");
+        press_with(&mut e, KeyModifiers::CONTROL, KeyCode::Char('r'));
+        keys(&mut e, "0");
+        assert_eq!(e.buf.rope.to_string(), format!("{text}{text}"));
+    }
+
+    #[test]
+    fn test_paste_chars() {
+        let mut e = editor("abc\n");
+        keys(&mut e, "vdy");
+        assert_eq!(e.registers[&'"'].text, "ab");
+        keys(&mut e, "p");
+        assert_eq!(e.buf.rope.to_string(), "aabbc\n");
+        assert_eq!(e.buf_cursor, 2);
+        keys(&mut e, "P");
+        assert_eq!(e.buf.rope.to_string(), "aaabbbc\n");
+        assert_eq!(e.buf_cursor, 3);
+
+        // after the last char of a line, the text is appended there
+        keys(&mut e, "dddd");
+        assert_eq!(e.buf_cursor, 7);
+        keys(&mut e, "p");
+        assert_eq!(e.buf.rope.to_string(), "aaabbbcab\n");
+    }
+
+    #[test]
+    fn test_bracketed_paste() {
+        let mut e = editor("ab\n");
+        keys(&mut e, "di");
+        let paste = |e: &mut Editor, text: &str| {
+            let mode = e.handle_event(Event::Paste(text.into())).unwrap();
+            e.set_mode(mode).unwrap();
+        };
+        paste(&mut e, "one\r\ntwo\rthree");
+        assert_eq!(e.buf.rope.to_string(), "aone\ntwo\nthreeb\n");
+        assert_eq!((e.buf_cursor, e.cur_line, e.cur_vline), (14, 2, 2));
+        assert_eq!(e.virtual_lines.len(), 3);
+
+        press(&mut e, KeyCode::Esc);
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "ab\n");
+
+        keys(&mut e, ":");
+        paste(&mut e, "set showtimer\nignored");
+        press(&mut e, KeyCode::Enter);
+        assert!(e.settings.show_timer);
+    }
+
+    #[test]
+    fn test_paste_lines() {
+        let mut e = editor("one\ntwo\nthree");
+        keys(&mut e, "syy");
+        assert_eq!(e.registers[&'"'].text, "two\n");
+        keys(&mut e, "p");
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\ntwo\nthree");
+        assert_eq!(e.cur_line, 2);
+        keys(&mut e, "wwP");
+        assert_eq!(e.buf.rope.to_string(), "two\none\ntwo\ntwo\nthree");
+        assert_eq!((e.cur_line, e.buf_cursor), (0, 0));
+
+        // below a last line without a line break
+        keys(&mut e, "ssssp");
+        assert_eq!(e.buf.rope.to_string(), "two\none\ntwo\ntwo\nthree\ntwo");
+        assert_eq!(e.cur_line, 5);
+        keys(&mut e, "3yy");
+        assert_eq!(e.registers[&'"'].text, "two");
+    }
+
+    #[test]
+    fn test_delete_lines() {
+        let mut e = editor("one\ntwo\nthree\nfour");
+        keys(&mut e, "sxx");
+        assert_eq!(e.buf.rope.to_string(), "one\nthree\nfour");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 4));
+        assert_eq!(e.registers[&'"'].text, "two\n");
+        assert!(!e.registers.contains_key(&'0'));
+
+        // the last line, without a line break
+        keys(&mut e, "sxx");
+        assert_eq!(e.buf.rope.to_string(), "one\nthree");
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 4));
+        keys(&mut e, "p");
+        assert_eq!(e.buf.rope.to_string(), "one\nthree\nfour");
+
+        keys(&mut e, "ww5xx");
+        assert_eq!(e.buf.rope.to_string(), "");
+        assert_eq!((e.cur_line, e.buf_cursor, e.virtual_lines.len()), (0, 0, 1));
+        keys(&mut e, "xx");
+        assert_eq!(e.registers[&'"'].text, "one\nthree\nfour");
+    }
+
+    #[test]
+    fn test_delete_selection() {
+        let mut e = editor("one two\n");
+        keys(&mut e, "ddddvddx");
+        assert_eq!(e.buf.rope.to_string(), "one \n");
+        assert_eq!(e.registers[&'"'].text, "two");
+        assert!(!e.registers.contains_key(&'0'));
+    }
+
+    #[test]
+    fn test_yank_and_delete_across_lines() {
+        let mut e = editor("one\ntwo\n");
+        keys(&mut e, "ddvs");
+        assert_eq!(e.selection(), Some(2..7));
+        keys(&mut e, "y");
+        assert_eq!(e.registers[&'"'].text, "e\ntwo");
+        assert_eq!(e.buf_cursor, 2);
+
+        keys(&mut e, "vsx");
+        assert_eq!(e.buf.rope.to_string(), "on\n");
+        assert_eq!((e.mode, e.cur_line), (Mode::Normal, 0));
+        assert_eq!(e.virtual_lines.len(), 1);
+        keys(&mut e, "p");
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\n");
+    }
+}
@@ -0,0 +1,191 @@
+use crossterm::style::Color;
+use std::ops::Range;
+
+/// Everything drawn in the gutter on one row.
+#[derive(PartialEq, Clone)]
+pub(super) struct GutterRow {
+    /// Column the gutter starts at.
+    pub column: u16,
+    pub sign_width: u16,
+    pub sign: Option<(char, Color)>,
+    pub label: String,
+    /// Drawn between the line numbers and the text.
+    pub separator: String,
+}
+
+/// Everything drawn right of the gutter on one row.
+#[derive(PartialEq, Clone)]
+pub(super) struct TextRow {
+    /// Column the text starts at.
+    pub column: u16,
+    pub text: String,
+    /// Color of the whole text.
+    pub color: Option<Color>,
+    /// Chars of `text` shown selected.
+    pub selected: Option<Range<usize>>,
+    /// Chars of `text` colored by syntax highlighting.
+    pub spans: Vec<(Range<usize>, Color)>,
+    /// A cell drawn with a colored background.
+    pub highlight: Option<(u16, Color)>,
+}
+
+impl TextRow {
+    /// `text` cut where its look changes, each piece with its color and whether it is selected.
+    pub fn segments(&self) -> Vec<(String, Option<Color>, bool)> {
+        let mut segments: Vec<(String, Option<Color>, bool)> = Vec::new();
+        for (i, ch) in self.text.chars().enumerate() {
+            let span = self.spans.iter().find(|(range, _)| range.contains(&i));
+            let color = span.map(|(_, color)| *color).or(self.color);
+            let selected = self.selected.as_ref().is_some_and(|s| s.contains(&i));
+            match segments.last_mut() {
+                Some((text, c, s)) if (*c, *s) == (color, selected) => text.push(ch),
+                _ => segments.push((ch.to_string(), color, selected)),
+            }
+        }
+        segments
+    }
+}
+
+/// What was last drawn on each screen row, so unchanged rows aren't sent to the terminal again.
+///
+/// Rows are compared by everything that affects how they look, so scrolling and
+/// edits repaint exactly the rows whose content moved or changed.
+#[derive(Default)]
+pub(super) struct RenderCache {
+    pub gutter: Rows<GutterRow>,
+    pub text: Rows<TextRow>,
+    pub status: Option<String>,
+}
+
+impl RenderCache {
+    /// Forgets everything, forcing the next render to repaint the whole screen.
+    pub fn invalidate(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Key bindings listed on the splash screen.
+const SPLASH_KEYS: [(&str, &str); 5] = [
+    ("i", "insert text"),
+    (":vsplit", "split the window"),
+    ("Ctrl+N", "open notes"),
+    ("u", "undo"),
+    ("q", "quit"),
+];
+
+/// The line of the splash screen, shown in an empty buffer without a file,
+/// that goes on `row` of a text area of the given size. The splash is centered both ways.
+pub(super) fn splash_row(row: u16, width: usize, height: u16) -> Option<String> {
+    let mut lines = vec![
+        format!("red v{}", env!("CARGO_PKG_VERSION")),
+        "a modal text editor".to_string(),
+        String::new(),
+    ];
+    lines.extend(SPLASH_KEYS.map(|(key, action)| format!("{key:<8} {action:<16}")));
+    let top = height.saturating_sub(lines.len() as u16) / 2;
+    let line = lines.get(row.checked_sub(top)? as usize)?;
+    let indent = width.saturating_sub(line.chars().count()) / 2;
+    Some(format!("{}{line}", " ".repeat(indent)))
+}
+
+pub(super) struct Rows<T>(Vec<Option<T>>);
+
+impl<T> Default for Rows<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: PartialEq + Clone> Rows<T> {
+    /// Records `content` for `row`, returning whether it differs from what was there.
+    pub fn changed(&mut self, row: u16, content: &T) -> bool {
+        let row = row as usize;
+        if self.0.len() <= row {
+            self.0.resize_with(row + 1, || None);
+        }
+        if self.0[row].as_ref() == Some(content) {
+            return false;
+        }
+        self.0[row] = Some(content.clone());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use crate::util::FileBuf;
+    use crossterm::event::KeyCode;
+
+    #[test]
+    fn test_unchanged_rows_are_not_reprinted() {
+        let mut e = editor("hello\nworld\n");
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let first = out.take();
+        assert!(first.contains("hello") && first.contains("world") && first.contains('~'));
+        assert!(first.contains("[NORMAL]"));
+
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let second = out.take();
+        assert!(!second.contains("hello") && !second.contains("world"));
+        assert!(!second.contains('~') && !second.contains("[NORMAL]"));
+
+        press(&mut e, KeyCode::Char('i'));
+        press(&mut e, KeyCode::Char('!'));
+        e.interface().unwrap();
+        e.redraw().unwrap();
+        let third = out.take();
+        assert!(third.contains("!hello") && !third.contains("world"));
+    }
+
+    #[test]
+    fn test_syntax_colors_follow_wrapped_rows() {
+        let mut e = editor_with(FileBuf {
+            rope: format!("{} \"ab\" cd\nlet\n", "x".repeat(35)).into(),
+            path: "main.rs".into(),
+            ..Default::default()
+        });
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        let screen = out.take();
+        // the string starts at the end of the first row and ends on the second
+        assert!(screen.contains("x \x1b[38;5;10m\"\x1b[39m"));
+        assert!(screen.contains("\x1b[2;4H\x1b[38;5;10mab\"\x1b[39m cd"));
+        assert!(screen.contains("\x1b[3;4H\x1b[38;5;13mlet\x1b[39m"));
+
+        // plain text stays plain
+        let mut e = editor("let x = \"y\"\n");
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        assert!(out.take().contains("\x1b[1;4Hlet x = \"y\" "));
+    }
+
+    #[test]
+    fn test_splash_in_empty_buffer_without_file() {
+        let mut e = editor_with(FileBuf::default());
+        let out = Output::default();
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(screen.contains("red v0.1.0") && screen.contains("insert text"));
+
+        press(&mut e, KeyCode::Char('i'));
+        press(&mut e, KeyCode::Char('x'));
+        e.redraw().unwrap();
+        let screen = out.take();
+        assert!(!screen.contains("red v") && !screen.contains("insert text"));
+        assert!(screen.contains('x'));
+
+        // a file that happens to be empty gets no splash
+        let mut e = editor("");
+        e.window.stdout = Box::new(out.clone());
+        e.redraw().unwrap();
+        assert!(!out.take().contains("red v"));
+    }
+}
@@ -0,0 +1,465 @@
+use color_eyre::{eyre::eyre, Result};
+use ropey::Rope;
+use std::{
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use super::{Editor, Mode, Prompt};
+use crate::grep::{spawn_grep, FileMatch, GrepResult};
+
+/// Char offsets of every occurrence of `pattern`, in order.
+fn find_matches(rope: &Rope, pattern: &str) -> Vec<usize> {
+    if pattern.is_empty() {
+        return Vec::new();
+    }
+    rope.to_string()
+        .match_indices(pattern)
+        .map(|(byte, _)| rope.byte_to_char(byte))
+        .collect()
+}
+
+/// Picks the `count`th of `matches` after (or before) `from`, going around the ends
+/// when `wrap` is set and stopping at the last one otherwise.
+/// Returns the match and whether an end was passed or hit.
+fn nth_match(
+    matches: &[usize],
+    from: usize,
+    count: usize,
+    forward: bool,
+    wrap: bool,
+) -> Option<(usize, bool)> {
+    let len = matches.len() as isize;
+    if len == 0 {
+        return None;
+    }
+    let count = count.max(1) as isize;
+    let target = if forward {
+        matches.partition_point(|&m| m <= from) as isize + count - 1
+    } else {
+        matches.partition_point(|&m| m < from) as isize - count
+    };
+    let past_end = !(0..len).contains(&target);
+    let target = match (past_end, wrap) {
+        (false, _) => target,
+        (true, true) => target.rem_euclid(len),
+        (true, false) => target.clamp(0, len - 1),
+    };
+    Some((matches[target as usize], past_end))
+}
+
+/// A match listed by `:grep`.
+#[derive(Debug, PartialEq)]
+pub(super) enum QuickfixEntry {
+    /// A char offset in the buffer that was searched.
+    Offset(usize),
+    File(FileMatch),
+}
+
+/// Matches collected by `:grep`, stepped through with `]q` and `[q`.
+#[derive(Default)]
+pub(super) struct Quickfix {
+    matches: Vec<QuickfixEntry>,
+    /// Index of the match last jumped to.
+    current: Option<usize>,
+    /// A search across files still running.
+    pub pending: Option<Receiver<GrepResult>>,
+}
+
+impl Editor {
+    /// Collects every match of `pattern`, or of the last search, and lists them in an overlay.
+    /// With a glob after the pattern, the files matching it are searched instead of the buffer.
+    pub(super) fn grep(&mut self, args: &str) -> Result<()> {
+        let (pattern, glob) = match args.split_once(' ') {
+            Some((pattern, glob)) => (pattern, Some(glob.trim())),
+            None => (args, None),
+        };
+        let pattern = match pattern {
+            "" => self
+                .last_search
+                .clone()
+                .ok_or_else(|| eyre!("no previous search"))?,
+            pattern => pattern.to_string(),
+        };
+        if let Some(glob) = glob {
+            self.dbg = format!("searching {glob} for {pattern}");
+            self.quickfix.pending = Some(spawn_grep(pattern.clone(), glob.to_string()));
+            self.last_search = Some(pattern);
+            return Ok(());
+        }
+        let matches = find_matches(&self.buf.rope, &pattern);
+        if matches.is_empty() {
+            return Err(eyre!("pattern not found: {pattern}"));
+        }
+        let rope = &self.buf.rope;
+        let lines = matches.iter().enumerate().map(|(i, &at)| {
+            let line = rope.char_to_line(at);
+            let column = at - rope.line_to_char(line);
+            let text = rope.line(line).to_string();
+            let text = text.trim_end_matches(['\n', '\r']);
+            format!("{:>3} {}:{} {text}", i + 1, line + 1, column + 1)
+        });
+        self.overlay = Some(lines.collect());
+        self.dbg = format!("{} matches", matches.len());
+        self.last_search = Some(pattern);
+        self.quickfix = Quickfix {
+            matches: matches.into_iter().map(QuickfixEntry::Offset).collect(),
+            ..Default::default()
+        };
+        self.redraw = true;
+        Ok(())
+    }
+
+    /// Lists the results of a search across files once it is done,
+    /// waiting for it at most `timeout`.
+    pub(super) fn poll_grep(&mut self, timeout: Duration) {
+        let Some(pending) = &self.quickfix.pending else {
+            return;
+        };
+        let result = match pending.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => return,
+            Err(RecvTimeoutError::Disconnected) => {
+                self.quickfix.pending = None;
+                self.dbg = "search failed".into();
+                return;
+            }
+        };
+        self.quickfix = Quickfix::default();
+        self.redraw = true;
+        if result.matches.is_empty() {
+            let pattern = self.last_search.as_deref().unwrap_or_default();
+            self.dbg = format!("pattern not found: {pattern}");
+            return;
+        }
+        let lines = result.matches.iter().enumerate().map(|(i, m)| {
+            let path = m.path.display();
+            format!(
+                "{:>3} {path}:{}:{} {}",
+                i + 1,
+                m.line + 1,
+                m.column + 1,
+                m.text
+            )
+        });
+        self.overlay = Some(lines.collect());
+        self.dbg = format!("{} matches in {} files", result.matches.len(), result.files);
+        if result.truncated {
+            self.dbg += ", stopped early";
+        }
+        self.quickfix.matches = result
+            .matches
+            .into_iter()
+            .map(QuickfixEntry::File)
+            .collect();
+    }
+
+    /// Jumps `count` entries of the `:grep` list forwards or backwards, going around the ends.
+    pub(super) fn jump_to_quickfix(&mut self, count: usize, forward: bool) {
+        let len = self.quickfix.matches.len() as isize;
+        if len == 0 {
+            self.dbg = "no matches listed".into();
+            return;
+        }
+        let count = count.max(1) as isize;
+        let target = match (self.quickfix.current, forward) {
+            (Some(current), true) => current as isize + count,
+            (Some(current), false) => current as isize - count,
+            (None, true) => count - 1,
+            (None, false) => len - count,
+        };
+        let target = target.rem_euclid(len) as usize;
+        self.quickfix.current = Some(target);
+        let position = format!("({} of {len})", target + 1);
+        match &self.quickfix.matches[target] {
+            &QuickfixEntry::Offset(at) => self.move_cursor_to(at),
+            QuickfixEntry::File(found) => {
+                let (line, column) = (found.line, found.column);
+                if let Err(e) = self.open_path(&found.path.clone()) {
+                    self.dbg = e.to_string();
+                    return;
+                }
+                // the file may have changed since it was searched
+                let rope = &self.buf.rope;
+                let line = line.min(rope.len_lines() - 1);
+                let column = column.min(rope.line(line).len_chars());
+                self.move_cursor_to(rope.line_to_char(line) + column);
+            }
+        }
+        self.dbg = position;
+    }
+
+    /// Opens the `/` prompt. The cursor jumps to the first match as the pattern is typed.
+    pub(super) fn start_search(&mut self) -> Mode {
+        self.search_count = self.take_count();
+        self.search_origin = self.buf_cursor;
+        self.command_buf.clear();
+        self.prompt = Prompt::Search;
+        Mode::Command
+    }
+
+    /// Moves the cursor to the match of the pattern typed so far,
+    /// or back to where the search started if there is none.
+    pub(super) fn preview_search(&mut self) {
+        let matches = find_matches(&self.buf.rope, &self.command_buf);
+        let wrap = self.settings.wrapscan;
+        let found = nth_match(&matches, self.search_origin, self.search_count, true, wrap);
+        self.move_cursor_to(found.map_or(self.search_origin, |(found, _)| found));
+    }
+
+    /// Abandons the line typed at the prompt, undoing where a search preview moved the cursor.
+    pub(super) fn leave_prompt(&mut self) -> Mode {
+        if self.prompt == Prompt::Search {
+            self.move_cursor_to(self.search_origin);
+        }
+        self.prompt.origin()
+    }
+
+    /// Searches for `pattern` and jumps to its `count`th match after the cursor.
+    pub(super) fn search(&mut self, pattern: &str, count: usize) {
+        if !pattern.is_empty() {
+            self.last_search = Some(pattern.to_string());
+        }
+        self.search_next(count, true);
+    }
+
+    /// Searches forwards for the word under the cursor.
+    pub(super) fn search_word(&mut self, count: usize) {
+        match self.word_under_cursor() {
+            Some(word) => self.search(&word, count),
+            None => self.dbg = "no word under the cursor".into(),
+        }
+    }
+
+    /// Jumps `count` matches of the last search forwards or backwards.
+    pub(super) fn search_next(&mut self, count: usize, forward: bool) {
+        let Some(pattern) = self.last_search.clone() else {
+            self.dbg = "no previous search".into();
+            return;
+        };
+        let matches = find_matches(&self.buf.rope, &pattern);
+        let wrap = self.settings.wrapscan;
+        let Some((found, past_end)) = nth_match(&matches, self.buf_cursor, count, forward, wrap)
+        else {
+            self.dbg = format!("pattern not found: {pattern}");
+            return;
+        };
+        self.dbg = match (past_end, wrap, forward) {
+            (false, ..) => format!("/{pattern}"),
+            (true, true, true) => "search hit BOTTOM, continuing at TOP".into(),
+            (true, true, false) => "search hit TOP, continuing at BOTTOM".into(),
+            (true, false, true) => "search hit BOTTOM".into(),
+            (true, false, false) => "search hit TOP".into(),
+        };
+        self.move_cursor_to(found);
+    }
+
+    /// Jumps `count` lines carrying a sign forwards or backwards, going around the ends,
+    /// and shows the sign's message.
+    pub(super) fn jump_to_sign(&mut self, count: usize, forward: bool) {
+        let lines: Vec<usize> = self.buf.signs.keys().copied().collect();
+        let line = self.buf.rope.char_to_line(self.buf_cursor);
+        let Some((found, _)) = nth_match(&lines, line, count, forward, true) else {
+            self.dbg = "no signs".into();
+            return;
+        };
+        self.dbg = format!("{}: {}", found + 1, self.buf.signs[&found].message);
+        self.goto_line(found + 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::*;
+    use super::{nth_match, QuickfixEntry::*};
+    use crate::util::{Sign, TempDir};
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use crossterm::style::Color;
+    use std::{fs, path::PathBuf, time::Duration};
+
+    #[test]
+    fn test_nth_match() {
+        let matches = [2, 5, 9];
+        assert_eq!(nth_match(&matches, 5, 1, true, true), Some((9, false)));
+        assert_eq!(nth_match(&matches, 5, 2, true, true), Some((2, true)));
+        assert_eq!(nth_match(&matches, 5, 2, true, false), Some((9, true)));
+        assert_eq!(nth_match(&matches, 5, 1, false, true), Some((2, false)));
+        assert_eq!(nth_match(&matches, 5, 2, false, true), Some((9, true)));
+        assert_eq!(nth_match(&[], 0, 1, true, true), None);
+    }
+
+    #[test]
+    fn test_grep_and_step_through_matches() {
+        let mut e = editor("foo bar\nbaz foo\nfoo\n");
+        keys(&mut e, ":grep foo");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.quickfix.matches, [Offset(0), Offset(12), Offset(16)]);
+        let overlay = e.overlay.clone().unwrap();
+        assert_eq!(
+            overlay,
+            ["  1 1:1 foo bar", "  2 2:5 baz foo", "  3 3:1 foo"]
+        );
+        press(&mut e, KeyCode::Esc);
+
+        let mut visited = Vec::new();
+        for _ in 0..4 {
+            keys(&mut e, "]q");
+            visited.push(e.buf_cursor);
+        }
+        assert_eq!(visited, [0, 12, 16, 0]);
+        assert_eq!(e.dbg, "(1 of 3)");
+        keys(&mut e, "[q");
+        assert_eq!(e.buf_cursor, 16);
+
+        // without a pattern the last search is used
+        keys(&mut e, "/bar");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, ":grep");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.quickfix.matches, [Offset(4)]);
+    }
+
+    #[test]
+    fn test_grep_across_files() {
+        let dir = TempDir::new("grep-files");
+        fs::create_dir_all(dir.0.join("sub")).unwrap();
+        fs::write(dir.0.join("one.txt"), "fn main() {}\nlet x = main;\n").unwrap();
+        fs::write(dir.0.join("sub/two.txt"), "nothing\n  main\n").unwrap();
+        fs::write(dir.0.join("sub/three.rs"), "main\n").unwrap();
+
+        let mut e = editor("");
+        keys(&mut e, &format!(":grep main {}/**/*.txt", dir.0.display()));
+        press(&mut e, KeyCode::Enter);
+        e.poll_grep(Duration::from_secs(10));
+        let found: Vec<_> = e
+            .quickfix
+            .matches
+            .iter()
+            .map(|m| match m {
+                File(m) => (
+                    m.path.strip_prefix(&dir.0).unwrap().to_owned(),
+                    m.line,
+                    m.column,
+                ),
+                Offset(_) => panic!("match in the buffer"),
+            })
+            .collect();
+        let at = |path: &str, line, column| (PathBuf::from(path), line, column);
+        assert_eq!(
+            found,
+            [
+                at("one.txt", 0, 3),
+                at("one.txt", 1, 8),
+                at("sub/two.txt", 1, 2)
+            ]
+        );
+        assert_eq!(e.dbg, "3 matches in 2 files");
+        press(&mut e, KeyCode::Esc);
+
+        keys(&mut e, "[q");
+        assert!(e.buf.path.ends_with("sub/two.txt"));
+        assert_eq!((e.cur_line, e.buf_cursor), (1, 10));
+        assert_eq!(e.dbg, "(3 of 3)");
+        keys(&mut e, "]q");
+        assert!(e.buf.path.ends_with("one.txt"));
+        assert_eq!(e.buf_cursor, 3);
+    }
+
+    #[test]
+    fn test_jump_between_signs() {
+        let mut e = editor(&"line\n".repeat(12));
+        for (line, message) in [(2, "unused variable"), (9, "missing semicolon")] {
+            e.buf.signs.insert(
+                line,
+                Sign {
+                    symbol: 'E',
+                    color: Color::Red,
+                    message: message.into(),
+                },
+            );
+        }
+
+        keys(&mut e, "]d");
+        assert_eq!(e.buf.rope.char_to_line(e.buf_cursor), 2);
+        assert_eq!(e.dbg, "3: unused variable");
+        keys(&mut e, "]d");
+        assert_eq!(e.buf.rope.char_to_line(e.buf_cursor), 9);
+        assert_eq!(e.dbg, "10: missing semicolon");
+        keys(&mut e, "]d");
+        assert_eq!(e.buf.rope.char_to_line(e.buf_cursor), 2);
+        keys(&mut e, "[d");
+        assert_eq!(e.buf.rope.char_to_line(e.buf_cursor), 9);
+    }
+
+    #[test]
+    fn test_search_moves_while_typing() {
+        let mut e = editor("alpha beta\nbetter\nbest\n");
+        keys(&mut e, "/be");
+        assert_eq!(e.buf_cursor, 6);
+        keys(&mut e, "tt");
+        assert_eq!(e.buf_cursor, 11);
+        keys(&mut e, "x");
+        assert_eq!(e.buf_cursor, 0);
+        press(&mut e, KeyCode::Backspace);
+        press(&mut e, KeyCode::Enter);
+        assert_eq!((e.buf_cursor, e.cur_line), (11, 1));
+
+        keys(&mut e, "/bes");
+        assert_eq!(e.cur_line, 2);
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf_cursor, 11);
+        keys(&mut e, "n");
+        assert_eq!(e.buf_cursor, 11);
+        assert_eq!(e.dbg, "search hit BOTTOM, continuing at TOP");
+    }
+
+    #[test]
+    fn test_star_searches_word_under_cursor() {
+        let mut e = editor("let count = 1;\ncount += 2;\n");
+        e.move_cursor_to(6);
+        keys(&mut e, "*");
+        assert_eq!(
+            (e.buf_cursor, e.last_search.as_deref()),
+            (15, Some("count"))
+        );
+        keys(&mut e, "n");
+        assert_eq!(e.buf_cursor, 4);
+
+        e.move_cursor_to(10);
+        keys(&mut e, "*");
+        assert_eq!(
+            (e.buf_cursor, e.dbg.as_str()),
+            (10, "no word under the cursor")
+        );
+    }
+
+    #[test]
+    fn test_count_before_n() {
+        let mut e = editor("foo x foo y foo z foo w foo\n");
+        keys(&mut e, "/foo");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf_cursor, 6);
+        e.move_cursor_to(0);
+
+        keys(&mut e, "3n");
+        assert_eq!(e.buf_cursor, 18);
+        keys(&mut e, "2N");
+        assert_eq!(e.buf_cursor, 6);
+        keys(&mut e, "4n");
+        assert_eq!(e.buf_cursor, 0);
+        assert_eq!(e.dbg, "search hit BOTTOM, continuing at TOP");
+
+        keys(&mut e, ":set nowrapscan");
+        press(&mut e, KeyCode::Enter);
+        keys(&mut e, "10n");
+        assert_eq!(e.buf_cursor, 24);
+        assert_eq!(e.dbg, "search hit BOTTOM");
+
+        e.move_cursor_to(0);
+        keys(&mut e, "2/foo");
+        press(&mut e, KeyCode::Enter);
+        assert_eq!(e.buf_cursor, 12);
+        press_with(&mut e, KeyModifiers::NONE, KeyCode::Char('n'));
+        assert_eq!(e.buf_cursor, 18);
+    }
+}
@@ -0,0 +1,262 @@
+use std::ops::{Range, RangeInclusive};
+
+use super::{Editor, Mode};
+use crate::textobject;
+
+/// Text being typed after `I` or `A` in Visual mode, which is repeated on the
+/// other selected lines when Insert mode is left.
+pub(super) struct BlockInsert {
+    /// Lines after the one being typed on.
+    lines: RangeInclusive<usize>,
+    column: usize,
+    /// Fill lines too short to reach `column` with spaces, rather than skipping them.
+    pad: bool,
+    /// Where typing started.
+    start: usize,
+}
+
+impl Editor {
+    pub(super) fn enter_visual(&mut self) {
+        self.anchor = self.buf_cursor;
+        self.expansions.clear();
+        self.redraw = true;
+    }
+
+    /// The chars selected in Visual mode, from the anchor to the cursor inclusive.
+    pub(super) fn selection(&self) -> Option<Range<usize>> {
+        if self.mode != Mode::Visual {
+            return None;
+        }
+        let len = self.buf.rope.len_chars();
+        let start = self.anchor.min(self.buf_cursor).min(len);
+        let end = (self.anchor.max(self.buf_cursor) + 1).min(len);
+        Some(start..end.max(start))
+    }
+
+    /// Selects `range`, leaving the cursor on its last char.
+    pub(super) fn select(&mut self, range: Range<usize>) {
+        self.anchor = range.start;
+        self.move_cursor_to(range.end.saturating_sub(1).max(range.start));
+        self.redraw = true;
+    }
+
+    /// Moves the cursor to the other end of the selection, so it grows or shrinks from there.
+    pub(super) fn swap_selection_ends(&mut self) {
+        let anchor = std::mem::replace(&mut self.anchor, self.buf_cursor);
+        self.move_cursor_to(anchor);
+        self.redraw = true;
+    }
+
+    /// Selects the whole buffer.
+    pub(super) fn select_all(&mut self) {
+        self.select(0..self.buf.rope.len_chars());
+    }
+
+    /// Remembers the selection in the `<` and `>` marks (first and last char),
+    /// for commands run from Visual mode.
+    pub(super) fn mark_selection(&mut self) {
+        if let Some(selection) = self.selection() {
+            self.buf.marks.insert('<', selection.start);
+            self.buf.marks.insert('>', selection.end.saturating_sub(1));
+        }
+    }
+
+    /// The last selection made in Visual mode.
+    pub(super) fn marked_selection(&self) -> Option<Range<usize>> {
+        let start = *self.buf.marks.get(&'<')?;
+        let last = *self.buf.marks.get(&'>')?;
+        Some(start..(last + 1).min(self.buf.rope.len_chars()))
+    }
+
+    /// Starts inserting on every selected line, at the left edge of the selection (`I`)
+    /// or right of its right edge (`A`). Columns are taken from the anchor and the cursor.
+    pub(super) fn start_block_insert(&mut self, append: bool) -> Mode {
+        let rope = &self.buf.rope;
+        let line_column = |idx: usize| {
+            let line = rope.char_to_line(idx);
+            (line, idx - rope.line_to_char(line))
+        };
+        let (anchor_line, anchor_column) = line_column(self.anchor.min(rope.len_chars()));
+        let (cursor_line, cursor_column) = line_column(self.buf_cursor);
+        let first = anchor_line.min(cursor_line);
+        let last = anchor_line.max(cursor_line);
+        let column = match append {
+            true => anchor_column.max(cursor_column) + 1,
+            false => anchor_column.min(cursor_column),
+        };
+        let start = self.pad_line_to(first, column);
+        self.block_insert = Some(BlockInsert {
+            lines: first + 1..=last,
+            column,
+            pad: append,
+            start,
+        });
+        self.move_cursor_to(start);
+        self.redraw = true;
+        Mode::Insert
+    }
+
+    /// Copies the text typed since `start_block_insert` to the other lines of the block.
+    /// Nothing is copied if the typing left the line it started on.
+    pub(super) fn finish_block_insert(&mut self) {
+        let Some(block) = self.block_insert.take() else {
+            return;
+        };
+        let rope = &self.buf.rope;
+        let first = rope.char_to_line(block.start);
+        if self.buf_cursor <= block.start || rope.char_to_line(self.buf_cursor) != first {
+            return;
+        }
+        let text = rope.slice(block.start..self.buf_cursor).to_string();
+        // from the bottom up, so the lines above keep their offsets
+        for line in block.lines.rev() {
+            if !block.pad && self.line_length(line) < block.column {
+                continue;
+            }
+            let at = self.pad_line_to(line, block.column);
+            self.insert_text(at, &text);
+        }
+        self.redraw = true;
+    }
+
+    /// Chars in `line`, not counting its line ending.
+    pub(super) fn line_length(&self, line: usize) -> usize {
+        let text = self.buf.rope.line(line).to_string();
+        text.trim_end_matches(['\n', '\r']).chars().count()
+    }
+
+    /// Appends spaces to `line` until it reaches `column`, returning where that column is.
+    fn pad_line_to(&mut self, line: usize, column: usize) -> usize {
+        let length = self.line_length(line);
+        let start = self.buf.rope.line_to_char(line);
+        if length < column {
+            self.insert_text(start + length, &" ".repeat(column - length));
+        }
+        start + column
+    }
+
+    /// Inserts a copy of the selection right after it and moves to the copy.
+    /// Whole selected lines are copied below the last of them.
+    pub(super) fn duplicate_selection(&mut self) {
+        let Some(selection) = self.selection() else {
+            return;
+        };
+        let rope = &self.buf.rope;
+        let mut text = rope.slice(selection.clone()).to_string();
+        let whole_lines = selection.start == rope.line_to_char(rope.char_to_line(selection.start));
+        let mut copy_start = selection.end;
+        // the last line has no line break for the copy to go after
+        if whole_lines && selection.end == rope.len_chars() && !text.ends_with('\n') {
+            text.insert(0, '\n');
+            copy_start += 1;
+        }
+        self.insert_text(selection.end, &text);
+        self.move_cursor_to(copy_start);
+        self.redraw = true;
+    }
+
+    /// Widens the selection to the next enclosing structural unit.
+    pub(super) fn expand_selection(&mut self) {
+        let Some(current) = self.selection() else {
+            return;
+        };
+        if let Some(wider) = textobject::expand(&self.buf.rope, current.clone()) {
+            self.expansions.push(current);
+            self.select(wider);
+        }
+    }
+
+    /// Undoes the last `expand_selection`, unless the selection was changed since.
+    pub(super) fn shrink_selection(&mut self) {
+        let Some(current) = self.selection() else {
+            return;
+        };
+        match self.expansions.pop() {
+            Some(narrower) if narrower.start >= current.start && narrower.end <= current.end => {
+                self.select(narrower)
+            }
+            _ => self.expansions.clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{tests::*, Mode};
+    use crossterm::event::KeyCode;
+
+    #[test]
+    fn test_swap_selection_ends() {
+        let mut e = editor("one two three\n");
+        keys(&mut e, "ddddvddd");
+        assert_eq!(e.selection(), Some(4..8));
+        keys(&mut e, "o");
+        assert_eq!((e.buf_cursor, e.anchor), (4, 7));
+        assert_eq!(e.selection(), Some(4..8));
+        keys(&mut e, "aa");
+        assert_eq!(e.selection(), Some(2..8));
+        keys(&mut e, "o");
+        assert_eq!(e.buf_cursor, 7);
+    }
+
+    #[test]
+    fn test_expand_and_shrink_selection() {
+        let mut e = editor("call(foo bar);\n");
+        e.move_cursor_to(6);
+        keys(&mut e, "v");
+        assert_eq!(e.mode, Mode::Visual);
+        assert_eq!(e.selection(), Some(6..7));
+
+        keys(&mut e, "+");
+        assert_eq!(e.selection(), Some(5..8));
+        keys(&mut e, "+");
+        assert_eq!(e.selection(), Some(5..12));
+        keys(&mut e, "+");
+        assert_eq!(e.selection(), Some(4..13));
+
+        keys(&mut e, "-");
+        assert_eq!(e.selection(), Some(5..12));
+        keys(&mut e, "--");
+        assert_eq!(e.selection(), Some(6..7));
+
+        keys(&mut e, "v");
+        assert_eq!(e.mode, Mode::Normal);
+        assert_eq!(e.selection(), None);
+    }
+
+    #[test]
+    fn test_duplicate_selection() {
+        let mut e = editor("one\ntwo\nthree\n");
+        keys(&mut e, "vs");
+        press(&mut e, KeyCode::End);
+        keys(&mut e, "D");
+        assert_eq!(e.mode, Mode::Normal);
+        assert_eq!(e.buf.rope.to_string(), "one\ntwo\none\ntwo\nthree\n");
+        assert_eq!((e.cur_line, e.buf_cursor), (2, 8));
+
+        let mut e = editor("one two");
+        keys(&mut e, "vddD");
+        assert_eq!(e.buf.rope.to_string(), "oneone two");
+        assert_eq!(e.buf_cursor, 3);
+    }
+
+    #[test]
+    fn test_block_insert_and_append() {
+        let mut e = editor("abcdefgh\nab\nabcdefg\n");
+        e.move_cursor_to(4);
+        keys(&mut e, "vssA");
+        assert_eq!(e.mode, Mode::Insert);
+        keys(&mut e, "!?");
+        assert_eq!(e.buf.rope.to_string(), "abcde!?fgh\nab\nabcdefg\n");
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf.rope.to_string(), "abcde!?fgh\nab   !?\nabcde!?fg\n");
+
+        keys(&mut e, "u");
+        assert_eq!(e.buf.rope.to_string(), "abcdefgh\nab\nabcdefg\n");
+
+        e.move_cursor_to(3);
+        keys(&mut e, "vssI#");
+        press(&mut e, KeyCode::Esc);
+        assert_eq!(e.buf.rope.to_string(), "abc#defgh\nab\nabc#defg\n");
+    }
+}
@@ -0,0 +1,202 @@
+use std::{fs, path::Path};
+
+use crate::util::{Encoding, LineEnding};
+
+/// The subset of EditorConfig properties the editor understands.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Properties {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub end_of_line: Option<LineEnding>,
+    pub charset: Option<Encoding>,
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndentStyle {
+    Tab,
+    Space,
+}
+
+impl Properties {
+    /// Collects the properties for `file` from `.editorconfig` files in its directory and
+    /// all parents, up to one marked `root = true`. Closer files take precedence.
+    pub fn for_file(file: &Path) -> Self {
+        let file = fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf());
+        let mut configs = Vec::new();
+        for dir in file.ancestors().skip(1) {
+            let Ok(contents) = fs::read_to_string(dir.join(".editorconfig")) else {
+                continue;
+            };
+            let root = is_root(&contents);
+            configs.push((dir.to_path_buf(), contents));
+            if root {
+                break;
+            }
+        }
+
+        let mut properties = Self::default();
+        for (dir, contents) in configs.iter().rev() {
+            properties.apply_config(dir, contents, &file);
+        }
+        properties
+    }
+
+    fn apply_config(&mut self, dir: &Path, contents: &str, file: &Path) {
+        let Ok(relative) = file.strip_prefix(dir) else {
+            return;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let mut matching = false;
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                matching = section_matches(section, &relative);
+            } else if let Some((key, value)) = line.split_once('=') {
+                if matching {
+                    self.set(&key.trim().to_lowercase(), &value.trim().to_lowercase());
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let flag = |value| match value {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        };
+        match key {
+            "indent_style" => {
+                self.indent_style = match value {
+                    "tab" => Some(IndentStyle::Tab),
+                    "space" => Some(IndentStyle::Space),
+                    _ => None,
+                }
+            }
+            "indent_size" if value == "tab" => self.indent_size = None,
+            "indent_size" => self.indent_size = value.parse().ok(),
+            "tab_width" => self.tab_width = value.parse().ok(),
+            "end_of_line" => {
+                self.end_of_line = match value {
+                    "lf" => Some(LineEnding::Lf),
+                    "crlf" => Some(LineEnding::CrLf),
+                    "cr" => Some(LineEnding::Cr),
+                    _ => None,
+                }
+            }
+            "charset" => {
+                self.charset = match value {
+                    "utf-8" => Some(Encoding::Utf8),
+                    "utf-8-bom" => Some(Encoding::Utf8Bom),
+                    "utf-16le" => Some(Encoding::Utf16Le),
+                    "utf-16be" => Some(Encoding::Utf16Be),
+                    _ => None,
+                }
+            }
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = flag(value),
+            _ => (),
+        }
+    }
+}
+
+fn is_root(contents: &str) -> bool {
+    contents
+        .lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .filter_map(|line| line.split_once('='))
+        .any(|(key, value)| {
+            key.trim().eq_ignore_ascii_case("root") && value.trim().eq_ignore_ascii_case("true")
+        })
+}
+
+/// Whether a section glob applies to a path relative to the `.editorconfig`'s directory.
+/// Globs without a `/` match the file name in any subdirectory.
+fn section_matches(glob: &str, relative: &str) -> bool {
+    let glob = match glob.strip_prefix('/') {
+        Some(anchored) => anchored.to_string(),
+        None if glob.contains('/') => glob.to_string(),
+        None => format!("**/{glob}"),
+    };
+    expand_braces(&glob)
+        .iter()
+        .any(|glob| glob_matches(glob.as_bytes(), relative.as_bytes()))
+}
+
+/// Expands `{a,b}` alternatives into separate globs.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(open) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let Some(close) = glob[open..].find('}').map(|c| open + c) else {
+        return vec![glob.to_string()];
+    };
+    glob[open + 1..close]
+        .split(',')
+        .flat_map(|alt| expand_braces(&format!("{}{alt}{}", &glob[..open], &glob[close + 1..])))
+        .collect()
+}
+
+fn glob_matches(glob: &[u8], path: &[u8]) -> bool {
+    match glob {
+        [] => path.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            glob_matches(rest, path)
+                || path
+                    .iter()
+                    .enumerate()
+                    .any(|(i, c)| *c == b'/' && glob_matches(rest, &path[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=path.len()).any(|i| glob_matches(rest, &path[i..])),
+        [b'*', rest @ ..] => (0..=path.len())
+            .take_while(|i| *i == 0 || path[i - 1] != b'/')
+            .any(|i| glob_matches(rest, &path[i..])),
+        [b'?', rest @ ..] => {
+            matches!(path, [c, ..] if *c != b'/') && glob_matches(rest, &path[1..])
+        }
+        [c, rest @ ..] => path.first() == Some(c) && glob_matches(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_section_globs() {
+    assert!(section_matches("*", "src/main.rs"));
+    assert!(section_matches("*.rs", "src/main.rs"));
+    assert!(section_matches("*.{rs,toml}", "Cargo.toml"));
+    assert!(!section_matches("*.{rs,toml}", "README.md"));
+    assert!(section_matches("src/*.rs", "src/main.rs"));
+    assert!(!section_matches("src/*.rs", "src/editor/command.rs"));
+    assert!(section_matches("src/**.rs", "src/editor/command.rs"));
+    assert!(section_matches("Makefil?", "Makefile"));
+}
+
+#[cfg(test)]
+#[test]
+fn test_closer_config_wins() {
+    use crate::util::TempDir;
+    let dir = TempDir::new("editorconfig");
+    let nested = dir.0.join("nested");
+    fs::create_dir_all(&nested).unwrap();
+    fs::write(
+        dir.0.join(".editorconfig"),
+        "root = true\n\n[*]\nindent_style = tab\nend_of_line = crlf\n\n[*.rs]\nindent_size = 4\n",
+    )
+    .unwrap();
+    fs::write(
+        nested.join(".editorconfig"),
+        "[*.rs]\nindent_size = 2\nindent_style = space\n",
+    )
+    .unwrap();
+    let file = nested.join("lib.rs");
+    fs::write(&file, "").unwrap();
+
+    let properties = Properties::for_file(&file);
+    assert_eq!(properties.indent_size, Some(2));
+    assert_eq!(properties.indent_style, Some(IndentStyle::Space));
+    assert_eq!(properties.end_of_line, Some(LineEnding::CrLf));
+    assert_eq!(properties.trim_trailing_whitespace, None);
+}
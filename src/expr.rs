@@ -0,0 +1,162 @@
+//! Expressions typed after `Ctrl+R =` in Insert mode: integer arithmetic and a few
+//! variables describing the editor state.
+
+use color_eyre::{eyre::eyre, Result};
+use std::{iter::Peekable, str::Chars};
+
+/// Values of the variables an expression can refer to.
+pub struct Context {
+    /// `%`, the name of the current file.
+    pub file: String,
+    /// `.`, the 1-based number of the current line.
+    pub line: usize,
+    /// `date`, today as `YYYY-MM-DD`.
+    pub date: String,
+}
+
+enum Value {
+    Number(i64),
+    Text(String),
+}
+
+impl Value {
+    fn number(self) -> Result<i64> {
+        match self {
+            Self::Number(n) => Ok(n),
+            Self::Text(text) => Err(eyre!("expected a number, got {text}")),
+        }
+    }
+}
+
+/// Evaluates `expr`, returning the text to insert.
+pub fn evaluate(expr: &str, context: &Context) -> Result<String> {
+    let mut parser = Parser {
+        chars: expr.chars().peekable(),
+        context,
+    };
+    let value = parser.sum()?;
+    if let Some(c) = parser.next_token() {
+        return Err(eyre!("unexpected `{c}` in expression"));
+    }
+    Ok(match value {
+        Value::Number(n) => n.to_string(),
+        Value::Text(text) => text,
+    })
+}
+
+struct Parser<'e> {
+    chars: Peekable<Chars<'e>>,
+    context: &'e Context,
+}
+
+impl Parser<'_> {
+    fn next_token(&mut self) -> Option<char> {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+        self.chars.peek().copied()
+    }
+
+    fn sum(&mut self) -> Result<Value> {
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.next_token() {
+            self.chars.next();
+            let lhs = value.number()?;
+            let rhs = self.product()?.number()?;
+            let result = match op {
+                '+' => lhs.checked_add(rhs),
+                _ => lhs.checked_sub(rhs),
+            };
+            value = Value::Number(result.ok_or_else(|| eyre!("overflow"))?);
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<Value> {
+        let mut value = self.atom()?;
+        while let Some(op @ ('*' | '/')) = self.next_token() {
+            self.chars.next();
+            let lhs = value.number()?;
+            let rhs = self.atom()?.number()?;
+            if op == '/' && rhs == 0 {
+                return Err(eyre!("division by zero"));
+            }
+            let result = match op {
+                '*' => lhs.checked_mul(rhs),
+                _ => lhs.checked_div(rhs),
+            };
+            value = Value::Number(result.ok_or_else(|| eyre!("overflow"))?);
+        }
+        Ok(value)
+    }
+
+    fn atom(&mut self) -> Result<Value> {
+        let Some(c) = self.next_token() else {
+            return Err(eyre!("expression ended early"));
+        };
+        self.chars.next();
+        match c {
+            '-' => match self.atom()?.number()?.checked_neg() {
+                Some(n) => Ok(Value::Number(n)),
+                None => Err(eyre!("overflow")),
+            },
+            '(' => {
+                let value = self.sum()?;
+                match self.next_token() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(value)
+                    }
+                    _ => Err(eyre!("missing `)`")),
+                }
+            }
+            '%' => Ok(Value::Text(self.context.file.clone())),
+            '.' => Ok(Value::Number(self.context.line as i64)),
+            '0'..='9' => {
+                let mut digits = c.to_string();
+                while let Some(d) = self.chars.next_if(char::is_ascii_digit) {
+                    digits.push(d);
+                }
+                Ok(Value::Number(digits.parse()?))
+            }
+            c if c.is_alphabetic() => {
+                let mut name = c.to_string();
+                while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric()) {
+                    name.push(c);
+                }
+                match name.as_str() {
+                    "date" => Ok(Value::Text(self.context.date.clone())),
+                    _ => Err(eyre!("unknown variable: {name}")),
+                }
+            }
+            c => Err(eyre!("unexpected `{c}` in expression")),
+        }
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_evaluate() {
+    let context = Context {
+        file: "src/main.rs".into(),
+        line: 12,
+        date: "2024-03-01".into(),
+    };
+    let eval = |expr| evaluate(expr, &context).map_err(|e| e.to_string());
+    assert_eq!(eval("%").unwrap(), "src/main.rs");
+    assert_eq!(eval(". + 1").unwrap(), "13");
+    assert_eq!(eval("2 * (3 + 4) - -1").unwrap(), "15");
+    assert_eq!(eval("date").unwrap(), "2024-03-01");
+    assert_eq!(eval("1 / 0").unwrap_err(), "division by zero");
+    assert_eq!(
+        eval("% + 1").unwrap_err(),
+        "expected a number, got src/main.rs"
+    );
+    assert_eq!(eval("1 2").unwrap_err(), "unexpected `2` in expression");
+    assert_eq!(eval("9999999999 * 9999999999").unwrap_err(), "overflow");
+    assert_eq!(eval("9223372036854775807 + 1").unwrap_err(), "overflow");
+    assert_eq!(eval("-9223372036854775807 - 2").unwrap_err(), "overflow");
+    assert_eq!(eval("-(-9223372036854775807 - 1)").unwrap_err(), "overflow");
+    assert_eq!(
+        eval("(-9223372036854775807 - 1) / -1").unwrap_err(),
+        "overflow"
+    );
+}
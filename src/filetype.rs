@@ -0,0 +1,59 @@
+use std::path::Path;
+
+/// Languages the editor knows something about, detected from the file extension.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Filetype {
+    Rust,
+    C,
+    Python,
+    Shell,
+    Toml,
+    Text,
+}
+
+impl Filetype {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension {
+            "rs" => Self::Rust,
+            "c" | "h" | "cc" | "cpp" | "hpp" | "js" | "ts" | "java" | "go" => Self::C,
+            "py" => Self::Python,
+            "sh" | "bash" | "zsh" => Self::Shell,
+            "toml" => Self::Toml,
+            _ => Self::Text,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Rust => "rust",
+            Self::C => "c",
+            Self::Python => "python",
+            Self::Shell => "shell",
+            Self::Toml => "toml",
+            Self::Text => "text",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [
+            Self::Rust,
+            Self::C,
+            Self::Python,
+            Self::Shell,
+            Self::Toml,
+            Self::Text,
+        ]
+        .into_iter()
+        .find(|filetype| filetype.name() == name)
+    }
+
+    /// What starts a line comment in this language.
+    pub fn comment_leader(self) -> Option<&'static str> {
+        match self {
+            Self::Rust | Self::C => Some("//"),
+            Self::Python | Self::Shell | Self::Toml => Some("#"),
+            Self::Text => None,
+        }
+    }
+}
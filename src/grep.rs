@@ -0,0 +1,198 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver},
+    thread,
+};
+
+/// Entries looked at before a search gives up on the rest of the tree.
+const MAX_ENTRIES: usize = 20_000;
+/// Matches collected before a search stops.
+const MAX_MATCHES: usize = 1_000;
+/// Files larger than this are skipped.
+const MAX_FILE_SIZE: u64 = 1 << 20;
+
+/// One occurrence of a pattern in a file, with a 0-based line and char column.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileMatch {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+}
+
+/// What a search across files found.
+#[derive(Debug, Default)]
+pub struct GrepResult {
+    pub matches: Vec<FileMatch>,
+    pub files: usize,
+    /// Whether the search hit one of its limits before walking the whole tree.
+    pub truncated: bool,
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of chars and `?` for one.
+fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // where the last `*` was and the char of `name` it is currently stretched to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Whether the path made of `components` matches the glob made of `pattern`,
+/// where a `**` component stands for any number of directories.
+fn components_match(pattern: &[&str], components: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => components.is_empty(),
+        Some((&"**", rest)) => {
+            (0..=components.len()).any(|i| components_match(rest, &components[i..]))
+        }
+        Some((first, rest)) => components.split_first().is_some_and(|(name, others)| {
+            wildcard_match(first, name) && components_match(rest, others)
+        }),
+    }
+}
+
+/// Splits `glob` into the directory to walk, made of its leading components without
+/// wildcards, and the components the paths under it have to match.
+fn split_glob(glob: &str) -> (PathBuf, Vec<&str>) {
+    let components: Vec<&str> = glob.split('/').collect();
+    let literal = components[..components.len() - 1]
+        .iter()
+        .take_while(|c| !c.contains(['*', '?']))
+        .count();
+    let root = match components[..literal].join("/") {
+        root if !root.is_empty() => root,
+        _ if glob.starts_with('/') => "/".into(),
+        _ => ".".into(),
+    };
+    let pattern = components[literal..]
+        .iter()
+        .copied()
+        .filter(|c| !c.is_empty())
+        .collect();
+    (root.into(), pattern)
+}
+
+/// Every match of `pattern` in the files matching `glob`, in path order.
+///
+/// Hidden files and directories are skipped, as are large and binary files,
+/// and the walk stops once it has seen too many entries or found too many matches.
+pub fn grep_files(pattern: &str, glob: &str) -> GrepResult {
+    let (root, glob) = split_glob(glob);
+    let mut result = GrepResult::default();
+    let mut entries = 0;
+    let mut dirs = vec![(root.clone(), Vec::<String>::new())];
+    let mut files = Vec::new();
+    while let Some((dir, components)) = dirs.pop() {
+        if result.truncated {
+            break;
+        }
+        let Ok(read) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut listed: Vec<_> = read.filter_map(|e| e.ok()).collect();
+        listed.sort_by_key(|e| e.file_name());
+        for entry in listed.into_iter().rev() {
+            entries += 1;
+            if entries > MAX_ENTRIES {
+                result.truncated = true;
+                break;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let mut components = components.clone();
+            components.push(name);
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => dirs.push((entry.path(), components)),
+                Ok(t) if t.is_file() => {
+                    let names: Vec<&str> = components.iter().map(String::as_str).collect();
+                    if components_match(&glob, &names) {
+                        files.push(entry.path());
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+    files.sort();
+    for path in files {
+        let too_big = fs::metadata(&path).map_or(true, |m| m.len() > MAX_FILE_SIZE);
+        let Some(contents) = (!too_big).then(|| fs::read_to_string(&path).ok()).flatten() else {
+            continue;
+        };
+        if contents.contains('\0') {
+            continue;
+        }
+        let found = result.matches.len();
+        for (line, text) in contents.lines().enumerate() {
+            for (byte, _) in text.match_indices(pattern) {
+                if result.matches.len() == MAX_MATCHES {
+                    result.truncated = true;
+                    return result;
+                }
+                result.matches.push(FileMatch {
+                    path: path.clone(),
+                    line,
+                    column: text[..byte].chars().count(),
+                    text: text.to_string(),
+                });
+            }
+        }
+        result.files += (result.matches.len() > found) as usize;
+    }
+    result
+}
+
+/// Runs `grep_files` on its own thread, so a big tree doesn't hold up the editor.
+pub fn spawn_grep(pattern: String, glob: String) -> Receiver<GrepResult> {
+    let (sender, receiver) = channel();
+    thread::spawn(move || {
+        let _ = sender.send(grep_files(&pattern, &glob));
+    });
+    receiver
+}
+
+#[cfg(test)]
+#[test]
+fn test_glob_match() {
+    use std::path::Path;
+    let glob = |glob: &str, path: &str| {
+        let (root, pattern) = split_glob(glob);
+        let path = path.strip_prefix(root.to_str().unwrap()).unwrap_or(path);
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        components_match(&pattern, &components)
+    };
+    assert!(glob("*.rs", "main.rs"));
+    assert!(!glob("*.rs", "src/main.rs"));
+    assert!(glob("**/*.rs", "main.rs"));
+    assert!(glob("**/*.rs", "src/editor/main.rs"));
+    assert!(glob("src/*/m?in.rs", "src/editor/main.rs"));
+    assert!(!glob("src/*.rs", "src/main.rs.bak"));
+    assert!(glob("a*b*c", "aXbYbZc"));
+    assert_eq!(split_glob("/tmp/dir/**/*.txt").0, Path::new("/tmp/dir"));
+    assert_eq!(split_glob("*.txt").0, Path::new("."));
+}
@@ -0,0 +1,168 @@
+use crossterm::style::Color;
+use std::ops::Range;
+
+use crate::filetype::Filetype;
+
+/// What kind of token a span of text is, which decides its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    Keyword,
+    String,
+    Comment,
+    Number,
+}
+
+impl Group {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Keyword => "keyword",
+            Self::String => "string",
+            Self::Comment => "comment",
+            Self::Number => "number",
+        }
+    }
+
+    pub fn color(self) -> Color {
+        match self {
+            Self::Keyword => Color::Magenta,
+            Self::String => Color::Green,
+            Self::Comment => Color::DarkGrey,
+            Self::Number => Color::Cyan,
+        }
+    }
+}
+
+fn keywords(filetype: Filetype) -> &'static [&'static str] {
+    match filetype {
+        Filetype::Rust => &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod",
+            "move", "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super",
+            "trait", "true", "type", "unsafe", "use", "where", "while",
+        ],
+        Filetype::C => &[
+            "break", "case", "char", "class", "const", "continue", "default", "do", "double",
+            "else", "enum", "extern", "false", "float", "for", "func", "function", "goto", "if",
+            "import", "int", "let", "long", "new", "null", "package", "return", "short", "sizeof",
+            "static", "struct", "switch", "this", "true", "typedef", "union", "unsigned", "var",
+            "void", "while",
+        ],
+        Filetype::Python => &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "global", "if", "import",
+            "in", "is", "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return",
+            "True", "try", "while", "with", "yield",
+        ],
+        Filetype::Shell => &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if",
+            "in", "local", "return", "then", "until", "while",
+        ],
+        Filetype::Toml => &["false", "true"],
+        Filetype::Text => &[],
+    }
+}
+
+/// The tokens of `line` worth coloring, as char ranges.
+///
+/// Each line is looked at on its own, so strings and block comments
+/// running over several lines are only colored on the line they start.
+pub fn highlight_line(filetype: Filetype, line: &str) -> Vec<(Range<usize>, Group)> {
+    if filetype == Filetype::Text {
+        return Vec::new();
+    }
+    let keywords = keywords(filetype);
+    let leader: Vec<char> = filetype.comment_leader().unwrap_or("").chars().collect();
+    let block_comments = matches!(filetype, Filetype::Rust | Filetype::C);
+    // Rust's `'` also starts lifetimes, so only its double quotes are looked for
+    let quotes: &[char] = match filetype {
+        Filetype::Rust => &['"'],
+        _ => &['"', '\''],
+    };
+    let chars: Vec<char> = line.trim_end_matches(['\n', '\r']).chars().collect();
+    let at = |i: usize, token: &[char]| chars[i..].starts_with(token);
+    let mut spans = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let start = i;
+        let c = chars[i];
+        if !leader.is_empty() && at(i, &leader) {
+            spans.push((start..chars.len(), Group::Comment));
+            break;
+        } else if block_comments && at(i, &['/', '*']) {
+            i += 2;
+            while i < chars.len() && !at(i, &['*', '/']) {
+                i += 1;
+            }
+            i = (i + 2).min(chars.len());
+            spans.push((start..i, Group::Comment));
+        } else if quotes.contains(&c) {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += 1 + usize::from(chars[i] == '\\');
+            }
+            i = (i + 1).min(chars.len());
+            spans.push((start..i, Group::String));
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.')) {
+                i += 1;
+            }
+            spans.push((start..i, Group::Number));
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            if keywords.contains(&word.as_str()) {
+                spans.push((start..i, Group::Keyword));
+            }
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+#[cfg(test)]
+#[test]
+fn test_highlight_line() {
+    let spans = |filetype, line| {
+        highlight_line(filetype, line)
+            .into_iter()
+            .map(|(range, group)| {
+                (
+                    line.chars().take(range.end).skip(range.start).collect(),
+                    group,
+                )
+            })
+            .collect::<Vec<(String, Group)>>()
+    };
+    assert_eq!(
+        spans(Filetype::Rust, "let x2 = \"a \\\" b\"; // 42 \"no\"\n"),
+        [
+            ("let".into(), Group::Keyword),
+            ("\"a \\\" b\"".into(), Group::String),
+            ("// 42 \"no\"".into(), Group::Comment),
+        ]
+    );
+    assert_eq!(
+        spans(Filetype::C, "x = 0x1f /* c */ + 'a';"),
+        [
+            ("0x1f".into(), Group::Number),
+            ("/* c */".into(), Group::Comment),
+            ("'a'".into(), Group::String),
+        ]
+    );
+    assert_eq!(
+        spans(Filetype::Rust, "fn f<'a>(s: &'a str)"),
+        [("fn".into(), Group::Keyword)]
+    );
+    assert_eq!(
+        spans(Filetype::Python, "return None  # done"),
+        [
+            ("return".into(), Group::Keyword),
+            ("None".into(), Group::Keyword),
+            ("# done".into(), Group::Comment),
+        ]
+    );
+    assert!(spans(Filetype::Text, "let x = 1 // plain").is_empty());
+}
@@ -0,0 +1,88 @@
+use std::collections::VecDeque;
+
+/// One change to the text: `removed` was replaced by `inserted` at char `at`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    pub at: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    fn size(&self) -> usize {
+        self.removed.len() + self.inserted.len()
+    }
+}
+
+/// Undo and redo stacks of a buffer. Edits are collected into groups which are
+/// undone as one, a group is closed by `commit`.
+#[derive(Debug, Default)]
+pub struct History {
+    undo: VecDeque<Vec<Edit>>,
+    redo: Vec<Vec<Edit>>,
+    /// Whether new edits start a new group.
+    committed: bool,
+    /// Bytes of text held by the undo stack.
+    size: usize,
+}
+
+impl History {
+    /// Records an edit just made, which makes everything undone so far unreachable.
+    pub fn record(&mut self, edit: Edit) {
+        self.redo.clear();
+        self.size += edit.size();
+        match self.undo.back_mut() {
+            Some(group) if !self.committed => group.push(edit),
+            _ => self.undo.push_back(vec![edit]),
+        }
+        self.committed = false;
+    }
+
+    /// Closes the current group, then drops the oldest groups until at most `levels`
+    /// are kept and, unless `max_size` is 0, they hold at most `max_size` bytes.
+    pub fn commit(&mut self, levels: usize, max_size: usize) {
+        self.committed = true;
+        while self.undo.len() > levels || (max_size > 0 && self.size > max_size) {
+            let Some(group) = self.undo.pop_front() else {
+                break;
+            };
+            self.size -= group.iter().map(Edit::size).sum::<usize>();
+        }
+    }
+
+    /// The most recent group, in the order its edits were made.
+    pub fn undo(&mut self) -> Option<Vec<Edit>> {
+        let group = self.undo.pop_back()?;
+        self.size -= group.iter().map(Edit::size).sum::<usize>();
+        self.redo.push(group.clone());
+        self.committed = true;
+        Some(group)
+    }
+
+    /// The most recently undone group.
+    pub fn redo(&mut self) -> Option<Vec<Edit>> {
+        let group = self.redo.pop()?;
+        self.size += group.iter().map(Edit::size).sum::<usize>();
+        self.undo.push_back(group.clone());
+        self.committed = true;
+        Some(group)
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_size_cap_drops_oldest() {
+    let edit = |text: &str| Edit {
+        at: 0,
+        removed: String::new(),
+        inserted: text.into(),
+    };
+    let mut history = History::default();
+    for text in ["aaaa", "bb", "cc"] {
+        history.record(edit(text));
+        history.commit(100, 5);
+    }
+    assert_eq!(history.undo(), Some(vec![edit("cc")]));
+    assert_eq!(history.undo(), Some(vec![edit("bb")]));
+    assert_eq!(history.undo(), None);
+}
@@ -2,7 +2,9 @@
 
 use clap::Parser;
 use color_eyre::{eyre::eyre, Result};
+use config::Config;
 use crossterm::{
+    event::{DisableBracketedPaste, DisableFocusChange, EnableBracketedPaste, EnableFocusChange},
     terminal::{
         disable_raw_mode, enable_raw_mode, size, Clear, ClearType, EnterAlternateScreen,
         LeaveAlternateScreen,
@@ -11,29 +13,99 @@ use crossterm::{
     ExecutableCommand,
 };
 use editor::*;
-use std::{io::stdout, path::PathBuf};
+use session::Session;
+use std::{
+    io::{stdin, stdout},
+    path::PathBuf,
+};
 use util::FileBuf;
 
+mod config;
+mod diff;
 mod editor;
+mod editorconfig;
+mod expr;
+mod filetype;
+mod grep;
+mod highlight;
+mod history;
+mod session;
+mod text;
+mod textobject;
+mod timer;
+mod toml;
 mod util;
 
 #[derive(Parser)]
 struct Args {
-    file: PathBuf,
+    /// Files to open, none starts with an empty buffer, or one holding the input
+    /// when it is piped in.
+    /// Put names starting with `-` after `--`, e.g. `red -- -notes.txt`
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+    /// Jump to the last change made to the file (or where it was last closed)
+    #[arg(short, long)]
+    restore: bool,
+    /// Save symlinked files as regular files in place of the links, leaving their targets alone
+    #[arg(long)]
+    no_follow: bool,
+    /// Open files read-only, so writing them has to be forced with `:w!`
+    #[arg(short = 'R', long)]
+    readonly: bool,
 }
 
 fn main() -> Result<()> {
     color_eyre::install()?;
-    let window = setup()?;
     let args = Args::parse();
-    driver(window, args.file)?;
+    // read before the terminal is set up, key presses then come from the terminal itself
+    let piped = match stdin().is_tty() {
+        true => None,
+        false => Some(FileBuf::read_from(stdin().lock())?),
+    };
+    let window = setup()?;
+    let result = driver(window, args, piped);
+    // leave the alternate screen first, so an error is printed where it can be read
     teardown()?;
-    Ok(())
+    result
 }
 
-fn driver(window: Window, path: PathBuf) -> Result<()> {
-    let mut editor = Editor::new(window, FileBuf::new(path)?);
-    editor.drive()
+fn driver(window: Window, args: Args, piped: Option<FileBuf>) -> Result<()> {
+    let session_path = Session::default_path();
+    let mut session = match &session_path {
+        Some(path) => Session::load(path)?,
+        None => Session::default(),
+    };
+    let mut files = args.files.into_iter();
+    let open = |path| -> Result<FileBuf> {
+        let mut buf = FileBuf::open(path, !args.no_follow)?;
+        buf.readonly |= args.readonly;
+        Ok(buf)
+    };
+    let first = match piped {
+        Some(piped) => piped,
+        None => match files.next() {
+            Some(first) => open(first)?,
+            None => FileBuf::default(),
+        },
+    };
+    let config = match Config::default_path() {
+        Some(path) => Config::load(&path)?,
+        None => Config::default(),
+    };
+    let mut editor = Editor::new(window, first);
+    editor.configure(config)?;
+    editor.restore_position(&session, args.restore);
+    for file in files {
+        editor.open_buffer(open(file)?);
+        editor.restore_position(&session, args.restore);
+    }
+    editor.switch_buffer(0);
+    editor.drive()?;
+    editor.save_positions(&mut session);
+    if let Some(path) = session_path {
+        session.save(&path)?;
+    }
+    Ok(())
 }
 
 fn setup() -> Result<Window> {
@@ -43,18 +115,49 @@ fn setup() -> Result<Window> {
     }
     stdout
         .execute(EnterAlternateScreen)?
+        .execute(EnableFocusChange)?
+        .execute(EnableBracketedPaste)?
         .execute(Clear(ClearType::All))?;
     enable_raw_mode()?;
     let (width, height) = size()?;
     Ok(Window {
         height,
         width,
-        stdout,
+        stdout: Box::new(stdout),
     })
 }
 
 fn teardown() -> Result<()> {
-    stdout().execute(LeaveAlternateScreen)?;
+    stdout()
+        .execute(SetCursorStyle(None))?
+        .execute(DisableFocusChange)?
+        .execute(DisableBracketedPaste)?
+        .execute(LeaveAlternateScreen)?;
     disable_raw_mode()?;
     Ok(())
 }
+
+#[cfg(test)]
+#[test]
+fn test_dash_prefixed_file_after_double_dash() {
+    let args = Args::try_parse_from(["red", "--", "-weird-name.txt"]).unwrap();
+    assert_eq!(args.files, [PathBuf::from("-weird-name.txt")]);
+    assert!(Args::try_parse_from(["red", "-weird-name.txt"]).is_err());
+
+    let dir = std::env::temp_dir().join(format!("red-args-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(&args.files[0]);
+    std::fs::write(&path, "dashed\n").unwrap();
+    let buf = FileBuf::new(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(buf.path, path);
+    assert_eq!(buf.rope.to_string(), "dashed\n");
+}
+
+#[cfg(test)]
+#[test]
+fn test_no_follow_flag() {
+    let args = Args::try_parse_from(["red", "--no-follow", "link.txt"]).unwrap();
+    assert!(args.no_follow);
+    assert!(!Args::try_parse_from(["red", "link.txt"]).unwrap().no_follow);
+}
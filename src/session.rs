@@ -0,0 +1,115 @@
+use color_eyre::Result;
+use std::{
+    collections::HashMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// Where the cursor was and where the last change happened when a file was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FilePosition {
+    pub cursor: usize,
+    pub last_change: Option<usize>,
+}
+
+/// Per-file positions remembered across editor runs.
+///
+/// Stored as one `cursor<TAB>last_change<TAB>path` line per file, with `-` for a missing last change.
+#[derive(Debug, Default)]
+pub struct Session {
+    positions: HashMap<PathBuf, FilePosition>,
+}
+
+impl Session {
+    pub fn default_path() -> Option<PathBuf> {
+        Some(state_dir()?.join("session"))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let positions = contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let cursor = fields.next()?.parse().ok()?;
+                let last_change = match fields.next()? {
+                    "-" => None,
+                    change => Some(change.parse().ok()?),
+                };
+                let file = PathBuf::from(fields.next()?);
+                Some((
+                    file,
+                    FilePosition {
+                        cursor,
+                        last_change,
+                    },
+                ))
+            })
+            .collect();
+        Ok(Self { positions })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut contents = String::new();
+        for (file, position) in &self.positions {
+            let last_change = position
+                .last_change
+                .map_or_else(|| "-".to_string(), |c| c.to_string());
+            contents += &format!("{}\t{}\t{}\n", position.cursor, last_change, file.display());
+        }
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    pub fn position(&self, file: &Path) -> Option<FilePosition> {
+        self.positions.get(&Self::key(file)).copied()
+    }
+
+    pub fn set_position(&mut self, file: &Path, position: FilePosition) {
+        // buffers that were never written have nothing to come back to
+        if file.as_os_str().is_empty() {
+            return;
+        }
+        self.positions.insert(Self::key(file), position);
+    }
+
+    fn key(file: &Path) -> PathBuf {
+        fs::canonicalize(file).unwrap_or_else(|_| file.to_path_buf())
+    }
+}
+
+/// Where red keeps the files it writes for itself between runs.
+pub fn state_dir() -> Option<PathBuf> {
+    let state = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))?;
+    Some(state.join("red"))
+}
+
+#[cfg(test)]
+#[test]
+fn test_session_roundtrip() {
+    let path = std::env::temp_dir().join(format!("red-session-{}", std::process::id()));
+    let mut session = Session::default();
+    let position = FilePosition {
+        cursor: 12,
+        last_change: Some(7),
+    };
+    session.set_position(Path::new("/tmp/some file.txt"), position);
+    session.save(&path).unwrap();
+
+    let session = Session::load(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!(
+        session.position(Path::new("/tmp/some file.txt")),
+        Some(position)
+    );
+}
@@ -0,0 +1,385 @@
+use std::ops::Range;
+
+/// The whitespace a line starts with.
+pub fn indentation(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+/// The indentation and comment leader (with the space after it) of a comment line.
+pub fn comment_prefix<'l>(line: &'l str, leader: &str) -> Option<&'l str> {
+    let indent = indentation(line);
+    let rest = line[indent.len()..].strip_prefix(leader)?;
+    let space = rest.len() - rest.trim_start_matches(' ').len();
+    Some(&line[..indent.len() + leader.len() + space.min(1)])
+}
+
+/// Code points drawn two columns wide: CJK, Hangul, fullwidth forms and most emoji.
+const WIDE: [(u32, u32); 14] = [
+    (0x1100, 0x115F),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE30, 0xFE4F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F300, 0x1F64F),
+    (0x1F900, 0x1F9FF),
+    (0x20000, 0x3FFFD),
+];
+
+/// Code points drawn on top of the previous char: combining marks,
+/// zero-width spaces and joiners, and variation selectors.
+const ZERO_WIDTH: [(u32, u32); 5] = [
+    (0x0300, 0x036F),
+    (0x200B, 0x200F),
+    (0x20D0, 0x20FF),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+];
+
+/// Latin letters with a single diacritic that have a precomposed form, by combining mark:
+/// the base letters, and what each of them composes to at the same position.
+const COMPOSITIONS: [(char, &str, &str); 13] = [
+    ('\u{300}', "AEIOUaeiou", "ÀÈÌÒÙàèìòù"),
+    (
+        '\u{301}',
+        "AEIOUYaeiouyCcLlNnRrSsZz",
+        "ÁÉÍÓÚÝáéíóúýĆćĹĺŃńŔŕŚśŹź",
+    ),
+    (
+        '\u{302}',
+        "AEIOUaeiouCcGgHhJjSsWwYy",
+        "ÂÊÎÔÛâêîôûĈĉĜĝĤĥĴĵŜŝŴŵŶŷ",
+    ),
+    ('\u{303}', "ANOanoIiUu", "ÃÑÕãñõĨĩŨũ"),
+    ('\u{304}', "AaEeIiOoUu", "ĀāĒēĪīŌōŪū"),
+    ('\u{306}', "AaEeGgIiOoUu", "ĂăĔĕĞğĬĭŎŏŬŭ"),
+    ('\u{307}', "CcEeGgIZz", "ĊċĖėĠġİŻż"),
+    ('\u{308}', "AEIOUaeiouyY", "ÄËÏÖÜäëïöüÿŸ"),
+    ('\u{30a}', "AaUu", "ÅåŮů"),
+    ('\u{30b}', "OoUu", "ŐőŰű"),
+    ('\u{30c}', "CcDdEeLlNnRrSsTtZz", "ČčĎďĚěĽľŇňŘřŠšŤťŽž"),
+    ('\u{327}', "CcGgKkLlNnRrSsTt", "ÇçĢģĶķĻļŅņŖŗŞşŢţ"),
+    ('\u{328}', "AaEeIiUu", "ĄąĘęĮįŲų"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// NFC, letter and accent as one code point.
+    Composed,
+    /// NFD, the letter followed by a combining accent.
+    Decomposed,
+}
+
+/// Brings accented Latin letters into one normalization form. Only the letters
+/// in `COMPOSITIONS` are handled, everything else is left as it is.
+pub fn normalize(text: &str, form: Normalization) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match form {
+            Normalization::Composed => {
+                let composed = chars.peek().and_then(|&mark| {
+                    let (_, bases, composed) = COMPOSITIONS.iter().find(|(m, ..)| *m == mark)?;
+                    let at = bases.chars().position(|base| base == ch)?;
+                    composed.chars().nth(at)
+                });
+                match composed {
+                    Some(composed) => {
+                        normalized.push(composed);
+                        chars.next();
+                    }
+                    None => normalized.push(ch),
+                }
+            }
+            Normalization::Decomposed => {
+                let decomposed = COMPOSITIONS.iter().find_map(|(mark, bases, composed)| {
+                    let at = composed.chars().position(|c| c == ch)?;
+                    Some((bases.chars().nth(at)?, *mark))
+                });
+                match decomposed {
+                    Some((base, mark)) => normalized.extend([base, mark]),
+                    None => normalized.push(ch),
+                }
+            }
+        }
+    }
+    normalized
+}
+
+/// Columns `ch` takes up on a terminal, not counting tabs.
+pub fn char_width(ch: char) -> usize {
+    let in_table = |table: &[(u32, u32)]| {
+        table
+            .iter()
+            .any(|&(first, last)| (first..=last).contains(&(ch as u32)))
+    };
+    if ch.is_control() || in_table(&ZERO_WIDTH) {
+        0
+    } else if in_table(&WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+/// Columns a line takes up, with tabs going to the next multiple of `tab_width`.
+/// The line ending isn't counted.
+pub fn display_width(line: impl IntoIterator<Item = char>, tab_width: usize) -> usize {
+    line.into_iter().fold(0, |width, ch| match ch {
+        '\t' => (width / tab_width.max(1) + 1) * tab_width.max(1),
+        _ => width + char_width(ch),
+    })
+}
+
+/// Whether `ch` belongs to the grapheme cluster of the char before it, `prev`:
+/// combining marks, joiners, variation selectors and skin tones, whatever follows
+/// a zero-width joiner, and the `\n` of a CRLF.
+fn extends_cluster(prev: char, ch: char) -> bool {
+    prev == '\u{200D}'
+        || (prev == '\r' && ch == '\n')
+        || (ch != '\u{200B}' && char_width(ch) == 0 && !ch.is_control())
+        || ('\u{1F3FB}'..='\u{1F3FF}').contains(&ch)
+}
+
+/// The grapheme clusters of `chars` as (char offset, cell column) pairs where each starts,
+/// followed by the total char count and width.
+///
+/// A cluster takes up as many cells as its widest char, and at least one,
+/// so the cursor can land on line breaks. Tabs reach to the next multiple of `tab_width`.
+pub fn grapheme_cells(
+    chars: impl IntoIterator<Item = char>,
+    tab_width: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    let tab_width = tab_width.max(1);
+    let mut chars = chars.into_iter().peekable();
+    let (mut offset, mut column) = (0, 0);
+    let mut done = false;
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+        let start = (offset, column);
+        let Some(mut prev) = chars.next() else {
+            done = true;
+            return Some(start);
+        };
+        let mut width = match prev {
+            '\t' => tab_width - column % tab_width,
+            _ => char_width(prev),
+        };
+        offset += 1;
+        while let Some(ch) = chars.next_if(|&ch| extends_cluster(prev, ch)) {
+            width = width.max(char_width(ch));
+            prev = ch;
+            offset += 1;
+        }
+        column += width.max(1);
+        Some(start)
+    })
+}
+
+/// How many chars from the start of `chars` fit in `width` cells without
+/// splitting a grapheme cluster. The first cluster is always taken, even if it is too wide.
+pub fn fit_cells(chars: impl IntoIterator<Item = char>, width: usize, tab_width: usize) -> usize {
+    let mut ends = grapheme_cells(chars, tab_width).skip(1);
+    let first = ends.next().map_or(0, |(offset, _)| offset);
+    ends.take_while(|&(_, column)| column <= width)
+        .last()
+        .map_or(first, |(offset, _)| offset)
+}
+
+/// How well `query` matches `candidate` when its chars appear there in order, ignoring case,
+/// `None` when they don't. Runs of consecutive chars and chars starting a word score
+/// higher, and of otherwise equal matches the shorter candidate wins.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut from = 0;
+    let mut prev = None;
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let found =
+            (from..candidate.len()).find(|&i| candidate[i].to_lowercase().eq(q.to_lowercase()))?;
+        score += 1;
+        if prev.is_some_and(|p| p + 1 == found) {
+            score += 4;
+        }
+        if found == 0 || !candidate[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        prev = Some(found);
+        from = found + 1;
+    }
+    Some(score * 100 + 99 - candidate.len().min(99))
+}
+
+/// The decimal number under or after char `from` of `line`: its char range,
+/// including a leading `-`, and value.
+pub fn number_at(line: &str, from: usize) -> Option<(Range<usize>, i64)> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut start = (from..chars.len()).find(|&i| chars[i].is_ascii_digit())?;
+    while start > 0 && chars[start - 1].is_ascii_digit() {
+        start -= 1;
+    }
+    let end = (start..chars.len())
+        .find(|&i| !chars[i].is_ascii_digit())
+        .unwrap_or(chars.len());
+    if start > 0 && chars[start - 1] == '-' {
+        start -= 1;
+    }
+    let value = chars[start..end].iter().collect::<String>().parse().ok()?;
+    Some((start..end, value))
+}
+
+/// Rewraps the words of `lines` so each output line fits in `width` columns.
+///
+/// The first line's indentation is kept, and if it is a comment starting with `leader`
+/// the leader is stripped from every line before wrapping and put back on each output line.
+pub fn reflow(lines: &[&str], width: usize, leader: Option<&str>) -> Vec<String> {
+    let Some(first) = lines.first() else {
+        return Vec::new();
+    };
+    let prefix = leader
+        .and_then(|leader| comment_prefix(first, leader))
+        .unwrap_or_else(|| indentation(first));
+    let words = lines.iter().flat_map(|line| {
+        let line = line.trim_start();
+        let line = match leader {
+            Some(leader) if prefix.trim_start().starts_with(leader) => {
+                line.strip_prefix(leader).unwrap_or(line)
+            }
+            _ => line,
+        };
+        line.split_whitespace()
+    });
+
+    let available = width.saturating_sub(prefix.chars().count()).max(1);
+    let mut output = Vec::new();
+    let mut current = String::new();
+    for word in words {
+        let len = current.chars().count();
+        if len > 0 && len + 1 + word.chars().count() > available {
+            output.push(format!("{prefix}{current}"));
+            current.clear();
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current += word;
+    }
+    if !current.is_empty() || output.is_empty() {
+        output.push(format!("{prefix}{current}").trim_end().to_string());
+    }
+    output
+}
+
+/// Rewraps each paragraph of `text` to `width` columns with `reflow`. Paragraphs are
+/// separated by blank lines, which are kept, and lines end in `ending` afterwards.
+pub fn wrap_paragraphs(text: &str, width: usize, ending: &str) -> String {
+    let mut wrapped = Vec::new();
+    let mut paragraph = Vec::new();
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            wrapped.extend(reflow(&paragraph, width, None));
+            paragraph.clear();
+            wrapped.push(String::new());
+        } else {
+            paragraph.push(line);
+        }
+    }
+    wrapped.extend(reflow(&paragraph, width, None));
+    let mut wrapped = wrapped.join(ending);
+    if text.ends_with(['\n', '\r']) {
+        wrapped += ending;
+    }
+    wrapped
+}
+
+#[cfg(test)]
+#[test]
+fn test_reflow_comment_block() {
+    let lines = [
+        "    // The quick brown fox jumps over the lazy dog, and then",
+        "    // it keeps",
+        "    //   running until it reaches the river bank.",
+    ];
+    let reflowed = reflow(&lines, 40, Some("//"));
+    assert_eq!(
+        reflowed,
+        [
+            "    // The quick brown fox jumps over",
+            "    // the lazy dog, and then it keeps",
+            "    // running until it reaches the",
+            "    // river bank.",
+        ]
+    );
+    assert!(reflowed.iter().all(|l| l.chars().count() <= 40));
+}
+
+#[cfg(test)]
+#[test]
+fn test_reflow_plain_text() {
+    let lines = ["  one two", "three four five"];
+    assert_eq!(
+        reflow(&lines, 12, None),
+        ["  one two", "  three four", "  five"]
+    );
+}
+
+#[cfg(test)]
+#[test]
+fn test_display_width() {
+    assert_eq!(display_width("hello\n".chars(), 4), 5);
+    assert_eq!(display_width("日本語".chars(), 4), 6);
+    assert_eq!(display_width("e\u{301}".chars(), 4), 1);
+    assert_eq!(display_width("a\tb".chars(), 4), 5);
+    assert_eq!(display_width("\t\t".chars(), 8), 16);
+}
+
+#[cfg(test)]
+#[test]
+fn test_grapheme_cells() {
+    let cells = |text: &str| grapheme_cells(text.chars(), 4).collect::<Vec<_>>();
+    assert_eq!(cells("ab"), [(0, 0), (1, 1), (2, 2)]);
+    assert_eq!(cells("e\u{301}x"), [(0, 0), (2, 1), (3, 2)]);
+    assert_eq!(cells("日本\n"), [(0, 0), (1, 2), (2, 4), (3, 5)]);
+    // a family emoji is one cluster two cells wide
+    let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}!";
+    assert_eq!(cells(family), [(0, 0), (5, 2), (6, 3)]);
+    assert_eq!(cells("a\r\n"), [(0, 0), (1, 1), (3, 2)]);
+    assert_eq!(cells(""), [(0, 0)]);
+    assert_eq!(
+        cells("\tab\tc"),
+        [(0, 0), (1, 4), (2, 5), (3, 6), (4, 8), (5, 9)]
+    );
+
+    assert_eq!(fit_cells("日本語".chars(), 5, 4), 2);
+    assert_eq!(fit_cells("日本語".chars(), 1, 4), 1);
+    assert_eq!(fit_cells("e\u{301}e\u{301}".chars(), 1, 4), 2);
+    assert_eq!(fit_cells("abc".chars(), 10, 4), 3);
+    assert_eq!(fit_cells("a\tb".chars(), 7, 8), 1);
+}
+
+#[cfg(test)]
+#[test]
+fn test_normalize() {
+    let decomposed = "cafe\u{301} Zu\u{308}rich x\u{301}";
+    let composed = normalize(decomposed, Normalization::Composed);
+    assert_eq!(composed, "café Zürich x\u{301}");
+    assert_eq!(composed.chars().count(), 14);
+    assert_eq!(normalize(&composed, Normalization::Decomposed), decomposed);
+}
+
+#[cfg(test)]
+#[test]
+fn test_number_at() {
+    assert_eq!(number_at("x = 42;", 0), Some((4..6, 42)));
+    assert_eq!(number_at("x = 42;", 5), Some((4..6, 42)));
+    assert_eq!(number_at("x = -7", 0), Some((4..6, -7)));
+    assert_eq!(number_at("a1 b2", 2), Some((4..5, 2)));
+    assert_eq!(number_at("none", 0), None);
+}
@@ -0,0 +1,126 @@
+//! Ranges of structural units of text around a position: words, bracket and quote pairs,
+//! lines and paragraphs.
+
+use ropey::Rope;
+use std::ops::Range;
+
+use crate::util::{char_class, matching_bracket};
+
+const OPENING: &str = "([{";
+const QUOTES: &str = "\"'`";
+
+/// The run of same-class chars (word, punctuation or blanks) the char at `idx` is in.
+pub fn word(rope: &Rope, idx: usize) -> Option<Range<usize>> {
+    let ch = rope.get_char(idx).filter(|c| *c != '\n')?;
+    let class = char_class(ch);
+    let same = |c: &char| *c != '\n' && char_class(*c) == class;
+    let mut chars = rope.chars_at(idx);
+    let mut start = idx;
+    while chars.prev().filter(same).is_some() {
+        start -= 1;
+    }
+    let end = idx + rope.chars_at(idx).take_while(same).count();
+    Some(start..end)
+}
+
+/// The innermost bracket pair strictly around `range`, including the brackets.
+pub fn enclosing_brackets(rope: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+    let mut chars = rope.chars_at(range.start);
+    let mut idx = range.start;
+    while let Some(c) = chars.prev() {
+        idx -= 1;
+        if !OPENING.contains(c) {
+            continue;
+        }
+        match matching_bracket(rope, idx, usize::MAX) {
+            Some(close) if close >= range.end => return Some(idx..close + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+/// The nearest pair of matching quotes around `range` on its line, including the quotes.
+pub fn enclosing_quotes(rope: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+    let line = rope.char_to_line(range.start);
+    let line_start = rope.line_to_char(line);
+    let line_end = line_start + rope.line(line).len_chars();
+    if range.end > line_end {
+        return None;
+    }
+    let mut chars = rope.chars_at(range.start);
+    let mut open = range.start;
+    while let Some(c) = chars.prev() {
+        open -= 1;
+        if !QUOTES.contains(c) {
+            continue;
+        }
+        let close = rope
+            .slice(range.end..line_end)
+            .chars()
+            .position(|q| q == c)
+            .map(|i| range.end + i);
+        if let Some(close) = close {
+            return Some(open..close + 1);
+        }
+    }
+    None
+}
+
+/// The lines `range` touches, including the final line break.
+pub fn lines(rope: &Rope, range: Range<usize>) -> Range<usize> {
+    let first = rope.char_to_line(range.start);
+    let last = rope.char_to_line(range.end.saturating_sub(1).max(range.start));
+    rope.line_to_char(first)..rope.line_to_char(last + 1)
+}
+
+/// The paragraph (run of non-blank lines) around `range`.
+pub fn paragraph(rope: &Rope, range: Range<usize>) -> Range<usize> {
+    let blank = |line: usize| rope.line(line).chars().all(char::is_whitespace);
+    let mut first = rope.char_to_line(range.start);
+    let mut last = rope.char_to_line(range.end.saturating_sub(1).max(range.start));
+    while first > 0 && !blank(first - 1) {
+        first -= 1;
+    }
+    while last + 1 < rope.len_lines() && !blank(last + 1) {
+        last += 1;
+    }
+    rope.line_to_char(first)..rope.line_to_char(last + 1)
+}
+
+/// The smallest structural unit strictly containing `range`: a word, the inside of
+/// the enclosing brackets or quotes, the pair itself, the lines, the paragraph
+/// and finally the whole text.
+pub fn expand(rope: &Rope, range: Range<usize>) -> Option<Range<usize>> {
+    let mut candidates = Vec::new();
+    candidates.extend(word(rope, range.start));
+    for pair in [
+        enclosing_brackets(rope, range.clone()),
+        enclosing_quotes(rope, range.clone()),
+    ]
+    .into_iter()
+    .flatten()
+    {
+        candidates.push(pair.start + 1..pair.end - 1);
+        candidates.push(pair);
+    }
+    candidates.push(lines(rope, range.clone()));
+    candidates.push(paragraph(rope, range.clone()));
+    candidates.push(0..rope.len_chars());
+    candidates
+        .into_iter()
+        .filter(|c| c.start <= range.start && c.end >= range.end && c.len() > range.len())
+        .min_by_key(|c| c.len())
+}
+
+#[cfg(test)]
+#[test]
+fn test_text_objects() {
+    let rope = Rope::from_str("let x = f(a, \"b c\");\nnext\n\nother\n");
+    assert_eq!(word(&rope, 1), Some(0..3));
+    assert_eq!(word(&rope, 3), Some(3..4));
+    assert_eq!(enclosing_brackets(&rope, 10..11), Some(9..19));
+    assert_eq!(enclosing_quotes(&rope, 14..15), Some(13..18));
+    assert_eq!(lines(&rope, 2..3), 0..21);
+    assert_eq!(paragraph(&rope, 2..3), 0..26);
+}
@@ -0,0 +1,92 @@
+use std::time::{Duration, Instant, SystemTime};
+
+/// Measures how long the editor has been focused.
+///
+/// Every method takes the current time so callers decide where it comes from.
+#[derive(Debug)]
+pub struct Stopwatch {
+    accumulated: Duration,
+    running_since: Option<Instant>,
+}
+
+impl Stopwatch {
+    pub fn start(now: Instant) -> Self {
+        Self {
+            accumulated: Duration::ZERO,
+            running_since: Some(now),
+        }
+    }
+
+    pub fn pause(&mut self, now: Instant) {
+        if let Some(since) = self.running_since.take() {
+            self.accumulated += now.saturating_duration_since(since);
+        }
+    }
+
+    pub fn resume(&mut self, now: Instant) {
+        self.running_since.get_or_insert(now);
+    }
+
+    pub fn elapsed(&self, now: Instant) -> Duration {
+        let running = self
+            .running_since
+            .map_or(Duration::ZERO, |since| now.saturating_duration_since(since));
+        self.accumulated + running
+    }
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+/// The UTC date of `time` as `YYYY-MM-DD`.
+pub fn format_date(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    // civil-from-days, counting in 400-year eras that start on March 1st
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+#[test]
+fn test_format_date() {
+    let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+    assert_eq!(format_date(at(0)), "1970-01-01");
+    assert_eq!(format_date(at(951_782_400)), "2000-02-29");
+    assert_eq!(format_date(at(1_709_337_600)), "2024-03-02");
+}
+
+#[cfg(test)]
+#[test]
+fn test_stopwatch_pauses() {
+    let t0 = Instant::now();
+    let at = |secs| t0 + Duration::from_secs(secs);
+    let mut watch = Stopwatch::start(t0);
+    assert_eq!(watch.elapsed(at(10)), Duration::from_secs(10));
+
+    watch.pause(at(10));
+    assert_eq!(watch.elapsed(at(100)), Duration::from_secs(10));
+    // focus events can repeat, a second pause or resume must not skew the count
+    watch.pause(at(50));
+    watch.resume(at(100));
+    watch.resume(at(110));
+    assert_eq!(watch.elapsed(at(130)), Duration::from_secs(40));
+    assert_eq!(format_duration(Duration::from_secs(3725)), "1:02:05");
+}
@@ -0,0 +1,67 @@
+//! Just enough TOML for the config file: `[section]` headers and `key = value` pairs
+//! with string, number and boolean values.
+
+use color_eyre::{eyre::eyre, Result};
+
+/// Reads `key = value` pairs, prefixing keys with the section they're in (`section.key`).
+/// Strings are unquoted, other values are kept as written.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>> {
+    let mut section = String::new();
+    let mut pairs = Vec::new();
+    for (nr, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| eyre!("line {}: expected `key = value`", nr + 1))?;
+        let key = key.trim().trim_matches('"');
+        let value = value.trim();
+        let value = match value.strip_prefix('"') {
+            Some(quoted) => quoted
+                .strip_suffix('"')
+                .ok_or_else(|| eyre!("line {}: unterminated string", nr + 1))?,
+            None => value,
+        };
+        let key = if section.is_empty() {
+            key.to_string()
+        } else {
+            format!("{section}.{key}")
+        };
+        pairs.push((key, value.to_string()));
+    }
+    Ok(pairs)
+}
+
+/// Cuts a line at the first `#` that isn't inside a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_sections_and_values() {
+    let text = "textwidth = 100 # wide\n\n[cursor]\ninsert = \"bar # blink\"\nnormal=block\n";
+    assert_eq!(
+        parse(text).unwrap(),
+        [
+            ("textwidth".to_string(), "100".to_string()),
+            ("cursor.insert".to_string(), "bar # blink".to_string()),
+            ("cursor.normal".to_string(), "block".to_string()),
+        ]
+    );
+    assert!(parse("nonsense").is_err());
+}
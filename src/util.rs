@@ -1,23 +1,492 @@
-use color_eyre::Result;
-use ropey::{Rope, RopeSlice};
-use std::{fs::File, path::PathBuf};
+use color_eyre::{eyre::eyre, Result};
+use crossterm::style::Color;
+use ropey::{iter::Lines, Rope, RopeSlice};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{ErrorKind, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::SystemTime,
+};
 
-use crate::editor::VirtualLine;
+use crate::{editor::VirtualLine, editorconfig::Properties, history::History, text::fit_cells};
 
+#[derive(Default)]
 pub struct FileBuf {
     pub rope: Rope,
     pub path: PathBuf,
+    pub marks: HashMap<char, usize>,
+    pub signs: BTreeMap<usize, Sign>,
+    pub editorconfig: Properties,
+    /// Whether the contents changed since the file was read.
+    pub modified: bool,
+    pub history: History,
+    pub encoding: Encoding,
+    /// What every line break is written as on save.
+    pub line_ending: LineEnding,
+    /// A read-only listing of the directory at `path` rather than a file.
+    pub listing: bool,
+    /// `path` is a symlink to be replaced by a regular file on save,
+    /// instead of writing through it to its target.
+    pub replace_link: bool,
+    /// Modification time of the file when it was last read or written,
+    /// to notice other programs changing it.
+    pub mtime: Option<SystemTime>,
+    /// Opened read-only, by flag or because the file can't be written.
+    /// Writing it has to be forced.
+    pub readonly: bool,
+}
+
+/// How a file's text is stored on disk. Anything but plain UTF-8 is told apart
+/// by its byte order mark, which isn't part of the text.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf8Bom => "utf-8-bom",
+            Self::Utf16Le => "utf-16le",
+            Self::Utf16Be => "utf-16be",
+        }
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<(Self, String)> {
+        let utf16 = |bytes: &[u8], from: fn([u8; 2]) -> u16| {
+            if !bytes.len().is_multiple_of(2) {
+                return Err(eyre!("invalid UTF-16: odd number of bytes"));
+            }
+            let units = bytes.chunks(2).map(|pair| from([pair[0], pair[1]]));
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|e| eyre!("invalid UTF-16: {e}"))
+        };
+        match bytes {
+            [0xef, 0xbb, 0xbf, rest @ ..] => Ok((Self::Utf8Bom, String::from_utf8(rest.to_vec())?)),
+            [0xff, 0xfe, rest @ ..] => Ok((Self::Utf16Le, utf16(rest, u16::from_le_bytes)?)),
+            [0xfe, 0xff, rest @ ..] => Ok((Self::Utf16Be, utf16(rest, u16::from_be_bytes)?)),
+            _ => Ok((Self::Utf8, String::from_utf8(bytes.to_vec())?)),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Utf8 => text.as_bytes().to_vec(),
+            Self::Utf8Bom => [&[0xef, 0xbb, 0xbf], text.as_bytes()].concat(),
+            Self::Utf16Le => [0xfeff]
+                .into_iter()
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+            Self::Utf16Be => [0xfeff]
+                .into_iter()
+                .chain(text.encode_utf16())
+                .flat_map(u16::to_be_bytes)
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Lf => "lf",
+            Self::CrLf => "crlf",
+            Self::Cr => "cr",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::CrLf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    /// The line ending most of `text`'s lines use, `Lf` when there are none or it's a tie.
+    pub fn detect(text: &str) -> Self {
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    crlf += 1;
+                }
+                '\r' => cr += 1,
+                '\n' => lf += 1,
+                _ => (),
+            }
+        }
+        if crlf > lf && crlf >= cr {
+            Self::CrLf
+        } else if cr > lf && cr > crlf {
+            Self::Cr
+        } else {
+            Self::Lf
+        }
+    }
+
+    /// `text` with every line break, whatever it was, replaced by this one.
+    pub fn apply(self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\r' | '\n' => {
+                    if c == '\r' && chars.peek() == Some(&'\n') {
+                        chars.next();
+                    }
+                    out.push_str(self.as_str());
+                }
+                c => out.push(c),
+            }
+        }
+        out
+    }
+}
+
+/// A marker shown in the gutter next to a line.
+#[derive(Debug, Clone)]
+pub struct Sign {
+    pub symbol: char,
+    pub color: Color,
+    /// Shown in the status line when jumping to the line.
+    pub message: String,
 }
 
 impl FileBuf {
     pub fn new(path: impl Into<PathBuf>) -> Result<Self> {
+        Self::open(path, true)
+    }
+
+    /// Reads a file, or starts empty if there is none yet. Unless `follow_links` is set,
+    /// saving a symlink replaces the link itself rather than changing the file it points to.
+    pub fn open(path: impl Into<PathBuf>, follow_links: bool) -> Result<Self> {
         let path = path.into();
-        let rope = Rope::from_reader(File::open(&path)?)?;
+        let is_link = fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_symlink());
+        let readonly = fs::metadata(&path).is_ok_and(|m| m.permissions().readonly());
+        // taken before reading, so a write racing the read still counts as a change
+        let mtime = modified_time(&path);
+        let (encoding, text) = match fs::read(&path) {
+            Ok(bytes) => Encoding::decode(&bytes)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(e.into()),
+        };
+        let rope = Rope::from_str(&text);
+        let line_ending = LineEnding::detect(&text);
+        let editorconfig = Properties::for_file(&path);
+
+        Ok(Self {
+            rope,
+            path,
+            marks: HashMap::new(),
+            signs: BTreeMap::new(),
+            editorconfig,
+            modified: false,
+            history: History::default(),
+            encoding,
+            line_ending,
+            listing: false,
+            replace_link: is_link && !follow_links,
+            mtime,
+            readonly,
+        })
+    }
+
+    /// A buffer without a file holding everything `reader` gives, e.g. piped input.
+    pub fn read_from(mut reader: impl Read) -> Result<Self> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let (encoding, text) = Encoding::decode(&bytes)?;
+        Ok(Self {
+            rope: Rope::from_str(&text),
+            encoding,
+            line_ending: LineEnding::detect(&text),
+            ..Default::default()
+        })
+    }
+
+    /// The bytes the contents are stored as on disk.
+    pub fn contents(&self) -> Vec<u8> {
+        let text = self.line_ending.apply(&self.rope.to_string());
+        self.encoding.encode(&text)
+    }
+
+    /// Writes the contents back to the file, returning the number of bytes written.
+    pub fn save(&mut self) -> Result<usize> {
+        if self.path.as_os_str().is_empty() {
+            return Err(eyre!("no file name"));
+        }
+        if self.listing {
+            return Err(eyre!("{} is a directory", self.path.display()));
+        }
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let contents = self.contents();
+        let written = contents.len();
+        if self.replace_link {
+            // renaming over the link replaces it, where writing would follow it
+            let mut temp = self.path.clone().into_os_string();
+            temp.push(".red-tmp");
+            write_file(Path::new(&temp), &contents)?;
+            fs::rename(&temp, &self.path)?;
+            self.replace_link = false;
+        } else {
+            write_file(&self.path, &contents)?;
+        }
+        self.modified = false;
+        self.mtime = modified_time(&self.path);
+        Ok(written)
+    }
+
+    /// Saves a file that isn't writable, making it writable for the write
+    /// and restoring its permissions afterwards.
+    pub fn force_save(&mut self) -> Result<usize> {
+        let Ok(permissions) = fs::metadata(&self.path).map(|m| m.permissions()) else {
+            return self.save();
+        };
+        if permissions.readonly() {
+            fs::set_permissions(&self.path, owner_writable(&permissions))
+                .map_err(|e| eyre!("can't make {} writable: {e}", self.path.display()))?;
+        }
+        let written = self.save();
+        fs::set_permissions(&self.path, permissions)?;
+        written
+    }
+
+    /// The file's new modification time, if it changed since it was last read or written.
+    pub fn changed_on_disk(&self) -> Option<SystemTime> {
+        if self.listing || self.path.as_os_str().is_empty() {
+            return None;
+        }
+        modified_time(&self.path).filter(|&mtime| Some(mtime) != self.mtime)
+    }
+
+    /// Keeps marks pointing at the same text after `len` chars were inserted at `at`.
+    pub fn shift_marks(&mut self, at: usize, len: usize) {
+        for mark in self.marks.values_mut().filter(|m| **m >= at) {
+            *mark += len;
+        }
+    }
+
+    /// Keeps marks pointing at the same text after `range` was removed.
+    pub fn collapse_marks(&mut self, range: Range<usize>) {
+        for mark in self.marks.values_mut().filter(|m| **m > range.start) {
+            *mark = if *mark < range.end {
+                range.start
+            } else {
+                *mark - range.len()
+            };
+        }
+    }
+
+    /// Keeps signs attached to their lines after `count` lines were inserted below `line`.
+    pub fn shift_signs(&mut self, line: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let moved = self.signs.split_off(&(line + 1));
+        self.signs
+            .extend(moved.into_iter().map(|(l, sign)| (l + count, sign)));
+    }
+
+    /// Drops signs of the `count` lines joined into `line` and moves the ones below up.
+    pub fn collapse_signs(&mut self, line: usize, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let moved = self.signs.split_off(&(line + 1));
+        self.signs.extend(
+            moved
+                .into_iter()
+                .filter(|(l, _)| *l > line + count)
+                .map(|(l, sign)| (l - count, sign)),
+        );
+    }
+}
+
+/// The same permissions with writing allowed for the owner.
+fn owner_writable(permissions: &fs::Permissions) -> fs::Permissions {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::Permissions::from_mode(permissions.mode() | 0o200)
+    }
+    #[cfg(not(unix))]
+    {
+        let mut writable = permissions.clone();
+        #[allow(clippy::permissions_set_readonly_false)]
+        writable.set_readonly(false);
+        writable
+    }
+}
+
+fn write_file(path: &Path, contents: &[u8]) -> Result<()> {
+    fs::write(path, contents).map_err(|e| eyre!("can't write {}: {e}", path.display()))
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Which run of characters a char belongs to when moving by words.
+#[derive(PartialEq, Eq)]
+pub enum CharClass {
+    Blank,
+    Word,
+    Punctuation,
+}
+
+pub fn char_class(ch: char) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Blank
+    } else if ch.is_alphanumeric() || ch == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Finds where the word before `idx` starts, skipping blanks in between.
+/// Never crosses a line break.
+pub fn prev_word_start(rope: &Rope, idx: usize) -> usize {
+    let mut chars = rope.chars_at(idx);
+    let mut start = idx;
+    let mut class = CharClass::Blank;
+    while let Some(c) = chars.prev() {
+        let c_class = char_class(c);
+        if c == '\n' || (c_class != class && class != CharClass::Blank) {
+            break;
+        }
+        class = c_class;
+        start -= 1;
+    }
+    start
+}
+
+/// Finds where the next word after the one at `idx` starts, across line breaks.
+/// Returns the end of the text when there is none.
+pub fn next_word_start(rope: &Rope, idx: usize) -> usize {
+    let mut chars = rope.chars_at(idx).peekable();
+    let mut at = idx;
+    if let Some(class) = chars.peek().map(|&c| char_class(c)) {
+        if class != CharClass::Blank {
+            while chars.next_if(|&c| char_class(c) == class).is_some() {
+                at += 1;
+            }
+        }
+    }
+    while chars
+        .next_if(|&c| char_class(c) == CharClass::Blank)
+        .is_some()
+    {
+        at += 1;
+    }
+    at
+}
 
-        Ok(Self { rope, path })
+/// Finds where the word before `idx` starts like `prev_word_start`, but across line breaks.
+pub fn word_start_before(rope: &Rope, idx: usize) -> usize {
+    let mut chars = rope.chars_at(idx);
+    let mut start = idx;
+    while chars
+        .prev()
+        .is_some_and(|c| char_class(c) == CharClass::Blank)
+    {
+        start -= 1;
     }
+    prev_word_start(rope, start)
 }
 
+/// Finds the bracket matching the one at `idx`, skipping over nested pairs
+/// and looking at no more than `limit` chars.
+pub fn matching_bracket(rope: &Rope, idx: usize, limit: usize) -> Option<usize> {
+    const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+    let ch = rope.get_char(idx)?;
+    let mut depth = 0usize;
+    if let Some(&(open, close)) = PAIRS.iter().find(|(open, _)| *open == ch) {
+        for (i, c) in rope.chars_at(idx).enumerate().take(limit) {
+            if c == open {
+                depth += 1;
+            } else if c == close {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(idx + i);
+                }
+            }
+        }
+    } else if let Some(&(open, close)) = PAIRS.iter().find(|(_, close)| *close == ch) {
+        let mut chars = rope.chars_at(idx + 1);
+        let mut i = idx + 1;
+        let stop = i.saturating_sub(limit);
+        while let Some(c) = chars.prev().filter(|_| i > stop) {
+            i -= 1;
+            if c == close {
+                depth += 1;
+            } else if c == open {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Runs `command` with the shell, feeding it `input`, and returns what it printed.
+/// When the command fails, what it printed to stderr is the error.
+pub fn pipe_through(command: &str, input: &str) -> Result<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| eyre!("can't run {command}: {e}"))?;
+    // written from another thread, so a command printing as it reads can't block on a full pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    // a command that doesn't read its input closes the pipe early, which is fine
+    let _ = writer.join();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(match stderr.trim() {
+            "" => eyre!("{command} failed with {}", output.status),
+            stderr => eyre!("{stderr}"),
+        });
+    }
+    String::from_utf8(output.stdout).map_err(|_| eyre!("{command} printed invalid UTF-8"))
+}
+
+pub fn is_bracket(ch: char) -> bool {
+    "()[]{}".contains(ch)
+}
+
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct LineSplitIterator<'s> {
     inner: VirtualLineIterator<'s>,
@@ -38,80 +507,127 @@ impl<'s> Iterator for LineSplitIterator<'s> {
 }
 
 pub trait RopeExt<'s> {
+    #[allow(dead_code)]
     fn iter_lines_split(&'s self, len: usize) -> LineSplitIterator<'s>;
-    fn iter_virtual_lines(&'s self, start: usize, len: usize) -> VirtualLineIterator<'s>;
+    fn iter_virtual_lines(
+        &'s self,
+        start: usize,
+        len: usize,
+        tab_width: usize,
+    ) -> VirtualLineIterator<'s>;
 }
 
 impl<'s> RopeExt<'s> for RopeSlice<'s> {
     fn iter_lines_split(&'s self, len: usize) -> LineSplitIterator<'s> {
         LineSplitIterator {
-            inner: self.iter_virtual_lines(0, len),
+            inner: self.iter_virtual_lines(0, len, 1),
         }
     }
 
-    fn iter_virtual_lines(&'s self, start: usize, len: usize) -> VirtualLineIterator<'s> {
-        VirtualLineIterator::new(*self, start, len)
+    fn iter_virtual_lines(
+        &'s self,
+        start: usize,
+        len: usize,
+        tab_width: usize,
+    ) -> VirtualLineIterator<'s> {
+        VirtualLineIterator::new(*self, start, len, tab_width)
     }
 }
 
 impl<'s> RopeExt<'s> for Rope {
     fn iter_lines_split(&'s self, len: usize) -> LineSplitIterator<'s> {
         LineSplitIterator {
-            inner: self.iter_virtual_lines(0, len),
+            inner: self.iter_virtual_lines(0, len, 1),
         }
     }
 
-    fn iter_virtual_lines(&'s self, start: usize, len: usize) -> VirtualLineIterator<'s> {
+    fn iter_virtual_lines(
+        &'s self,
+        start: usize,
+        len: usize,
+        tab_width: usize,
+    ) -> VirtualLineIterator<'s> {
         let rope = self.slice(..);
-        VirtualLineIterator::new(rope, start, len)
+        VirtualLineIterator::new(rope, start, len, tab_width)
     }
 }
 
 #[derive(Debug)]
 pub struct VirtualLineIterator<'s> {
     len: usize,
+    tab_width: usize,
     rope: RopeSlice<'s>,
+    lines: Lines<'s>,
+    /// The line being split, taken from `lines`.
+    line: Option<RopeSlice<'s>>,
+    line_start: usize,
     line_offset: usize,
     line_nr: usize,
 }
 
 impl<'s> VirtualLineIterator<'s> {
-    fn new(rope: RopeSlice<'s>, start: usize, len: usize) -> Self {
+    fn new(rope: RopeSlice<'s>, start: usize, len: usize, tab_width: usize) -> Self {
+        let start = start.min(rope.len_lines());
         Self {
             len,
+            tab_width,
             rope,
+            lines: rope.lines_at(start),
+            line: None,
+            line_start: rope.line_to_char(start),
             line_offset: 0,
             line_nr: start,
         }
     }
+
+    fn next_line(&mut self, line_len: usize) {
+        self.line = None;
+        self.line_start += line_len;
+        self.line_offset = 0;
+        self.line_nr += 1;
+    }
 }
 
 impl<'s> Iterator for VirtualLineIterator<'s> {
     type Item = VirtualLine;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let line = self.rope.get_line(self.line_nr);
-        if let Some(line) = line {
-            let start = self.rope.line_to_char(self.line_nr) + self.line_offset;
+        loop {
+            let line = match self.line {
+                Some(line) => line,
+                None => *self.line.insert(self.lines.next()?),
+            };
             let line_len = line.len_chars();
-            let subline_len = line_len - self.line_offset;
-            let len = self.len.min(subline_len);
-            let end = start + len;
+            let rest = line_len - self.line_offset;
+            // only the last line can be empty, and it gets no virtual line
+            if rest == 0 {
+                self.next_line(line_len);
+                continue;
+            }
+            // no char is wider than two cells or a tab, so short lines need no measuring
+            let len = match rest * self.tab_width.max(2) <= self.len {
+                true => rest,
+                false => fit_cells(
+                    line.slice(self.line_offset..).chars(),
+                    self.len,
+                    self.tab_width,
+                ),
+            };
+            let start = self.line_start + self.line_offset;
             let subline = line_len != len && self.line_offset != 0;
+            let vline = VirtualLine::new(start, start + len, self.line_nr, subline);
             self.line_offset += len;
-            if len == 0 {
-                self.line_nr += 1;
-                self.line_offset = 0;
-                return self.next();
+            if self.line_offset == line_len {
+                self.next_line(line_len);
             }
-
-            Some(VirtualLine::new(start, end, self.line_nr, subline))
-        } else {
-            None
+            return Some(vline);
         }
     }
 
     fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.len == 0 {
+            return (0, None);
+        }
         (
             self.rope.len_chars() / self.len,
             Some(self.rope.len_chars() / self.len),
@@ -119,20 +635,275 @@ impl<'s> Iterator for VirtualLineIterator<'s> {
     }
 }
 
+#[cfg(all(test, unix))]
+#[test]
+fn test_symlinks() {
+    use std::os::unix::fs::symlink;
+    let dir = TempDir::new("symlink");
+    let target = dir.0.join("target.txt");
+    let link = dir.0.join("link.txt");
+    fs::write(&target, "target\n").unwrap();
+    symlink(&target, &link).unwrap();
+
+    let mut buf = FileBuf::new(&link).unwrap();
+    buf.rope.insert(0, "followed ");
+    buf.save().unwrap();
+    assert_eq!(fs::read_to_string(&target).unwrap(), "followed target\n");
+
+    let mut buf = FileBuf::open(&link, false).unwrap();
+    assert_eq!(buf.rope.to_string(), "followed target\n");
+    buf.rope.insert(0, "own ");
+    buf.save().unwrap();
+    assert_eq!(fs::read_to_string(&target).unwrap(), "followed target\n");
+    assert!(!fs::symlink_metadata(&link)
+        .unwrap()
+        .file_type()
+        .is_symlink());
+    assert_eq!(fs::read_to_string(&link).unwrap(), "own followed target\n");
+}
+
+#[cfg(test)]
+#[test]
+fn test_byte_order_marks() {
+    let dir = TempDir::new("bom");
+    let path = dir.0.join("bom.txt");
+    fs::write(&path, b"\xef\xbb\xbfhello\n").unwrap();
+    let mut buf = FileBuf::new(&path).unwrap();
+    assert_eq!(buf.rope.char(0), 'h');
+    assert_eq!(buf.encoding, Encoding::Utf8Bom);
+    buf.rope.insert(0, "oh ");
+    buf.save().unwrap();
+    assert_eq!(fs::read(&path).unwrap(), b"\xef\xbb\xbfoh hello\n");
+
+    fs::write(&path, b"\xff\xfeh\0i\0\n\0").unwrap();
+    let buf = FileBuf::new(&path).unwrap();
+    assert_eq!(buf.rope.to_string(), "hi\n");
+    assert_eq!(buf.encoding, Encoding::Utf16Le);
+    assert_eq!(buf.encoding.encode("hi\n"), b"\xff\xfeh\0i\0\n\0");
+    assert_eq!(Encoding::Utf16Be.encode("h"), b"\xfe\xff\0h");
+    let err = Encoding::decode(b"\xff\xfeh\0i").unwrap_err();
+    assert_eq!(err.to_string(), "invalid UTF-16: odd number of bytes");
+
+    let mut buf = FileBuf::read_from(&b"\xef\xbb\xbfpiped\n"[..]).unwrap();
+    assert_eq!(buf.rope.to_string(), "piped\n");
+    assert_eq!(buf.encoding, Encoding::Utf8Bom);
+    assert_eq!(buf.save().unwrap_err().to_string(), "no file name");
+}
+
+#[cfg(test)]
+#[test]
+fn test_line_endings_kept_on_save() {
+    let dir = TempDir::new("line-endings");
+    let path = dir.0.join("dos.txt");
+    fs::write(&path, "one\r\ntwo\r\nthree\nfour\r\n").unwrap();
+    let mut buf = FileBuf::new(&path).unwrap();
+    assert_eq!(buf.line_ending, LineEnding::CrLf);
+    buf.rope.insert(0, "zero\n");
+    buf.save().unwrap();
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "zero\r\none\r\ntwo\r\nthree\r\nfour\r\n"
+    );
+
+    assert_eq!(LineEnding::detect("a\nb\r\n"), LineEnding::Lf);
+    assert_eq!(LineEnding::detect("a\rb\rc\n"), LineEnding::Cr);
+    assert_eq!(LineEnding::Lf.apply("a\r\nb\rc\n"), "a\nb\nc\n");
+}
+
+#[cfg(test)]
+#[test]
+fn test_matching_bracket() {
+    let rope = Rope::from_str("f(a[0], (b)) }");
+    assert_eq!(matching_bracket(&rope, 1, usize::MAX), Some(11));
+    assert_eq!(matching_bracket(&rope, 11, usize::MAX), Some(1));
+    assert_eq!(matching_bracket(&rope, 8, usize::MAX), Some(10));
+    assert_eq!(matching_bracket(&rope, 13, usize::MAX), None);
+    assert_eq!(matching_bracket(&rope, 0, usize::MAX), None);
+    assert_eq!(matching_bracket(&rope, 1, 10), None);
+    assert_eq!(matching_bracket(&rope, 1, 11), Some(11));
+    assert_eq!(matching_bracket(&rope, 11, 10), None);
+    assert_eq!(matching_bracket(&rope, 11, 11), Some(1));
+}
+
+#[cfg(test)]
+#[test]
+fn test_prev_word_start() {
+    let rope = Rope::from_str("let foo = bar.baz  \n  qux");
+    assert_eq!(prev_word_start(&rope, 7), 4);
+    assert_eq!(prev_word_start(&rope, 6), 4);
+    assert_eq!(prev_word_start(&rope, 10), 8);
+    assert_eq!(prev_word_start(&rope, 14), 13);
+    assert_eq!(prev_word_start(&rope, 19), 14);
+    assert_eq!(prev_word_start(&rope, 20), 20);
+    assert_eq!(prev_word_start(&rope, 22), 20);
+}
+
+#[cfg(test)]
+#[test]
+fn test_word_starts_across_lines() {
+    let rope = Rope::from_str("let foo = bar.baz  \n  qux");
+    assert_eq!(next_word_start(&rope, 0), 4);
+    assert_eq!(next_word_start(&rope, 5), 8);
+    assert_eq!(next_word_start(&rope, 10), 13);
+    assert_eq!(next_word_start(&rope, 14), 22);
+    assert_eq!(next_word_start(&rope, 22), 25);
+    assert_eq!(word_start_before(&rope, 22), 14);
+    assert_eq!(word_start_before(&rope, 14), 13);
+    assert_eq!(word_start_before(&rope, 4), 0);
+    assert_eq!(word_start_before(&rope, 0), 0);
+}
+
 #[cfg(test)]
 #[test]
 fn test_iter_line_split() {
     let rope = Rope::from_reader(std::fs::File::open("test.txt").unwrap()).unwrap();
-    for slice in rope.iter_virtual_lines(0, 30) {
+    for slice in rope.iter_virtual_lines(0, 30, 4) {
         dbg!(&slice);
         dbg!(rope.slice(slice.range()));
     }
 }
 
-pub fn log(arg: impl std::fmt::Debug) {
-    use std::io::Write;
-    let mut options = std::fs::OpenOptions::new();
-    options.create(true).append(true);
-    let mut file = options.open("./red.log").unwrap();
-    writeln!(file, "{arg:?}").unwrap();
+#[cfg(test)]
+#[test]
+fn test_virtual_lines_of_degenerate_sizes() {
+    let empty = Rope::new();
+    let mut lines = empty.iter_virtual_lines(0, 0, 4);
+    assert_eq!(lines.size_hint(), (0, None));
+    assert_eq!(lines.next(), None);
+
+    // with no room at all each char still gets a line of its own
+    let rope = Rope::from_str("ab\n");
+    let lines: Vec<_> = rope
+        .iter_virtual_lines(0, 0, 4)
+        .map(|l| l.range())
+        .collect();
+    assert_eq!(lines, vec![0..1, 1..2, 2..3]);
+}
+
+/// A scratch directory for tests, removed when dropped.
+#[cfg(test)]
+pub struct TempDir(pub PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("red-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// How much gets written to `red.log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Off,
+    Info,
+    /// Also cursor movement and other tracing, to reproduce bugs with.
+    Debug,
+}
+
+impl LogLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Info => "info",
+            Self::Debug => "debug",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        [Self::Off, Self::Info, Self::Debug]
+            .into_iter()
+            .find(|level| level.name() == name)
+    }
+}
+
+/// Where log lines go and which of them are written. Shared by the whole process,
+/// so logging doesn't need access to the editor.
+struct Logger {
+    level: LogLevel,
+    /// Opened on the first line written, `red.log` unless replaced.
+    out: Option<Box<dyn std::io::Write + Send>>,
+}
+
+impl Logger {
+    fn log(&mut self, level: LogLevel, arg: impl std::fmt::Debug) {
+        if level == LogLevel::Off || level > self.level {
+            return;
+        }
+        if self.out.is_none() {
+            let mut options = fs::OpenOptions::new();
+            options.create(true).append(true);
+            let Ok(file) = options.open("./red.log") else {
+                return;
+            };
+            self.out = Some(Box::new(file));
+        }
+        if let Some(out) = &mut self.out {
+            let _ = writeln!(out, "{arg:?}");
+            let _ = out.flush();
+        }
+    }
+}
+
+static LOGGER: std::sync::Mutex<Logger> = std::sync::Mutex::new(Logger {
+    level: LogLevel::Off,
+    out: None,
+});
+
+pub fn set_log_level(level: LogLevel) {
+    LOGGER.lock().unwrap_or_else(|e| e.into_inner()).level = level;
+}
+
+/// Writes `arg` to the log if the log level lets `level` through.
+pub fn log(level: LogLevel, arg: impl std::fmt::Debug) {
+    let mut logger = LOGGER.lock().unwrap_or_else(|e| e.into_inner());
+    logger.log(level, arg);
+}
+
+#[cfg(test)]
+#[test]
+fn test_log_levels() {
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // a logger of its own, as other tests change the shared one's level
+    let out = Shared::default();
+    let mut logger = Logger {
+        level: LogLevel::Off,
+        out: Some(Box::new(out.clone())),
+    };
+    let logged = || String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+
+    logger.log(LogLevel::Info, "started");
+    logger.log(LogLevel::Debug, "cursor moved");
+    assert!(!logged().contains("started") && !logged().contains("cursor moved"));
+
+    logger.level = LogLevel::Info;
+    logger.log(LogLevel::Info, "started");
+    logger.log(LogLevel::Debug, "cursor moved");
+    assert!(logged().contains("started") && !logged().contains("cursor moved"));
+
+    logger.level = LogLevel::Debug;
+    logger.log(LogLevel::Debug, "cursor moved");
+    assert!(logged().contains("cursor moved"));
+    assert_eq!(LogLevel::parse("debug"), Some(LogLevel::Debug));
 }